@@ -0,0 +1,57 @@
+// Answers to aggregate questions like "how many todos are done" or "count todos per
+// status", read from the rows PostgREST returns for `select=count()` or
+// `select=status,count()`
+
+use crate::row::{Cell, Row, Value};
+use candid::{CandidType, Deserialize};
+use common::PostgrestQuery;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Answer {
+    // One aggregate over every matching row
+    Scalar(Value),
+    // One entry per combination of grouped values, or a single entry without a key
+    // when several aggregates were asked for
+    Groups(Vec<AnswerGroup>),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AnswerGroup {
+    // The grouped columns, e.g. `status = "open"`
+    pub key: Vec<Cell>,
+    // The aggregates, named like PostgREST names them: `count`, `sum`, `avg`, ...
+    pub values: Vec<Cell>,
+}
+
+// `None` for queries without aggregates
+pub fn answer(query: &PostgrestQuery, rows: &[Row]) -> Option<Answer> {
+    if query.aggregates.is_empty() {
+        return None;
+    }
+    if query.select.is_empty() && query.aggregates.len() == 1 {
+        let value = rows
+            .first()
+            .and_then(|row| row.first())
+            .map_or(Value::Null, |cell| cell.value.clone());
+        return Some(Answer::Scalar(value));
+    }
+    let groups = rows
+        .iter()
+        .map(|row| {
+            let (key, values) = row
+                .iter()
+                .cloned()
+                .partition(|cell| query.select.contains(&cell.column));
+            AnswerGroup { key, values }
+        })
+        .collect();
+    Some(Answer::Groups(groups))
+}
+
+// What PostgREST would return for `select=count()`, for counts read from `Content-Range`
+pub fn count_row(count: u64) -> Row {
+    vec![Cell {
+        column: "count".to_string(),
+        value: Value::Int(i64::try_from(count).unwrap_or(i64::MAX)),
+    }]
+}
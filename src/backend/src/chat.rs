@@ -0,0 +1,263 @@
+// Conversations with the `llm` canister. Clients send the whole history with string
+// roles; it's checked, mapped to the canister's `ChatRoleV0` and trimmed to what the
+// model accepts before being forwarded to `v0_chat`.
+
+use crate::registry::{DownstreamCanister, Registry};
+use candid::{CandidType, Deserialize};
+use common::ApiError;
+
+pub const LLM_MODEL: &str = "llama3.1:8b";
+
+// The `llm` canister rejects prompts over 10 KiB and more than 10 messages, so the
+// history is cut well below that
+const MAX_HISTORY_TOKENS: usize = 2_000;
+const MAX_HISTORY_MESSAGES: usize = 10;
+// Role markers and separators the model adds around every message
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+#[derive(CandidType, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+// Update the LLM types to match the canister interface
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ChatMessageV0 {
+    pub content: String,
+    pub role: ChatRoleV0,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatRoleV0 {
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "assistant")]
+    Assistant,
+    #[serde(rename = "system")]
+    System,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ChatRequestV0 {
+    pub model: String,
+    pub messages: Vec<ChatMessageV0>,
+}
+
+impl ChatRoleV0 {
+    pub fn parse(role: &str) -> Result<Self, String> {
+        match role.trim().to_lowercase().as_str() {
+            "user" => Ok(ChatRoleV0::User),
+            "assistant" => Ok(ChatRoleV0::Assistant),
+            "system" => Ok(ChatRoleV0::System),
+            other => Err(format!(
+                "Unknown role '{}', use user, assistant or system",
+                other
+            )),
+        }
+    }
+}
+
+impl ChatRequestV0 {
+    pub fn new(messages: Vec<ChatMessageV0>) -> Self {
+        Self {
+            model: LLM_MODEL.to_string(),
+            messages,
+        }
+    }
+}
+
+// Sends the conversation to the `llm` canister and returns the assistant's reply
+pub async fn complete(messages: Vec<ChatMessage>) -> Result<String, ApiError> {
+    let history = to_v0(messages)?;
+    let sent = history.len();
+    let history = trim_history(history)?;
+    if history.len() < sent {
+        ic_cdk::println!(
+            "Dropped the {} oldest chat messages to fit the budget",
+            sent - history.len()
+        );
+    }
+    let llm_canister_id = Registry::get(DownstreamCanister::Llm)?;
+    ic_cdk::println!("Forwarding {} chat messages to the LLM", history.len());
+
+    ic_cdk::call::<(ChatRequestV0,), (String,)>(
+        llm_canister_id,
+        "v0_chat",
+        (ChatRequestV0::new(history),),
+    )
+    .await
+    .map(|(response,)| response)
+    .map_err(|(code, message)| crate::call_error(code, message))
+}
+
+fn to_v0(messages: Vec<ChatMessage>) -> Result<Vec<ChatMessageV0>, ApiError> {
+    if messages.is_empty() {
+        return Err(ApiError::invalid_input("A chat needs at least one message"));
+    }
+
+    let history = messages
+        .into_iter()
+        .enumerate()
+        .map(|(i, message)| {
+            let role = ChatRoleV0::parse(&message.role)
+                .map_err(|e| ApiError::invalid_input(format!("Message {}: {}", i + 1, e)))?;
+            let content = message.content.trim().to_string();
+            if content.is_empty() {
+                return Err(ApiError::invalid_input(format!(
+                    "Message {} is empty",
+                    i + 1
+                )));
+            }
+            Ok(ChatMessageV0 { content, role })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if history.last().map(|message| message.role) != Some(ChatRoleV0::User) {
+        return Err(ApiError::invalid_input(
+            "The last message must come from the user",
+        ));
+    }
+    Ok(history)
+}
+
+// Keeps every system message and as many of the newest other messages as fit the
+// budget. The dropped turns are the oldest, so the model still sees the question.
+fn trim_history(history: Vec<ChatMessageV0>) -> Result<Vec<ChatMessageV0>, ApiError> {
+    let mut keep: Vec<bool> = history
+        .iter()
+        .map(|message| message.role == ChatRoleV0::System)
+        .collect();
+    let mut tokens: usize = history
+        .iter()
+        .zip(&keep)
+        .filter(|(_, system)| **system)
+        .map(|(message, _)| estimate_tokens(message))
+        .sum();
+    let mut count = keep.iter().filter(|system| **system).count();
+
+    for (i, message) in history.iter().enumerate().rev() {
+        if keep[i] {
+            continue;
+        }
+        let cost = estimate_tokens(message);
+        if tokens + cost > MAX_HISTORY_TOKENS || count >= MAX_HISTORY_MESSAGES {
+            break;
+        }
+        tokens += cost;
+        count += 1;
+        keep[i] = true;
+    }
+
+    if !keep.last().copied().unwrap_or(false) {
+        return Err(ApiError::invalid_input(format!(
+            "The conversation doesn't fit the model's limit of {} tokens and {} messages, shorten the system messages or the last message",
+            MAX_HISTORY_TOKENS, MAX_HISTORY_MESSAGES
+        )));
+    }
+
+    Ok(history
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(message, kept)| kept.then_some(message))
+        .collect())
+}
+
+// Roughly four characters per token for English text; the canister doesn't expose its
+// tokenizer
+fn estimate_tokens(message: &ChatMessageV0) -> usize {
+    message.content.chars().count().div_ceil(4) + MESSAGE_OVERHEAD_TOKENS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: ChatRoleV0, content: &str) -> ChatMessageV0 {
+        ChatMessageV0 {
+            content: content.to_string(),
+            role,
+        }
+    }
+
+    fn contents(history: &[ChatMessageV0]) -> Vec<&str> {
+        history
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect()
+    }
+
+    // `count` alternating turns ending with the user, each costing `tokens`
+    fn turns(count: usize, tokens: usize) -> Vec<ChatMessageV0> {
+        (0..count)
+            .map(|i| {
+                let role = if (count - i) % 2 == 1 {
+                    ChatRoleV0::User
+                } else {
+                    ChatRoleV0::Assistant
+                };
+                let text = format!("{:<1$}", i, (tokens - MESSAGE_OVERHEAD_TOKENS) * 4);
+                message(role, &text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimates_four_characters_per_token() {
+        assert_eq!(estimate_tokens(&message(ChatRoleV0::User, "")), 4);
+        assert_eq!(estimate_tokens(&message(ChatRoleV0::User, "abcd")), 5);
+        assert_eq!(estimate_tokens(&message(ChatRoleV0::User, "abcde")), 6);
+        assert_eq!(estimate_tokens(&message(ChatRoleV0::User, "żółw")), 5);
+    }
+
+    #[test]
+    fn keeps_the_newest_ten_messages() {
+        let history = turns(14, 10);
+        let kept = trim_history(history.clone()).unwrap();
+        assert_eq!(kept.len(), MAX_HISTORY_MESSAGES);
+        assert_eq!(contents(&kept), contents(&history[4..]));
+
+        let short = turns(MAX_HISTORY_MESSAGES, 10);
+        assert_eq!(trim_history(short).unwrap().len(), MAX_HISTORY_MESSAGES);
+    }
+
+    #[test]
+    fn keeps_the_newest_messages_within_the_token_budget() {
+        // Four 500-token turns fill the budget exactly, a fifth would go over it
+        let history = turns(5, 500);
+        let kept = trim_history(history.clone()).unwrap();
+        assert_eq!(contents(&kept), contents(&history[1..]));
+
+        let mut history = turns(3, 500);
+        history[0] = message(ChatRoleV0::User, &"x".repeat(4_000));
+        let kept = trim_history(history.clone()).unwrap();
+        assert_eq!(contents(&kept), contents(&history[1..]));
+    }
+
+    #[test]
+    fn keeps_system_messages_wherever_they_are() {
+        let mut history = vec![message(ChatRoleV0::System, "You answer about todos")];
+        history.extend(turns(12, 10));
+        history.insert(3, message(ChatRoleV0::System, "Be brief"));
+
+        let kept = trim_history(history.clone()).unwrap();
+        assert_eq!(kept.len(), MAX_HISTORY_MESSAGES);
+        assert_eq!(kept[0].content, "You answer about todos");
+        assert_eq!(kept[1].content, "Be brief");
+        assert_eq!(
+            contents(&kept[2..]),
+            contents(&history[history.len() - 8..])
+        );
+    }
+
+    #[test]
+    fn rejects_a_last_message_that_cannot_fit() {
+        let mut history = vec![message(ChatRoleV0::System, &"x".repeat(7_000))];
+        history.extend(turns(1, 300));
+        let error = trim_history(history).unwrap_err();
+        assert_eq!(error.code, common::ErrorCode::InvalidInput);
+
+        let history = vec![message(ChatRoleV0::User, &"x".repeat(8_000))];
+        assert!(trim_history(history).is_err());
+    }
+}
@@ -1,14 +1,173 @@
 // Configuration module for API keys and external service URLs
-// Uses environment variables at compile time
+// Values are set through the init/upgrade argument or the controller-only setters
+// and kept in stable memory, so rotating a key doesn't require a rebuild
+
+use crate::storage::{self, Candid, Memory};
+use candid::{CandidType, Deserialize, Principal};
+use common::rpc::validate_function_name;
+use common::time::parse_time_zone;
+use common::{mask_secret, ApiError};
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct StoredConfig {
+    supabase_url: Option<String>,
+    supabase_anon_key: Option<String>,
+    allowed_rpcs: Option<Vec<String>>,
+    time_zone: Option<String>,
+    mutation_gateway_url: Option<String>,
+}
+
+// Init and upgrade argument. Fields left as `null` keep their current value.
+#[derive(CandidType, Deserialize, Default)]
+pub struct InitArgs {
+    pub supabase_url: Option<String>,
+    pub supabase_anon_key: Option<String>,
+    pub llm_service_canister_id: Option<Principal>,
+    pub llm_canister_id: Option<Principal>,
+    pub time_zone: Option<String>,
+    pub mutation_gateway_url: Option<String>,
+}
+
+// Configuration as shown to controllers, with secrets masked
+#[derive(CandidType, Deserialize)]
+pub struct ConfigView {
+    pub supabase_url: Option<String>,
+    pub supabase_anon_key: Option<String>,
+    pub allowed_rpcs: Vec<String>,
+    pub time_zone: Option<String>,
+    pub mutation_gateway_url: Option<String>,
+}
+
+thread_local! {
+    static CONFIG: RefCell<StableCell<Candid<StoredConfig>, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::CONFIG_MEMORY_ID), Candid::default())
+            .expect("failed to initialize config in stable memory"),
+    );
+}
 
 pub struct Config;
 
 impl Config {
-    pub fn supabase_url() -> Result<&'static str, &'static str> {
-        option_env!("SUPABASE_URL").ok_or("SUPABASE_URL environment variable not set")
+    pub fn supabase_url() -> Result<String, ApiError> {
+        Self::read(|config| config.supabase_url.clone()).ok_or_else(|| {
+            ApiError::config("SUPABASE_URL is not configured, call set_supabase_url")
+        })
+    }
+
+    pub fn supabase_anon_key() -> Result<String, ApiError> {
+        Self::read(|config| config.supabase_anon_key.clone()).ok_or_else(|| {
+            ApiError::config("SUPABASE_ANON_KEY is not configured, call set_supabase_anon_key")
+        })
+    }
+
+    // Postgres functions the natural language pipeline is allowed to call
+    pub fn allowed_rpcs() -> Vec<String> {
+        Self::read(|config| config.allowed_rpcs.clone().unwrap_or_default())
+    }
+
+    // Offset like `UTC+2` that "today" or "this week" in questions are read in
+    pub fn time_zone() -> Option<String> {
+        Self::read(|config| config.time_zone.clone())
+    }
+
+    // Proxy in front of Supabase that turns POSTs carrying `X-HTTP-Method-Override` into
+    // PATCH and DELETE. Outcalls can't send those methods and Supabase ignores the header,
+    // so updates and deletes are refused until one is set.
+    pub fn mutation_gateway_url() -> Option<String> {
+        Self::read(|config| config.mutation_gateway_url.clone())
+    }
+
+    pub fn apply(args: &InitArgs) -> Result<(), ApiError> {
+        if let Some(url) = &args.supabase_url {
+            Self::set_supabase_url(url.clone())?;
+        }
+        if let Some(anon_key) = &args.supabase_anon_key {
+            Self::set_supabase_anon_key(anon_key.clone())?;
+        }
+        if let Some(time_zone) = &args.time_zone {
+            Self::set_time_zone(time_zone.clone())?;
+        }
+        if let Some(url) = &args.mutation_gateway_url {
+            Self::set_mutation_gateway_url(url.clone())?;
+        }
+        Ok(())
+    }
+
+    pub fn set_supabase_url(url: String) -> Result<(), ApiError> {
+        let url = url.trim().trim_end_matches('/').to_string();
+        if !url.starts_with("https://") {
+            return Err(ApiError::invalid_input(
+                "Supabase URL must start with https://",
+            ));
+        }
+        Self::update(|config| config.supabase_url = Some(url))
+    }
+
+    pub fn set_supabase_anon_key(anon_key: String) -> Result<(), ApiError> {
+        let anon_key = anon_key.trim().to_string();
+        if anon_key.is_empty() {
+            return Err(ApiError::invalid_input(
+                "Supabase anon key must not be empty",
+            ));
+        }
+        Self::update(|config| config.supabase_anon_key = Some(anon_key))
+    }
+
+    pub fn set_allowed_rpcs(functions: Vec<String>) -> Result<(), ApiError> {
+        let mut functions: Vec<String> = functions.iter().map(|f| f.trim().to_string()).collect();
+        for function in &functions {
+            validate_function_name(function).map_err(ApiError::invalid_input)?;
+        }
+        functions.sort();
+        functions.dedup();
+        Self::update(|config| config.allowed_rpcs = Some(functions))
+    }
+
+    pub fn set_time_zone(time_zone: String) -> Result<(), ApiError> {
+        let time_zone = time_zone.trim().to_string();
+        if parse_time_zone(&time_zone).is_none() {
+            return Err(ApiError::invalid_input(format!(
+                "Invalid time zone '{}', use an offset like UTC, UTC+2 or -05:30",
+                time_zone
+            )));
+        }
+        Self::update(|config| config.time_zone = Some(time_zone))
+    }
+
+    pub fn set_mutation_gateway_url(url: String) -> Result<(), ApiError> {
+        let url = url.trim().trim_end_matches('/').to_string();
+        if !url.starts_with("https://") {
+            return Err(ApiError::invalid_input(
+                "Mutation gateway URL must start with https://",
+            ));
+        }
+        Self::update(|config| config.mutation_gateway_url = Some(url))
+    }
+
+    pub fn view() -> ConfigView {
+        Self::read(|config| ConfigView {
+            supabase_url: config.supabase_url.clone(),
+            supabase_anon_key: config.supabase_anon_key.as_deref().map(mask_secret),
+            allowed_rpcs: config.allowed_rpcs.clone().unwrap_or_default(),
+            time_zone: config.time_zone.clone(),
+            mutation_gateway_url: config.mutation_gateway_url.clone(),
+        })
+    }
+
+    fn read<R>(f: impl FnOnce(&StoredConfig) -> R) -> R {
+        CONFIG.with(|cell| f(&cell.borrow().get().0))
     }
 
-    pub fn supabase_anon_key() -> Result<&'static str, &'static str> {
-        option_env!("SUPABASE_ANON_KEY").ok_or("SUPABASE_ANON_KEY environment variable not set")
+    fn update(f: impl FnOnce(&mut StoredConfig)) -> Result<(), ApiError> {
+        CONFIG.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let mut config = cell.get().0.clone();
+            f(&mut config);
+            cell.set(Candid(config))
+                .map(|_| ())
+                .map_err(|e| ApiError::storage(format!("Failed to store config: {:?}", e)))
+        })
     }
 }
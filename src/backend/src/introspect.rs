@@ -0,0 +1,276 @@
+// Schema introspection from the OpenAPI document PostgREST serves at `/rest/v1/`
+// Every exposed table or view is listed under `definitions` with its columns and their
+// Postgres types. The result is merged into the schema registry, keeping the synonyms
+// and descriptions controllers added, and the differences are reported back.
+
+use crate::schema::SchemaRegistry;
+use crate::supabase::SupabaseClient;
+use candid::{CandidType, Deserialize};
+use common::query::validate_column;
+use common::{ApiError, ColumnReference, ColumnSchema, ColumnType, Schema, TableSchema};
+use serde_json::Value;
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct ColumnTypeChange {
+    pub column: String,
+    pub stored: ColumnType,
+    pub introspected: ColumnType,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct TableDiff {
+    pub table: String,
+    pub added_columns: Vec<String>,
+    pub removed_columns: Vec<String>,
+    pub changed_columns: Vec<ColumnTypeChange>,
+}
+
+// Differences between the stored schema and the database, from the database's point of view
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SchemaDiff {
+    pub added_tables: Vec<String>,
+    pub removed_tables: Vec<String>,
+    pub changed_tables: Vec<TableDiff>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct SchemaRefresh {
+    pub diff: SchemaDiff,
+    // False for dry runs, which only report the diff
+    pub applied: bool,
+    // The schema after the refresh, or what it would be for a dry run
+    pub schema: Schema,
+}
+
+pub async fn refresh_schema(dry_run: bool) -> Result<SchemaRefresh, ApiError> {
+    let response = SupabaseClient::from_config()?
+        .openapi()
+        .send_raw()
+        .await?
+        .ensure_success()?;
+    let introspected = parse_openapi(&response.body)?;
+
+    let stored = SchemaRegistry::get();
+    let diff = diff(&stored, &introspected);
+    let schema = merge(&stored, introspected);
+    ic_cdk::println!(
+        "Schema refresh: {} added, {} removed, {} changed tables",
+        diff.added_tables.len(),
+        diff.removed_tables.len(),
+        diff.changed_tables.len()
+    );
+
+    let applied = !dry_run;
+    if applied {
+        SchemaRegistry::set(schema.clone())?;
+    }
+    Ok(SchemaRefresh {
+        diff,
+        applied,
+        schema,
+    })
+}
+
+// Reads the table definitions of a Swagger 2.0 document (what PostgREST serves) or,
+// for proxies that convert it, an OpenAPI 3 document
+fn parse_openapi(body: &str) -> Result<Schema, ApiError> {
+    let document: Value = serde_json::from_str(body)
+        .map_err(|e| ApiError::parse(format!("Invalid OpenAPI document: {}", e)))?;
+    let definitions = document
+        .get("definitions")
+        .or_else(|| document.pointer("/components/schemas"))
+        .and_then(Value::as_object)
+        .ok_or_else(|| ApiError::parse("OpenAPI document has no table definitions"))?;
+
+    let mut tables = Vec::new();
+    for (name, definition) in definitions {
+        // Names we couldn't put in a URL safely are left out rather than failing the refresh
+        if validate_column(name).is_err() {
+            ic_cdk::println!("Skipping table '{}' with an unsupported name", name);
+            continue;
+        }
+        let columns: Vec<ColumnSchema> = definition
+            .get("properties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .filter(|(column, _)| validate_column(column).is_ok())
+            .map(|(column, property)| ColumnSchema {
+                description: description(property),
+                references: reference(property),
+                ..ColumnSchema::new(column.as_str(), column_type(property))
+            })
+            .collect();
+        if columns.is_empty() {
+            continue;
+        }
+
+        tables.push(TableSchema {
+            description: description(definition),
+            ..TableSchema::new(name.as_str(), columns)
+        });
+    }
+
+    Ok(Schema { tables })
+}
+
+// PostgREST puts the Postgres type in `format` and a JSON type in `type`
+fn column_type(property: &Value) -> ColumnType {
+    let format = property.get("format").and_then(Value::as_str).unwrap_or("");
+    match format {
+        "smallint" | "integer" | "bigint" => ColumnType::Integer,
+        "real" | "double precision" | "numeric" => ColumnType::Float,
+        "boolean" => ColumnType::Boolean,
+        "date" => ColumnType::Date,
+        "uuid" => ColumnType::Uuid,
+        "json" | "jsonb" => ColumnType::Json,
+        format if format.starts_with("timestamp") => ColumnType::Timestamp,
+        _ => match property.get("type").and_then(Value::as_str) {
+            Some("integer") => ColumnType::Integer,
+            Some("number") => ColumnType::Float,
+            Some("boolean") => ColumnType::Boolean,
+            Some("array") | Some("object") => ColumnType::Json,
+            _ => ColumnType::Text,
+        },
+    }
+}
+
+// Column comments, without the "Note: This is a Primary Key" lines PostgREST appends
+fn description(value: &Value) -> Option<String> {
+    let description = value.get("description")?.as_str()?;
+    let comment = description.split("Note:").next().unwrap_or("").trim();
+    (!comment.is_empty()).then(|| comment.to_string())
+}
+
+// Foreign keys, which PostgREST marks in the description as
+// "Note:\nThis is a Foreign Key to `users.id`.<fk table='users' column='id'/>"
+fn reference(property: &Value) -> Option<ColumnReference> {
+    let description = property.get("description")?.as_str()?;
+    let tag = &description[description.find("<fk ")?..];
+    let tag = &tag[..tag.find("/>")?];
+    let attribute = |name: &str| {
+        let start = tag.find(&format!("{}='", name))? + name.len() + 2;
+        let value = &tag[start..];
+        Some(value[..value.find('\'')?].to_string())
+    };
+    let reference = ColumnReference {
+        table: attribute("table")?,
+        column: attribute("column")?,
+    };
+    (validate_column(&reference.table).is_ok() && validate_column(&reference.column).is_ok())
+        .then_some(reference)
+}
+
+fn diff(stored: &Schema, introspected: &Schema) -> SchemaDiff {
+    let mut diff = SchemaDiff::default();
+
+    for table in &introspected.tables {
+        let Some(stored_table) = stored.table(&table.name) else {
+            diff.added_tables.push(table.name.clone());
+            continue;
+        };
+
+        let mut table_diff = TableDiff {
+            table: table.name.clone(),
+            added_columns: Vec::new(),
+            removed_columns: Vec::new(),
+            changed_columns: Vec::new(),
+        };
+        for column in &table.columns {
+            match stored_table.column(&column.name) {
+                None => table_diff.added_columns.push(column.name.clone()),
+                Some(stored_column) if stored_column.column_type != column.column_type => {
+                    table_diff.changed_columns.push(ColumnTypeChange {
+                        column: column.name.clone(),
+                        stored: stored_column.column_type,
+                        introspected: column.column_type,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        table_diff.removed_columns = stored_table
+            .columns
+            .iter()
+            .filter(|column| !table.has_column(&column.name))
+            .map(|column| column.name.clone())
+            .collect();
+
+        if !table_diff.added_columns.is_empty()
+            || !table_diff.removed_columns.is_empty()
+            || !table_diff.changed_columns.is_empty()
+        {
+            diff.changed_tables.push(table_diff);
+        }
+    }
+
+    diff.removed_tables = stored
+        .tables
+        .iter()
+        .filter(|table| introspected.table(&table.name).is_none())
+        .map(|table| table.name.clone())
+        .collect();
+
+    diff
+}
+
+// Takes tables, columns, types and foreign keys from the database and everything the database doesn't
+// know about (synonyms, hand-written descriptions, table and column order) from the
+// stored schema
+fn merge(stored: &Schema, introspected: Schema) -> Schema {
+    let mut tables: Vec<TableSchema> = introspected
+        .tables
+        .into_iter()
+        .map(|table| {
+            let Some(stored_table) = stored.table(&table.name) else {
+                return table;
+            };
+
+            let mut columns: Vec<ColumnSchema> = stored_table
+                .columns
+                .iter()
+                .filter_map(|stored_column| {
+                    let column = table.column(&stored_column.name)?;
+                    Some(ColumnSchema {
+                        column_type: column.column_type,
+                        references: column
+                            .references
+                            .clone()
+                            .or_else(|| stored_column.references.clone()),
+                        description: stored_column
+                            .description
+                            .clone()
+                            .or_else(|| column.description.clone()),
+                        ..stored_column.clone()
+                    })
+                })
+                .collect();
+            columns.extend(
+                table
+                    .columns
+                    .iter()
+                    .filter(|column| !stored_table.has_column(&column.name))
+                    .cloned(),
+            );
+
+            TableSchema {
+                name: table.name,
+                description: stored_table.description.clone().or(table.description),
+                synonyms: stored_table.synonyms.clone(),
+                columns,
+            }
+        })
+        .collect();
+
+    // Registry order decides which table wins when a question mentions several, so
+    // known tables keep their place and new ones go at the end
+    tables.sort_by_key(|table| {
+        stored
+            .tables
+            .iter()
+            .position(|t| t.name == table.name)
+            .unwrap_or(usize::MAX)
+    });
+
+    Schema { tables }
+}
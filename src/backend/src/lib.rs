@@ -1,34 +1,188 @@
-use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::management_canister::http_request::{
-    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
-    TransformContext,
+use candid::Principal;
+use common::rpc::validate_function_name;
+use common::validate::{describe_issues, rejection_error};
+use common::{
+    validate_mutation, validate_query, ApiError, Mutation, MutationAction, ParseContext,
+    PostgrestQuery, QueryParseResult, Schema, TableSchema,
 };
-use std::cell::RefCell;
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use std::time::Duration;
 
+mod answer;
+mod chat;
 mod config;
-use config::Config;
+mod introspect;
+mod plans;
+mod registry;
+mod row;
+mod schema;
+mod sessions;
+mod storage;
+mod supabase;
+mod tables;
+use chat::{ChatMessage, ChatMessageV0, ChatRequestV0, ChatRoleV0};
+use config::{Config, ConfigView, InitArgs};
+use introspect::SchemaRefresh;
+use plans::Plans;
+use registry::{DownstreamCanister, RegisteredCanister, Registry};
+use row::Row;
+use schema::SchemaRegistry;
+use sessions::{LastQuery, Session, SessionSummary, Sessions};
+use supabase::{
+    ensure_row_filter, on_conflict_query, BulkInsertOptions, BulkInsertResult, Method, Page,
+    PageOptions, SupabaseClient, SupabaseResponse,
+};
+use tables::{FromRow, Post, Todo, User};
+
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    if let Err(e) = storage::init() {
+        ic_cdk::trap(&e);
+    }
+    apply_init_args(args);
+    schedule_health_check();
+}
+
+// All state lives in stable structures, so there is no pre_upgrade hook to serialize the heap
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    if let Err(e) = storage::migrate() {
+        ic_cdk::trap(&e);
+    }
+    apply_init_args(args);
+    schedule_health_check();
+}
+
+fn apply_init_args(args: Option<InitArgs>) {
+    let Some(args) = args else {
+        return;
+    };
+    let mut result = Config::apply(&args);
+    if let Some(canister_id) = args.llm_service_canister_id {
+        result = result.and_then(|_| Registry::set(DownstreamCanister::LlmService, canister_id));
+    }
+    if let Some(canister_id) = args.llm_canister_id {
+        result = result.and_then(|_| Registry::set(DownstreamCanister::Llm, canister_id));
+    }
+    if let Err(e) = result {
+        ic_cdk::trap(&format!("Invalid init arguments: {}", e));
+    }
+}
 
-thread_local! {
-    static COUNTER: RefCell<u64> = const { RefCell::new(0) };
+// Inter-canister calls aren't allowed during init, so ping the registry right after it
+fn schedule_health_check() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            Registry::check_all().await;
+        })
+    });
 }
 
-#[derive(CandidType, Deserialize)]
-pub struct ChatMessage {
-    pub role: String,
-    pub content: String,
+fn ensure_controller() -> Result<(), ApiError> {
+    if ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(ApiError::unauthorized(
+            "Only canister controllers can call this method",
+        ))
+    }
 }
 
-#[derive(CandidType, Deserialize)]
-pub struct SupabaseResponse {
-    pub data: Option<String>,
-    pub error: Option<String>,
+// Only transient rejections (e.g. a full queue) are worth retrying
+fn call_error(code: RejectionCode, message: String) -> ApiError {
+    ApiError::canister_call(
+        format!("{:?}: {}", code, message),
+        code == RejectionCode::SysTransient,
+    )
 }
 
-#[derive(CandidType, Deserialize, Debug)]
-pub struct QueryParseResult {
-    pub table: String,
-    pub query: String,
-    pub error: Option<String>,
+#[ic_cdk::update]
+fn set_supabase_url(url: String) -> Result<(), ApiError> {
+    ensure_controller()?;
+    Config::set_supabase_url(url)
+}
+
+#[ic_cdk::update]
+fn set_supabase_anon_key(anon_key: String) -> Result<(), ApiError> {
+    ensure_controller()?;
+    Config::set_supabase_anon_key(anon_key)
+}
+
+#[ic_cdk::update]
+fn set_allowed_rpcs(functions: Vec<String>) -> Result<(), ApiError> {
+    ensure_controller()?;
+    Config::set_allowed_rpcs(functions)
+}
+
+#[ic_cdk::update]
+fn set_time_zone(time_zone: String) -> Result<(), ApiError> {
+    ensure_controller()?;
+    Config::set_time_zone(time_zone)
+}
+
+#[ic_cdk::update]
+fn set_mutation_gateway_url(url: String) -> Result<(), ApiError> {
+    ensure_controller()?;
+    Config::set_mutation_gateway_url(url)
+}
+
+#[ic_cdk::query]
+fn get_config() -> Result<ConfigView, ApiError> {
+    ensure_controller()?;
+    Ok(Config::view())
+}
+
+// The schema isn't secret, so anyone may read it
+#[ic_cdk::query]
+fn get_schema() -> Schema {
+    SchemaRegistry::get()
+}
+
+#[ic_cdk::update]
+fn set_schema(schema: Schema) -> Result<(), ApiError> {
+    ensure_controller()?;
+    SchemaRegistry::set(schema)
+}
+
+#[ic_cdk::update]
+fn upsert_table_schema(table: TableSchema) -> Result<(), ApiError> {
+    ensure_controller()?;
+    SchemaRegistry::upsert_table(table)
+}
+
+#[ic_cdk::update]
+fn remove_table_schema(table: String) -> Result<(), ApiError> {
+    ensure_controller()?;
+    SchemaRegistry::remove_table(&table)
+}
+
+// Replaces the stored schema with the tables PostgREST reports, keeping synonyms and
+// descriptions. With `dry_run` only the differences are reported.
+#[ic_cdk::update]
+async fn refresh_schema(dry_run: Option<bool>) -> Result<SchemaRefresh, ApiError> {
+    ensure_controller()?;
+    introspect::refresh_schema(dry_run.unwrap_or(false)).await
+}
+
+#[ic_cdk::update]
+fn set_downstream_canister(
+    canister: DownstreamCanister,
+    canister_id: Principal,
+) -> Result<(), ApiError> {
+    ensure_controller()?;
+    Registry::set(canister, canister_id)
+}
+
+#[ic_cdk::query]
+fn get_downstream_canisters() -> Vec<RegisteredCanister> {
+    Registry::list()
+}
+
+#[ic_cdk::update]
+async fn check_downstream_canisters() -> Result<Vec<RegisteredCanister>, ApiError> {
+    ensure_controller()?;
+    Ok(Registry::check_all().await)
 }
 
 #[ic_cdk::query]
@@ -38,113 +192,48 @@ fn greet(name: String) -> String {
 
 #[ic_cdk::update]
 fn increment() -> u64 {
-    COUNTER.with(|counter| {
-        let mut count = counter.borrow_mut();
-        *count += 1;
-        *count
+    storage::update_state(|state| {
+        state.counter += 1;
+        state.counter
     })
 }
 
 #[ic_cdk::query]
 fn get_count() -> u64 {
-    COUNTER.with(|counter| *counter.borrow())
+    storage::read_state(|state| state.counter)
 }
 
 #[ic_cdk::update]
 fn set_count(value: u64) -> u64 {
-    COUNTER.with(|counter| {
-        *counter.borrow_mut() = value;
+    storage::update_state(|state| {
+        state.counter = value;
         value
     })
 }
 
+#[ic_cdk::query]
+fn get_storage_version() -> u32 {
+    storage::version()
+}
+
+// Kept for existing callers, identical to `fetch_from_supabase`
 #[ic_cdk::update]
 async fn fetch_from_supabase_no_encoding(
     table: String,
     query: String,
-) -> Result<SupabaseResponse, String> {
-    let supabase_url = Config::supabase_url().map_err(|e| e.to_string())?;
-    let supabase_key = Config::supabase_anon_key().map_err(|e| e.to_string())?;
-
-    let url = if query.is_empty() {
-        format!("{}/rest/v1/{}", supabase_url, table)
-    } else {
-        // Don't URL encode - try direct concatenation
-        let final_url = format!("{}/rest/v1/{}?{}", supabase_url, table, query);
-        ic_cdk::println!("Final URL (no encoding): {}", final_url);
-        final_url
-    };
-
-    // ... rest of the function same as original fetch_from_supabase
-    let request_headers = vec![
-        HttpHeader {
-            name: "apikey".to_string(),
-            value: supabase_key.to_string(),
-        },
-        HttpHeader {
-            name: "Authorization".to_string(),
-            value: format!("Bearer {}", supabase_key),
-        },
-        HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        },
-        HttpHeader {
-            name: "Accept".to_string(),
-            value: "application/json".to_string(),
-        },
-    ];
-
-    let request = CanisterHttpRequestArgument {
-        url: url.clone(),
-        method: HttpMethod::GET,
-        body: None,
-        max_response_bytes: Some(8192),
-        transform: Some(TransformContext::from_name(
-            "transform".to_string(),
-            serde_json::to_vec(&()).unwrap(),
-        )),
-        headers: request_headers,
-    };
-
-    match http_request(request, 50_000_000_000).await {
-        Ok((response,)) => {
-            let str_body = String::from_utf8(response.body)
-                .map_err(|_| "Failed to parse response body as UTF-8".to_string())?;
-
-            let status_code: u32 = response.status.0.to_string().parse().unwrap_or(500);
-            ic_cdk::println!("Response status: {}", status_code);
-            ic_cdk::println!("Response body: {}", str_body);
-
-            if (200..300).contains(&status_code) {
-                Ok(SupabaseResponse {
-                    data: Some(str_body),
-                    error: None,
-                })
-            } else {
-                Ok(SupabaseResponse {
-                    data: None,
-                    error: Some(format!("HTTP {} - {}", status_code, str_body)),
-                })
-            }
-        }
-        Err((r, m)) => {
-            let message = format!("HTTP request failed with code {:?}: {}", r, m);
-            Ok(SupabaseResponse {
-                data: None,
-                error: Some(message),
-            })
-        }
-    }
+) -> Result<SupabaseResponse, ApiError> {
+    fetch_from_supabase(table, query).await
 }
 
+// Multi-turn conversation with the `llm` canister; `messages` is the whole history,
+// oldest first, ending with the user's message
 #[ic_cdk::update]
-async fn chat(messages: Vec<ChatMessage>) -> String {
-    format!("Received {} messages", messages.len())
+async fn chat(messages: Vec<ChatMessage>) -> Result<String, ApiError> {
+    chat::complete(messages).await
 }
 
 #[ic_cdk::update]
-async fn debug_parse_query(user_query: String) -> Result<QueryParseResult, String> {
+async fn debug_parse_query(user_query: String) -> Result<QueryParseResult, ApiError> {
     ic_cdk::println!("=== DEBUG PARSE QUERY ===");
     ic_cdk::println!("Input query: {}", user_query);
 
@@ -159,39 +248,26 @@ async fn debug_parse_query(user_query: String) -> Result<QueryParseResult, Strin
     llm_result
 }
 
-// src/backend/src/lib.rs
-
-// Update the LLM types to match the canister interface
-#[derive(candid::CandidType, serde::Deserialize, Debug, Clone)]
-pub struct ChatMessageV0 {
-    pub content: String,
-    pub role: ChatRoleV0,
-}
-
-#[derive(candid::CandidType, serde::Deserialize, Debug, Clone)]
-pub enum ChatRoleV0 {
-    #[serde(rename = "user")]
-    User,
-    #[serde(rename = "assistant")]
-    Assistant,
-    #[serde(rename = "system")]
-    System,
-}
-
-#[derive(candid::CandidType, serde::Deserialize, Debug, Clone)]
-pub struct ChatRequestV0 {
-    pub model: String,
-    pub messages: Vec<ChatMessageV0>,
+fn parse_context() -> ParseContext {
+    ParseContext {
+        allowed_rpcs: Config::allowed_rpcs(),
+        schema: Some(SchemaRegistry::get()),
+        now: Some(ic_cdk::api::time()),
+        time_zone: Config::time_zone(),
+    }
 }
 
-// src/backend/src/lib.rs
-
 #[ic_cdk::update]
 async fn parse_natural_language_query_with_llm(
     user_query: String,
-) -> Result<QueryParseResult, String> {
-    let llm_canister_id = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai")
-        .map_err(|_| "Invalid LLM canister ID".to_string())?;
+) -> Result<QueryParseResult, ApiError> {
+    let llm_canister_id = match Registry::get(DownstreamCanister::LlmService) {
+        Ok(canister_id) => canister_id,
+        Err(e) => {
+            ic_cdk::println!("{}, using fallback", e);
+            return parse_natural_language_query_fallback(user_query).await;
+        }
+    };
 
     ic_cdk::println!(
         "Calling LLM service parse_natural_language_to_sql with query: {}",
@@ -199,10 +275,10 @@ async fn parse_natural_language_query_with_llm(
     );
 
     // Call the LLM service using parse_natural_language_to_sql method
-    let llm_response: Result<(Result<QueryParseResult, String>,), _> = ic_cdk::call(
+    let llm_response: Result<(Result<QueryParseResult, ApiError>,), _> = ic_cdk::call(
         llm_canister_id,
         "parse_natural_language_to_sql",
-        (user_query.clone(),),
+        (user_query.clone(), Some(parse_context())),
     )
     .await;
 
@@ -228,132 +304,35 @@ async fn parse_natural_language_query_with_llm(
         }
     }
 }
-// Fallback parsing function (improved simple approach)
+// Fallback parsing function, the shared rule-based parser
 #[ic_cdk::update]
 async fn parse_natural_language_query_fallback(
     user_query: String,
-) -> Result<QueryParseResult, String> {
-    let user_query_lower = user_query.to_lowercase();
-
-    // Validate if this looks like a database query
-    let database_keywords = [
-        "todo",
-        "task",
-        "user",
-        "post",
-        "show",
-        "get",
-        "find",
-        "list",
-        "all",
-        "completed",
-        "done",
-        "incomplete",
-        "id",
-    ];
-
-    let has_database_keywords = database_keywords
-        .iter()
-        .any(|&keyword| user_query_lower.contains(keyword));
-
-    if !has_database_keywords {
-        return Ok(QueryParseResult {
-            table: "".to_string(),
-            query: "".to_string(),
-            error: Some(format!(
-                "Unable to parse '{}' as a database query. Please use keywords like 'show', 'get', 'todos', 'users', etc.",
-                user_query
-            )),
-        });
-    }
-
-    // Extract table name
-    let table = if user_query_lower.contains("todos") || user_query_lower.contains("todo") {
-        "todos".to_string()
-    } else if user_query_lower.contains("users") || user_query_lower.contains("user") {
-        "users".to_string()
-    } else if user_query_lower.contains("posts") || user_query_lower.contains("post") {
-        "posts".to_string()
-    } else {
-        return Ok(QueryParseResult {
-            table: "".to_string(),
-            query: "".to_string(),
-            error: Some(
-                "Could not identify table from query. Please mention 'todos', 'users', or 'posts'"
-                    .to_string(),
-            ),
-        });
-    };
-
-    // Extract query type and build Supabase query - Use correct column names
-    let query = if user_query_lower.contains("all") || user_query_lower.contains("everything") {
-        "select=*".to_string()
-    } else if user_query_lower.contains("completed") || user_query_lower.contains("done") {
-        if user_query_lower.contains("not")
-            || user_query_lower.contains("incomplete")
-            || user_query_lower.contains("false")
-            || user_query_lower.contains("unfinished")
-        {
-            "select=*&is_done=eq.false".to_string()
-        } else {
-            "select=*&is_done=eq.true".to_string()
-        }
-    } else if user_query_lower.contains("id") {
-        // Try to extract ID number
-        let words: Vec<&str> = user_query_lower.split_whitespace().collect();
-        if let Some(id_pos) = words.iter().position(|&w| w == "id") {
-            if let Some(id_value) = words.get(id_pos + 1) {
-                if id_value.parse::<i32>().is_ok() {
-                    format!("select=*&id=eq.{}", id_value)
-                } else {
-                    "select=*".to_string()
-                }
-            } else {
-                "select=*".to_string()
-            }
-        } else {
-            "select=*".to_string()
-        }
-    } else if user_query_lower.contains("show")
-        || user_query_lower.contains("get")
-        || user_query_lower.contains("list")
-    {
-        // Allow basic "show" commands even without specific filters
-        "select=*".to_string()
-    } else {
-        return Ok(QueryParseResult {
-            table: "".to_string(),
-            query: "".to_string(),
-            error: Some(format!(
-                "Could not understand what to do with '{}'. Try 'show all todos', 'get completed tasks', etc.",
-                user_query
-            )),
-        });
-    };
-
-    Ok(QueryParseResult {
-        table,
-        query,
-        error: None,
-    })
+) -> Result<QueryParseResult, ApiError> {
+    nl_parser::parse(&user_query, &parse_context())
 }
 
 // Nowa funkcja używająca naszego własnego kanister LLM service
 #[ic_cdk::update]
-async fn parse_with_llm_service(user_query: String) -> Result<QueryParseResult, String> {
+async fn parse_with_llm_service(user_query: String) -> Result<QueryParseResult, ApiError> {
     ic_cdk::println!("Using LLM service to parse query: {}", user_query);
 
     // ID naszego kanister LLM service
-    let llm_service_canister_id = Principal::from_text("br5f7-7uaaa-aaaaa-qaaca-cai")
-        .map_err(|_| "Invalid LLM service canister ID".to_string())?;
+    let llm_service_canister_id = match Registry::get(DownstreamCanister::LlmService) {
+        Ok(canister_id) => canister_id,
+        Err(e) => {
+            ic_cdk::println!("{}, using fallback", e);
+            return parse_enhanced_fallback(user_query).await;
+        }
+    };
 
     ic_cdk::println!("Calling LLM service canister: {}", llm_service_canister_id);
 
     // Wywołaj nasz kanister LLM service
-    let llm_response: Result<(Result<QueryParseResult, String>,), _> = ic_cdk::call(
+    let llm_response: Result<(Result<QueryParseResult, ApiError>,), _> = ic_cdk::call(
         llm_service_canister_id,
         "parse_natural_language_to_sql",
-        (user_query.clone(),),
+        (user_query.clone(), Some(parse_context())),
     )
     .await;
 
@@ -381,157 +360,9 @@ async fn parse_with_llm_service(user_query: String) -> Result<QueryParseResult,
 
 // Ulepszona wersja fallback parsera
 #[ic_cdk::update]
-async fn parse_enhanced_fallback(user_query: String) -> Result<QueryParseResult, String> {
-    let query_lower = user_query.to_lowercase();
-    ic_cdk::println!("Parsing with enhanced fallback: {}", query_lower);
-
-    // First validate if this looks like a meaningful database query
-    let database_keywords = [
-        "todo",
-        "task",
-        "user",
-        "post",
-        "show",
-        "get",
-        "find",
-        "list",
-        "all",
-        "completed",
-        "done",
-        "incomplete",
-        "pending",
-        "due",
-        "date",
-        "title",
-        "id",
-    ];
-
-    let has_database_keywords = database_keywords
-        .iter()
-        .any(|&keyword| query_lower.contains(keyword));
-
-    if !has_database_keywords {
-        return Ok(QueryParseResult {
-            table: "".to_string(),
-            query: "".to_string(),
-            error: Some(format!(
-                "Unable to parse '{}' as a database query. Please use words like 'show todos', 'get users', 'find completed tasks', etc.",
-                user_query
-            )),
-        });
-    }
-
-    // Określ tabelę
-    let table = if query_lower.contains("todo") || query_lower.contains("task") {
-        "todos"
-    } else if query_lower.contains("user") {
-        "users"
-    } else if query_lower.contains("post") {
-        "posts"
-    } else {
-        // Only default to todos if there are other valid query keywords
-        if query_lower.contains("show")
-            || query_lower.contains("get")
-            || query_lower.contains("find")
-            || query_lower.contains("all")
-            || query_lower.contains("completed")
-            || query_lower.contains("done")
-        {
-            "todos"
-        } else {
-            return Ok(QueryParseResult {
-                table: "".to_string(),
-                query: "".to_string(),
-                error: Some(format!(
-                    "Could not determine table from query '{}'. Please specify 'todos', 'users', or 'posts'.",
-                    user_query
-                )),
-            });
-        }
-    };
-
-    // Zbuduj query
-    let mut query_parts = vec!["select=*".to_string()];
-    let mut has_filters = false;
-
-    // Filtry dla todos
-    if table == "todos" {
-        if query_lower.contains("completed") || query_lower.contains("done") {
-            query_parts.push("is_done=eq.true".to_string());
-            has_filters = true;
-        } else if query_lower.contains("incomplete")
-            || query_lower.contains("not done")
-            || query_lower.contains("pending")
-        {
-            query_parts.push("is_done=eq.false".to_string());
-            has_filters = true;
-        }
-
-        if query_lower.contains("due date") && query_lower.contains("not null") {
-            query_parts.push("due_date=not.is.null".to_string());
-            has_filters = true;
-        } else if query_lower.contains("due date") && query_lower.contains("null") {
-            query_parts.push("due_date=is.null".to_string());
-            has_filters = true;
-        }
-
-        // Wyszukiwanie tekstowe w tytule
-        if let Some(search_term) = extract_search_term(&query_lower) {
-            let search_query = format!("title=ilike.*{}*", search_term);
-            query_parts.push(search_query);
-            has_filters = true;
-        }
-    }
-
-    // If no specific filters were found but we have valid keywords, allow basic "all" queries
-    if !has_filters
-        && !query_lower.contains("all")
-        && !query_lower.contains("everything")
-        && !query_lower.contains("show")
-        && !query_lower.contains("get")
-        && !query_lower.contains("list")
-    {
-        return Ok(QueryParseResult {
-            table: "".to_string(),
-            query: "".to_string(),
-            error: Some(format!(
-                "Query '{}' doesn't specify what to retrieve. Try 'show all todos', 'get completed tasks', etc.",
-                user_query
-            )),
-        });
-    }
-
-    let final_query = query_parts.join("&");
-
-    Ok(QueryParseResult {
-        table: table.to_string(),
-        query: final_query,
-        error: None,
-    })
-}
-
-// Funkcja pomocnicza do wyciągania terminu wyszukiwania
-fn extract_search_term(query: &str) -> Option<String> {
-    // Proste parsowanie terminu w cudzysłowach lub po "with", "containing", etc.
-    if let Some(start) = query.find('"') {
-        if let Some(end) = query[start + 1..].find('"') {
-            return Some(query[start + 1..start + 1 + end].to_string());
-        }
-    }
-
-    // Szukaj po "with" lub "containing"
-    for keyword in &["with ", "containing ", "about "] {
-        if let Some(pos) = query.find(keyword) {
-            let remainder = &query[pos + keyword.len()..];
-            if let Some(word_end) = remainder.find(' ') {
-                return Some(remainder[..word_end].to_string());
-            } else {
-                return Some(remainder.to_string());
-            }
-        }
-    }
-
-    None
+async fn parse_enhanced_fallback(user_query: String) -> Result<QueryParseResult, ApiError> {
+    ic_cdk::println!("Parsing with enhanced fallback: {}", user_query);
+    nl_parser::parse(&user_query, &parse_context())
 }
 
 // ...existing code...
@@ -539,180 +370,300 @@ fn extract_search_term(query: &str) -> Option<String> {
 #[ic_cdk::update]
 async fn query_supabase_with_natural_language(
     user_query: String,
-) -> Result<SupabaseResponse, String> {
+) -> Result<SupabaseResponse, ApiError> {
     ic_cdk::println!("=== MAIN QUERY FUNCTION DEBUG ===");
     ic_cdk::println!("User query: {}", user_query);
 
     // Use fallback parsing directly for now
     let parse_result = parse_natural_language_query_with_llm(user_query).await?;
+    execute_parsed(parse_result)
+        .await
+        .map(|(response, _)| response)
+}
 
+// Runs a parsed question and returns the response with the validated table and query
+// it ran as; `None` for functions and previewed changes, which follow-ups can't refine
+async fn execute_parsed(
+    parse_result: QueryParseResult,
+) -> Result<(SupabaseResponse, Option<(String, PostgrestQuery)>), ApiError> {
     ic_cdk::println!(
         "Parse result: table={}, query={}",
         parse_result.table,
         parse_result.query
     );
 
-    if let Some(error) = parse_result.error {
-        return Ok(SupabaseResponse {
-            data: None,
-            error: Some(error),
-        });
+    // Parsers only see the allowlist, so check it again before calling anything
+    if let Some(rpc) = parse_result.rpc {
+        if !Config::allowed_rpcs().contains(&rpc.function) {
+            return Err(ApiError::invalid_input(format!(
+                "Function '{}' is not allowlisted",
+                rpc.function
+            )));
+        }
+        let response = call_supabase_rpc(rpc.function, rpc.args).await?;
+        return Ok((response, None));
+    }
+
+    // Whatever the parser produced is checked against the schema before spending an outcall
+    let query = match parse_result.parsed {
+        Some(query) => query,
+        None => parse_result.query.parse().map_err(ApiError::parse)?,
+    };
+    let schema = SchemaRegistry::get();
+    let validated = validate_query(&schema, &parse_result.table, &query)
+        .map_err(|issues| rejection_error(&issues))?;
+    if !validated.repairs.is_empty() {
+        ic_cdk::println!("Repaired query: {}", describe_issues(&validated.repairs));
+    }
+
+    // Changes are only previewed here and applied by `confirm_mutation`
+    if let Some(mutation) = parse_result.mutation {
+        let (mutation, repairs) =
+            validate_mutation(&schema, &validated.table, &validated.query, &mutation)
+                .map_err(|issues| rejection_error(&issues))?;
+        if !repairs.is_empty() {
+            ic_cdk::println!("Repaired mutation: {}", describe_issues(&repairs));
+        }
+        let response = plan_mutation(validated.table, validated.query, mutation).await?;
+        return Ok((response, None));
     }
 
-    // Use the non-encoding version that we know works
-    fetch_from_supabase_no_encoding(parse_result.table, parse_result.query).await
+    // A plain count doesn't need the rows, or aggregates enabled in PostgREST
+    let response = if validated.query.is_plain_count() {
+        supabase::count_rows(&validated.table, &validated.query).await?
+    } else {
+        SupabaseClient::from_config()?
+            .from(&validated.table)
+            .postgrest(&validated.query)
+            .send()
+            .await?
+            .with_answer(&validated.query)
+    };
+    Ok((response, Some((validated.table, validated.query))))
 }
 
-// Update the main fetch function to not use URL encoding
+// Rows shown in the preview of a change; `affected_rows` in the plan counts all of them
+const PREVIEW_ROWS: u64 = 20;
+
+// Finds the rows a change would affect with a dry-run SELECT and keeps the change as a
+// plan for the caller to confirm
+async fn plan_mutation(
+    table: String,
+    query: PostgrestQuery,
+    mutation: Mutation,
+) -> Result<SupabaseResponse, ApiError> {
+    // A plan that can never be confirmed isn't worth previewing
+    SupabaseClient::from_config()?.ensure_mutations()?;
+    let rows = PostgrestQuery {
+        filters: query.filters,
+        groups: query.groups,
+        ..PostgrestQuery::new()
+    };
+    let (mut preview, affected_rows) = supabase::preview_rows(&table, &rows, PREVIEW_ROWS).await?;
+    let plan = Plans::create(ic_cdk::caller(), &table, rows, mutation, affected_rows);
+    ic_cdk::println!(
+        "Planned {} on {} for {} rows as plan {}",
+        plan.mutation,
+        table,
+        affected_rows,
+        plan.id
+    );
+    preview.plan = Some(plan);
+    Ok(preview)
+}
+
+// Applies a plan from `query_supabase_with_natural_language` and returns the changed
+// or deleted rows. Only the caller who asked for the change can confirm it, once.
 #[ic_cdk::update]
-async fn fetch_from_supabase(table: String, query: String) -> Result<SupabaseResponse, String> {
-    let supabase_url = Config::supabase_url().map_err(|e| e.to_string())?;
-    let supabase_key = Config::supabase_anon_key().map_err(|e| e.to_string())?;
+async fn confirm_mutation(plan_id: u64) -> Result<SupabaseResponse, ApiError> {
+    // Checked before the plan is taken, so a missing gateway doesn't use it up
+    let client = SupabaseClient::from_config()?;
+    client.ensure_mutations()?;
+    let caller = ic_cdk::caller();
+    // Taken while the request is out so it can't be confirmed twice
+    let (plan, rows) = Plans::take(plan_id, caller)?;
+    let request = client
+        .from(&plan.table)
+        .postgrest(&rows)
+        .prefer("return=representation");
+    let request = match plan.mutation.action {
+        MutationAction::Update => request
+            .method(Method::Patch)
+            .json_body(plans::patch_json(&plan.mutation)),
+        MutationAction::Delete => request.method(Method::Delete),
+    };
+    // A failed send changed nothing that can't be asked again with the same filter, so
+    // the plan goes back for another try rather than making the caller start over
+    let result = request.send().await;
+    if result.is_err() {
+        Plans::restore(caller, plan, rows);
+    }
+    result
+}
 
+#[ic_cdk::update]
+fn cancel_mutation(plan_id: u64) -> Result<(), ApiError> {
+    Plans::cancel(plan_id, ic_cdk::caller())
+}
+
+#[ic_cdk::update]
+async fn fetch_from_supabase(table: String, query: String) -> Result<SupabaseResponse, ApiError> {
     ic_cdk::println!(
         "Fetching from Supabase - Table: {}, Query: {}",
         table,
         query
     );
 
-    let url = if query.is_empty() {
-        format!("{}/rest/v1/{}", supabase_url, table)
-    } else {
-        // Don't URL encode - use direct concatenation since it works
-        format!("{}/rest/v1/{}?{}", supabase_url, table, query)
-    };
+    SupabaseClient::from_config()?
+        .from(&table)
+        .query(&query)
+        .send()
+        .await
+}
 
-    ic_cdk::println!("Final URL: {}", url);
+async fn fetch_records<T: FromRow>(query: String) -> Result<Vec<T>, ApiError> {
+    let response = fetch_from_supabase(T::TABLE.to_string(), query).await?;
+    let rows = response
+        .rows
+        .ok_or_else(|| ApiError::parse(format!("{} response is not a list of rows", T::TABLE)))?;
+    tables::records(&rows).map_err(ApiError::parse)
+}
 
-    let request_headers = vec![
-        HttpHeader {
-            name: "apikey".to_string(),
-            value: supabase_key.to_string(),
-        },
-        HttpHeader {
-            name: "Authorization".to_string(),
-            value: format!("Bearer {}", supabase_key),
-        },
-        HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        },
-        HttpHeader {
-            name: "Accept".to_string(),
-            value: "application/json".to_string(),
-        },
-    ];
-
-    let request = CanisterHttpRequestArgument {
-        url: url.clone(),
-        method: HttpMethod::GET,
-        body: None,
-        max_response_bytes: Some(8192),
-        transform: Some(TransformContext::from_name(
-            "transform".to_string(),
-            serde_json::to_vec(&()).unwrap(),
-        )),
-        headers: request_headers,
-    };
+#[ic_cdk::update]
+async fn fetch_todos(query: String) -> Result<Vec<Todo>, ApiError> {
+    fetch_records(query).await
+}
 
-    match http_request(request, 50_000_000_000).await {
-        Ok((response,)) => {
-            let str_body = String::from_utf8(response.body)
-                .map_err(|_| "Failed to parse response body as UTF-8".to_string())?;
-
-            let status_code: u32 = response.status.0.to_string().parse().unwrap_or(500);
-            ic_cdk::println!("Response status: {}, body: {}", status_code, str_body);
-
-            if (200..300).contains(&status_code) {
-                Ok(SupabaseResponse {
-                    data: Some(str_body),
-                    error: None,
-                })
-            } else {
-                Ok(SupabaseResponse {
-                    data: None,
-                    error: Some(format!("HTTP {} - {}", status_code, str_body)),
-                })
-            }
-        }
-        Err((r, m)) => {
-            let message = format!("HTTP request failed with code {:?}: {}", r, m);
-            Ok(SupabaseResponse {
-                data: None,
-                error: Some(message),
-            })
-        }
-    }
+#[ic_cdk::update]
+async fn fetch_users(query: String) -> Result<Vec<User>, ApiError> {
+    fetch_records(query).await
 }
 
 #[ic_cdk::update]
-async fn insert_to_supabase(table: String, data: String) -> Result<SupabaseResponse, String> {
-    let supabase_url = Config::supabase_url().map_err(|e| e.to_string())?;
-    let supabase_key = Config::supabase_anon_key().map_err(|e| e.to_string())?;
+async fn fetch_posts(query: String) -> Result<Vec<Post>, ApiError> {
+    fetch_records(query).await
+}
 
-    let url = format!("{}/rest/v1/{}", supabase_url, table);
+// Reads one page, or with `row_budget` several, of the rows matching `query`
+#[ic_cdk::update]
+async fn fetch_page_from_supabase(
+    table: String,
+    query: String,
+    options: Option<PageOptions>,
+) -> Result<Page, ApiError> {
+    supabase::fetch_pages(&table, &query, options.unwrap_or_default()).await
+}
 
-    let request_headers = vec![
-        HttpHeader {
-            name: "apikey".to_string(),
-            value: supabase_key.to_string(),
-        },
-        HttpHeader {
-            name: "Authorization".to_string(),
-            value: format!("Bearer {}", supabase_key),
-        },
-        HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        },
-        HttpHeader {
-            name: "Accept".to_string(),
-            value: "application/json".to_string(),
-        },
-        HttpHeader {
-            name: "Prefer".to_string(),
-            value: "return=representation".to_string(),
-        },
-    ];
-
-    let request = CanisterHttpRequestArgument {
-        url: url.clone(),
-        method: HttpMethod::POST,
-        body: Some(data.into_bytes()),
-        max_response_bytes: Some(8192),
-        transform: Some(TransformContext::from_name(
-            "transform".to_string(),
-            serde_json::to_vec(&()).unwrap(),
-        )),
-        headers: request_headers,
+// Calls a Postgres function exposed by PostgREST; `args_json` is a JSON object of named
+// arguments and may be empty for functions without any
+#[ic_cdk::update]
+async fn call_supabase_rpc(
+    function: String,
+    args_json: String,
+) -> Result<SupabaseResponse, ApiError> {
+    validate_function_name(&function).map_err(ApiError::invalid_input)?;
+    let args_json = if args_json.trim().is_empty() {
+        "{}".to_string()
+    } else {
+        args_json
     };
+    let args: serde_json::Value = serde_json::from_str(&args_json)
+        .map_err(|e| ApiError::invalid_input(format!("Arguments are not valid JSON: {}", e)))?;
+    if !args.is_object() {
+        return Err(ApiError::invalid_input(
+            "Arguments must be a JSON object of named parameters",
+        ));
+    }
 
-    match http_request(request, 50_000_000_000).await {
-        Ok((response,)) => {
-            let str_body = String::from_utf8(response.body)
-                .map_err(|_| "Failed to parse response body as UTF-8".to_string())?;
-
-            let status_code: u32 = response.status.0.to_string().parse().unwrap_or(500);
-            if (200..300).contains(&status_code) {
-                Ok(SupabaseResponse {
-                    data: Some(str_body),
-                    error: None,
-                })
-            } else {
-                Ok(SupabaseResponse {
-                    data: None,
-                    error: Some(format!("HTTP {} - {}", status_code, str_body)),
-                })
-            }
-        }
-        Err((r, m)) => {
-            let message = format!("HTTP request failed with code {:?}: {}", r, m);
-            Ok(SupabaseResponse {
-                data: None,
-                error: Some(message),
-            })
-        }
+    SupabaseClient::from_config()?
+        .rpc(&function)
+        .json_body(args_json)
+        .send()
+        .await
+}
+
+#[ic_cdk::update]
+async fn insert_to_supabase(table: String, data: String) -> Result<SupabaseResponse, ApiError> {
+    SupabaseClient::from_config()?
+        .from(&table)
+        .method(Method::Post)
+        .prefer("return=representation")
+        .json_body(data)
+        .send()
+        .await
+}
+
+// Inserts `data` or merges it into existing rows that collide on `on_conflict`
+// (the primary key when empty)
+#[ic_cdk::update]
+async fn upsert_to_supabase(
+    table: String,
+    data: String,
+    on_conflict: Vec<String>,
+) -> Result<SupabaseResponse, ApiError> {
+    let query = on_conflict_query(&on_conflict)?;
+
+    SupabaseClient::from_config()?
+        .from(&table)
+        .method(Method::Post)
+        .query(&query)
+        .prefer("resolution=merge-duplicates")
+        .prefer("return=representation")
+        .json_body(data)
+        .send()
+        .await
+}
+
+#[ic_cdk::update]
+async fn bulk_insert_to_supabase(
+    table: String,
+    rows: Vec<Row>,
+    options: Option<BulkInsertOptions>,
+) -> Result<BulkInsertResult, ApiError> {
+    supabase::bulk_insert(&table, &rows, options.unwrap_or_default()).await
+}
+
+// Applies a JSON object `patch` to the rows matched by `filter`, e.g. `id=eq.5`
+#[ic_cdk::update]
+async fn update_in_supabase(
+    table: String,
+    filter: String,
+    patch: String,
+) -> Result<SupabaseResponse, ApiError> {
+    ensure_row_filter(&filter)?;
+    let patch_value: serde_json::Value = serde_json::from_str(&patch)
+        .map_err(|e| ApiError::invalid_input(format!("Patch is not valid JSON: {}", e)))?;
+    if patch_value
+        .as_object()
+        .is_none_or(|fields| fields.is_empty())
+    {
+        return Err(ApiError::invalid_input(
+            "Patch must be a JSON object with at least one column",
+        ));
     }
+
+    SupabaseClient::from_config()?
+        .from(&table)
+        .method(Method::Patch)
+        .query(&filter)
+        .prefer("return=representation")
+        .json_body(patch)
+        .send()
+        .await
+}
+
+// Deletes the rows matched by `filter` and returns them
+#[ic_cdk::update]
+async fn delete_from_supabase(table: String, filter: String) -> Result<SupabaseResponse, ApiError> {
+    ensure_row_filter(&filter)?;
+
+    SupabaseClient::from_config()?
+        .from(&table)
+        .method(Method::Delete)
+        .query(&filter)
+        .prefer("return=representation")
+        .send()
+        .await
 }
 
 #[ic_cdk::query]
@@ -722,6 +673,8 @@ fn transform(raw: TransformArgs) -> HttpResponse {
         if header.name.to_lowercase().starts_with("x-")
             || header.name.to_lowercase() == "content-type"
             || header.name.to_lowercase() == "accept"
+            // Carries the total row count of paginated reads
+            || header.name.to_lowercase() == "content-range"
         {
             sanitized_headers.push(header.clone());
         }
@@ -734,204 +687,188 @@ fn transform(raw: TransformArgs) -> HttpResponse {
     }
 }
 #[ic_cdk::update]
-async fn create_test_todos() -> Result<SupabaseResponse, String> {
+async fn create_test_todos() -> Result<SupabaseResponse, ApiError> {
     // Fix: Include user_id in the test data to satisfy the NOT NULL constraint
+    // Fixed ids far above the serial sequence make re-running update these rows instead
+    // of adding duplicates
     let test_todos = r#"[
-        {"title": "Buy groceries", "is_done": false, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
-        {"title": "Walk the dog", "is_done": true, "user_id": "123e4567-e89b-12d3-a456-426614174000"}, 
-        {"title": "Finish project", "is_done": false, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
-        {"title": "Read book", "is_done": true, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
-        {"title": "Learn Rust", "is_done": true, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
-        {"title": "Build IC app", "is_done": false, "user_id": "123e4567-e89b-12d3-a456-426614174000"}
+        {"id": 900001, "title": "Buy groceries", "is_done": false, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
+        {"id": 900002, "title": "Walk the dog", "is_done": true, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
+        {"id": 900003, "title": "Finish project", "is_done": false, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
+        {"id": 900004, "title": "Read book", "is_done": true, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
+        {"id": 900005, "title": "Learn Rust", "is_done": true, "user_id": "123e4567-e89b-12d3-a456-426614174000"},
+        {"id": 900006, "title": "Build IC app", "is_done": false, "user_id": "123e4567-e89b-12d3-a456-426614174000"}
     ]"#;
 
     ic_cdk::println!("Creating test todos with data: {}", test_todos);
-    insert_to_supabase("todos".to_string(), test_todos.to_string()).await
+    upsert_to_supabase(
+        "todos".to_string(),
+        test_todos.to_string(),
+        vec!["id".to_string()],
+    )
+    .await
 }
 
-// Add missing prompt function with robust error handling
+// Answers in the caller's current session: follow-ups refine the session's last query,
+// database questions run like `query_supabase_with_natural_language` and anything else
+// goes to the LLM with the conversation so far
 #[ic_cdk::update]
-async fn prompt(user_prompt: String) -> String {
+async fn prompt(user_prompt: String) -> Result<String, ApiError> {
     ic_cdk::println!("Received prompt: {}", user_prompt);
 
-    // Check if this is a database query first
+    sessions::check_prompt(&user_prompt)?;
+    let caller = ic_cdk::caller();
+    let session = Sessions::open(caller, &user_prompt);
+    // A failed answer leaves only the prompt in the session, so asking again continues it
+    let (reply, last_query) = answer_prompt(&user_prompt, session.as_ref()).await?;
+    if let Some(session) = session {
+        Sessions::record(caller, session.id, &reply, last_query);
+    }
+    Ok(reply)
+}
+
+async fn answer_prompt(
+    user_prompt: &str,
+    session: Option<&Session>,
+) -> Result<(String, Option<LastQuery>), ApiError> {
+    let follow_up = session
+        .and_then(|session| session.last_query.as_ref())
+        .and_then(|last| {
+            nl_parser::refine(user_prompt, &last.table, &last.query, &parse_context())
+        });
+
+    let parse_result = match follow_up {
+        Some(result) => {
+            ic_cdk::println!("Detected follow-up, refining the session's last query");
+            result
+        }
+        None if is_database_question(user_prompt) => {
+            ic_cdk::println!("Detected database query, processing with natural language parser");
+            parse_natural_language_query_with_llm(user_prompt.to_string()).await
+        }
+        None => return Ok((ask_llm(user_prompt, session).await?, None)),
+    };
+
+    match execute_parsed(parse_result?).await? {
+        // A change only has a plan so far, which the caller needs to confirm it
+        (
+            SupabaseResponse {
+                plan: Some(plan),
+                data,
+                ..
+            },
+            _,
+        ) => Ok((plans::describe(&plan, &data), None)),
+        (response, ran) => {
+            let last_query = ran.map(|(table, query)| LastQuery {
+                question: user_prompt.to_string(),
+                table,
+                query,
+                summary: sessions::summarize(&response),
+            });
+            Ok((
+                format!(
+                    "Database query executed successfully. Results:\n{}",
+                    response.data
+                ),
+                last_query,
+            ))
+        }
+    }
+}
+
+fn is_database_question(user_prompt: &str) -> bool {
     let prompt_lower = user_prompt.to_lowercase();
-    if prompt_lower.contains("todo")
-        || prompt_lower.contains("user")
-        || prompt_lower.contains("post")
+    SchemaRegistry::get().match_table(&prompt_lower).is_some()
         || prompt_lower.contains("show")
         || prompt_lower.contains("get")
         || prompt_lower.contains("find")
         || prompt_lower.contains("all")
         || prompt_lower.contains("select")
-    {
-        ic_cdk::println!("Detected database query, processing with natural language parser");
-
-        // Use the existing natural language processing for database queries
-        match query_supabase_with_natural_language(user_prompt.clone()).await {
-            Ok(response) => {
-                if let Some(data) = response.data {
-                    format!("Database query executed successfully. Results:\n{}", data)
-                } else if let Some(error) = response.error {
-                    format!("Database query failed: {}", error)
-                } else {
-                    "Database query executed but no data returned".to_string()
-                }
-            }
-            Err(error) => {
-                format!("Error processing database query: {}", error)
-            }
-        }
-    } else {
-        // For general LLM queries, handle the timeout issue gracefully
-        ic_cdk::println!("Detected general LLM query, attempting to call LLM canister");
-
-        let llm_canister_id_result = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai");
-
-        match llm_canister_id_result {
-            Ok(llm_canister_id) => {
-                // Define the chat message types matching LLM canister exactly
-                #[derive(CandidType)]
-                struct ChatRequestV0 {
-                    model: String,
-                    messages: Vec<ChatMessageV0>,
-                }
-
-                #[derive(CandidType)]
-                struct ChatMessageV0 {
-                    content: String,
-                    role: ChatRoleV0,
-                }
-
-                #[derive(CandidType)]
-                enum ChatRoleV0 {
-                    User,
-                    Assistant,
-                    System,
-                }
-
-                let chat_request = ChatRequestV0 {
-                    model: "llama3.1:8b".to_string(),
-                    messages: vec![ChatMessageV0 {
-                        content: user_prompt.clone(),
-                        role: ChatRoleV0::User,
-                    }],
-                };
-
-                ic_cdk::println!("Calling LLM canister with v0_chat method - this may take time for model loading");
-
-                // Add a longer timeout and better error handling for model loading
-                match ic_cdk::call::<(ChatRequestV0,), (String,)>(
-                    llm_canister_id,
-                    "v0_chat",
-                    (chat_request,),
-                )
-                .await
-                {
-                    Ok((response,)) => {
-                        ic_cdk::println!("LLM canister responded successfully");
-                        response
-                    }
-                    Err(e) => {
-                        ic_cdk::println!("LLM canister call failed: {:?}", e);
-
-                        // Provide a helpful response when LLM fails
-                        let error_msg = format!("{:?}", e);
-                        if error_msg.contains("Timeout") || error_msg.contains("timeout") {
-                            format!(
-                                "I'm sorry, the AI model is taking longer than expected to respond (likely due to model loading time). \
-                                This is common on the first request. Please try again in a moment, or try a database-related query like 'show all todos' which I can handle directly.\n\n\
-                                Your original question was: \"{}\"", 
-                                user_prompt
-                            )
-                        } else if error_msg.contains("Panicked") || error_msg.contains("trap") {
-                            format!(
-                                "I'm experiencing a technical issue with the AI model service. \
-                                However, I can help you with database queries like 'show all todos', 'get completed tasks', etc.\n\n\
-                                For general questions, please try again later when the AI service is more stable.\n\n\
-                                Your original question was: \"{}\"", 
-                                user_prompt
-                            )
-                        } else {
-                            format!(
-                                "I'm currently unable to process your request due to a technical issue: {}\n\n\
-                                I can help you with database queries instead. Try asking 'show all todos' or 'get my tasks'.\n\n\
-                                Your original question was: \"{}\"", 
-                                error_msg, user_prompt
-                            )
-                        }
-                    }
-                }
-            }
-            Err(_) => {
-                ic_cdk::println!("Invalid LLM canister ID");
-                format!(
-                    "I'm sorry, there's a configuration issue with the AI service. \
-                    However, I can help you with database queries like 'show all todos', 'find completed tasks', etc.\n\n\
-                    Your question was: \"{}\"", 
-                    user_prompt
-                )
-            }
-        }
-    }
 }
 
-// Add a warm-up function to pre-load the LLM model
+// General questions go to the LLM canister with the session history
+async fn ask_llm(user_prompt: &str, session: Option<&Session>) -> Result<String, ApiError> {
+    ic_cdk::println!("Detected general LLM query, attempting to call LLM canister");
+
+    // The session already ends with this prompt
+    let history = match session {
+        Some(session) => session
+            .messages
+            .iter()
+            .map(|message| ChatMessage {
+                role: message.role.clone(),
+                content: message.content.clone(),
+            })
+            .collect(),
+        None => vec![ChatMessage {
+            role: "user".to_string(),
+            content: user_prompt.to_string(),
+        }],
+    };
+
+    ic_cdk::println!(
+        "Calling LLM canister with v0_chat method - this may take time for model loading"
+    );
+    // Timeouts while the model loads come back retryable, so clients can ask again
+    chat::complete(history).await.inspect_err(|e| {
+        ic_cdk::println!("LLM canister call failed: {}", e);
+    })
+}
+
+// Conversations of the caller, most recently used first
+#[ic_cdk::query]
+fn list_sessions() -> Vec<SessionSummary> {
+    Sessions::list(ic_cdk::caller())
+}
+
+// Starts an empty session that the next `prompt` continues
 #[ic_cdk::update]
-async fn warm_up_llm() -> String {
-    ic_cdk::println!("Warming up LLM model - this will pre-load llama3.1:8b");
+fn start_session() -> Result<SessionSummary, ApiError> {
+    Sessions::start(ic_cdk::caller())
+}
 
-    let llm_canister_id_result = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai");
+// Makes an earlier session the one `prompt` continues and returns its history
+#[ic_cdk::update]
+fn resume_session(session_id: u64) -> Result<Session, ApiError> {
+    Sessions::resume(ic_cdk::caller(), session_id)
+}
 
-    match llm_canister_id_result {
-        Ok(llm_canister_id) => {
-            #[derive(CandidType)]
-            struct ChatRequestV0 {
-                model: String,
-                messages: Vec<ChatMessageV0>,
-            }
+#[ic_cdk::update]
+fn delete_session(session_id: u64) -> Result<(), ApiError> {
+    Sessions::delete(ic_cdk::caller(), session_id)
+}
 
-            #[derive(CandidType)]
-            struct ChatMessageV0 {
-                content: String,
-                role: ChatRoleV0,
-            }
+// Add a warm-up function to pre-load the LLM model
+#[ic_cdk::update]
+async fn warm_up_llm() -> Result<String, ApiError> {
+    ic_cdk::println!(
+        "Warming up LLM model - this will pre-load {}",
+        chat::LLM_MODEL
+    );
 
-            #[derive(CandidType)]
-            enum ChatRoleV0 {
-                User,
-                Assistant,
-                System,
-            }
+    let llm_canister_id = Registry::get(DownstreamCanister::Llm)?;
+    let chat_request = ChatRequestV0::new(vec![ChatMessageV0 {
+        content: "Hello".to_string(), // Simple warm-up message
+        role: ChatRoleV0::User,
+    }]);
 
-            let chat_request = ChatRequestV0 {
-                model: "llama3.1:8b".to_string(),
-                messages: vec![ChatMessageV0 {
-                    content: "Hello".to_string(), // Simple warm-up message
-                    role: ChatRoleV0::User,
-                }],
-            };
-
-            ic_cdk::println!("Sending warm-up request to LLM canister");
-
-            match ic_cdk::call::<(ChatRequestV0,), (String,)>(
-                llm_canister_id,
-                "v0_chat",
-                (chat_request,),
-            )
-            .await
-            {
-                Ok((response,)) => {
-                    ic_cdk::println!("LLM warm-up successful");
-                    format!("LLM model warmed up successfully. Response: {}", response)
-                }
-                Err(e) => {
-                    ic_cdk::println!("LLM warm-up failed: {:?}", e);
-                    format!("LLM warm-up failed: {:?}", e)
-                }
-            }
+    ic_cdk::println!("Sending warm-up request to LLM canister");
+
+    match ic_cdk::call::<(ChatRequestV0,), (String,)>(llm_canister_id, "v0_chat", (chat_request,))
+        .await
+    {
+        Ok((response,)) => {
+            ic_cdk::println!("LLM warm-up successful");
+            Ok(format!(
+                "LLM model warmed up successfully. Response: {}",
+                response
+            ))
+        }
+        Err((code, message)) => {
+            ic_cdk::println!("LLM warm-up failed: {:?} {}", code, message);
+            Err(call_error(code, message))
         }
-        Err(_) => "Invalid LLM canister ID".to_string(),
     }
 }
 
@@ -0,0 +1,249 @@
+// Changes asked for in natural language ("mark 'Walk the dog' as done", "delete all
+// archived todos") aren't applied right away. They are kept as plans with a preview of
+// the rows they would touch, and only run once the caller confirms the plan id.
+//
+// Plans live in heap memory and expire after PLAN_TTL, so an upgrade only drops pending
+// plans, which means asking again. Ids come from stable state and never repeat, so an
+// id from before an upgrade can't confirm a newer plan.
+
+use crate::storage;
+use candid::{CandidType, Deserialize, Principal};
+use common::{ApiError, FilterValue, Mutation, PostgrestQuery};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const PLAN_TTL: Duration = Duration::from_secs(5 * 60);
+// Oldest plans are dropped beyond this, so abandoned previews can't fill the heap
+const MAX_PLANS: usize = 100;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MutationPlan {
+    pub id: u64,
+    pub table: String,
+    pub mutation: Mutation,
+    // The filters picking the rows, e.g. `is_done=eq.true&id=gt.5`
+    pub filter: String,
+    // How many rows matched the filter when the plan was made
+    pub affected_rows: u64,
+    // Nanoseconds since the epoch; the plan can't be confirmed after that
+    pub expires_at: u64,
+}
+
+struct PendingPlan {
+    plan: MutationPlan,
+    rows: PostgrestQuery,
+    owner: Principal,
+}
+
+thread_local! {
+    static PLANS: RefCell<BTreeMap<u64, PendingPlan>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub struct Plans;
+
+impl Plans {
+    // Keeps a plan for `owner`; `rows` must only hold the filters picking the rows
+    pub fn create(
+        owner: Principal,
+        table: &str,
+        rows: PostgrestQuery,
+        mutation: Mutation,
+        affected_rows: u64,
+    ) -> MutationPlan {
+        let now = ic_cdk::api::time();
+        let id = storage::update_state(|state| {
+            let id = state.last_plan_id.unwrap_or_default() + 1;
+            state.last_plan_id = Some(id);
+            id
+        });
+        let plan = MutationPlan {
+            id,
+            table: table.to_string(),
+            mutation,
+            filter: filter_string(&rows),
+            affected_rows,
+            expires_at: now.saturating_add(PLAN_TTL.as_nanos() as u64),
+        };
+
+        PLANS.with(|plans| {
+            let mut plans = plans.borrow_mut();
+            plans.retain(|_, pending| pending.plan.expires_at > now);
+            while plans.len() >= MAX_PLANS {
+                plans.pop_first();
+            }
+            plans.insert(
+                id,
+                PendingPlan {
+                    plan: plan.clone(),
+                    rows,
+                    owner,
+                },
+            );
+        });
+        plan
+    }
+
+    // Removes the plan so it can only be applied once, and returns it with the query
+    // picking its rows
+    pub fn take(id: u64, caller: Principal) -> Result<(MutationPlan, PostgrestQuery), ApiError> {
+        let pending = Self::remove(id, caller)?;
+        if pending.plan.expires_at <= ic_cdk::api::time() {
+            return Err(ApiError::invalid_input(format!(
+                "Plan {} has expired, ask for the change again to get a new preview",
+                id
+            )));
+        }
+        Ok((pending.plan, pending.rows))
+    }
+
+    // Puts back a plan from `take` whose change couldn't be sent, so confirming it can be
+    // tried again until it expires
+    pub fn restore(owner: Principal, plan: MutationPlan, rows: PostgrestQuery) {
+        PLANS.with(|plans| {
+            plans
+                .borrow_mut()
+                .insert(plan.id, PendingPlan { plan, rows, owner })
+        });
+    }
+
+    pub fn cancel(id: u64, caller: Principal) -> Result<(), ApiError> {
+        Self::remove(id, caller).map(|_| ())
+    }
+
+    fn remove(id: u64, caller: Principal) -> Result<PendingPlan, ApiError> {
+        PLANS.with(|plans| {
+            let mut plans = plans.borrow_mut();
+            match plans.get(&id) {
+                Some(pending) if pending.owner != caller => Err(ApiError::unauthorized(format!(
+                    "Plan {} was made by another caller",
+                    id
+                ))),
+                Some(_) => Ok(plans.remove(&id).expect("plan was just found")),
+                None => Err(ApiError::invalid_input(format!(
+                    "No pending plan {}, it was already confirmed, cancelled or has expired",
+                    id
+                ))),
+            }
+        })
+    }
+}
+
+// How `prompt` answers a change: a preview with everything needed to confirm it
+pub fn describe(plan: &MutationPlan, preview: &str) -> String {
+    let rows = if plan.affected_rows == 1 {
+        "row"
+    } else {
+        "rows"
+    };
+    format!(
+        "Nothing has been changed yet, this is a preview. Plan {id} ({mutation}) would affect {count} {rows} of {table} where {filter}. \
+        Confirm it with confirm_mutation({id}) or drop it with cancel_mutation({id}); it expires in {minutes} minutes.\n\
+        Rows that would change:\n{preview}",
+        id = plan.id,
+        mutation = plan.mutation,
+        count = plan.affected_rows,
+        rows = rows,
+        table = plan.table,
+        filter = plan.filter,
+        minutes = PLAN_TTL.as_secs() / 60,
+        preview = preview,
+    )
+}
+
+// `is_done=eq.true&or=(...)`, the part of the query PATCH and DELETE use
+fn filter_string(rows: &PostgrestQuery) -> String {
+    rows.filters
+        .iter()
+        .map(ToString::to_string)
+        .chain(rows.groups.iter().map(ToString::to_string))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// The PATCH body of an update, `{"is_done":true}`
+pub fn patch_json(mutation: &Mutation) -> String {
+    let fields: Map<String, Value> = mutation
+        .set
+        .iter()
+        .map(|assignment| (assignment.column.clone(), json_value(&assignment.value)))
+        .collect();
+    Value::Object(fields).to_string()
+}
+
+fn json_value(value: &FilterValue) -> Value {
+    match value {
+        FilterValue::Null => Value::Null,
+        FilterValue::Bool(value) => Value::Bool(*value),
+        FilterValue::Int(value) => Value::from(*value),
+        FilterValue::Float(value) => Value::from(*value),
+        FilterValue::Text(value) => Value::String(value.clone()),
+        FilterValue::List(values) => Value::Array(values.iter().map(json_value).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Assignment;
+
+    #[test]
+    fn describes_a_plan_as_a_preview() {
+        let plan = MutationPlan {
+            id: 7,
+            table: "todos".to_string(),
+            mutation: Mutation::update(vec![Assignment::new("is_done", true)]),
+            filter: "status=eq.open".to_string(),
+            affected_rows: 2,
+            expires_at: 0,
+        };
+
+        assert_eq!(
+            describe(&plan, "[{\"id\":1},{\"id\":2}]"),
+            "Nothing has been changed yet, this is a preview. Plan 7 (update set is_done=true) would affect 2 rows of todos where status=eq.open. \
+            Confirm it with confirm_mutation(7) or drop it with cancel_mutation(7); it expires in 5 minutes.\n\
+            Rows that would change:\n[{\"id\":1},{\"id\":2}]"
+        );
+
+        let plan = MutationPlan {
+            mutation: Mutation::delete(),
+            affected_rows: 1,
+            ..plan
+        };
+        assert!(describe(&plan, "[]").contains("Plan 7 (delete) would affect 1 row of todos"));
+    }
+
+    #[test]
+    fn patches_hold_the_assigned_values() {
+        let mutation = Mutation::update(vec![
+            Assignment::new("is_done", true),
+            Assignment::new("status", "closed"),
+            Assignment::new("due_date", FilterValue::Null),
+        ]);
+        assert_eq!(
+            patch_json(&mutation),
+            r#"{"due_date":null,"is_done":true,"status":"closed"}"#
+        );
+    }
+
+    #[test]
+    fn restores_a_plan_whose_change_could_not_be_sent() {
+        let owner = Principal::from_slice(&[1]);
+        let plan = MutationPlan {
+            id: 41,
+            table: "todos".to_string(),
+            mutation: Mutation::delete(),
+            filter: "is_done=eq.true".to_string(),
+            affected_rows: 3,
+            expires_at: u64::MAX,
+        };
+
+        Plans::restore(owner, plan, PostgrestQuery::new());
+
+        let other = Plans::cancel(41, Principal::anonymous()).unwrap_err();
+        assert_eq!(other.code, common::ErrorCode::Unauthorized);
+        assert!(Plans::cancel(41, owner).is_ok());
+        assert!(Plans::cancel(41, owner).is_err());
+    }
+}
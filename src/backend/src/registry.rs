@@ -0,0 +1,185 @@
+// Registry of the downstream canisters the backend calls (llm_service and the pulled `llm` canister)
+// Principals differ per network, so they are set at install time or by controllers
+// and kept in stable memory. Health is checked with a ping and kept in heap memory.
+
+use crate::storage::{self, Candid, Memory};
+use candid::{CandidType, Deserialize, Principal};
+use common::ApiError;
+use ic_cdk::api::call::RejectionCode;
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DownstreamCanister {
+    LlmService,
+    Llm,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum HealthStatus {
+    Unknown,
+    NotRegistered,
+    Healthy,
+    Unreachable(String),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RegisteredCanister {
+    pub name: DownstreamCanister,
+    pub canister_id: Option<Principal>,
+    pub health: HealthStatus,
+    pub last_checked_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct StoredRegistry {
+    llm_service: Option<Principal>,
+    llm: Option<Principal>,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<StableCell<Candid<StoredRegistry>, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::REGISTRY_MEMORY_ID), Candid::default())
+            .expect("failed to initialize canister registry in stable memory"),
+    );
+
+    static HEALTH: RefCell<BTreeMap<DownstreamCanister, (HealthStatus, u64)>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+impl DownstreamCanister {
+    pub const ALL: [DownstreamCanister; 2] =
+        [DownstreamCanister::LlmService, DownstreamCanister::Llm];
+
+    fn name(&self) -> &'static str {
+        match self {
+            DownstreamCanister::LlmService => "llm_service",
+            DownstreamCanister::Llm => "llm",
+        }
+    }
+}
+
+pub struct Registry;
+
+impl Registry {
+    pub fn get(canister: DownstreamCanister) -> Result<Principal, ApiError> {
+        REGISTRY
+            .with(|cell| {
+                let cell = cell.borrow();
+                let registry = &cell.get().0;
+                match canister {
+                    DownstreamCanister::LlmService => registry.llm_service,
+                    DownstreamCanister::Llm => registry.llm,
+                }
+            })
+            .ok_or_else(|| {
+                ApiError::config(format!(
+                    "The {} canister is not registered, call set_downstream_canister",
+                    canister.name()
+                ))
+            })
+    }
+
+    pub fn set(canister: DownstreamCanister, canister_id: Principal) -> Result<(), ApiError> {
+        if canister_id == Principal::anonymous() || canister_id == ic_cdk::id() {
+            return Err(ApiError::invalid_input(format!(
+                "{} is not a valid {} canister",
+                canister_id,
+                canister.name()
+            )));
+        }
+
+        REGISTRY.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let mut registry = cell.get().0.clone();
+            match canister {
+                DownstreamCanister::LlmService => registry.llm_service = Some(canister_id),
+                DownstreamCanister::Llm => registry.llm = Some(canister_id),
+            }
+            cell.set(Candid(registry)).map(|_| ()).map_err(|e| {
+                ApiError::storage(format!("Failed to store canister registry: {:?}", e))
+            })
+        })?;
+
+        // A new principal invalidates whatever we knew about the old one
+        HEALTH.with(|health| health.borrow_mut().remove(&canister));
+        Ok(())
+    }
+
+    pub fn list() -> Vec<RegisteredCanister> {
+        DownstreamCanister::ALL
+            .iter()
+            .map(|&canister| {
+                let health = HEALTH.with(|health| health.borrow().get(&canister).cloned());
+                RegisteredCanister {
+                    name: canister,
+                    canister_id: Self::get(canister).ok(),
+                    health: health
+                        .as_ref()
+                        .map(|(status, _)| status.clone())
+                        .unwrap_or(HealthStatus::Unknown),
+                    last_checked_at: health.map(|(_, checked_at)| checked_at),
+                }
+            })
+            .collect()
+    }
+
+    // Pings every registered canister and records the outcome
+    pub async fn check_all() -> Vec<RegisteredCanister> {
+        for canister in DownstreamCanister::ALL {
+            let status = match Self::get(canister) {
+                Ok(canister_id) => ping(canister_id).await,
+                Err(_) => HealthStatus::NotRegistered,
+            };
+            ic_cdk::println!("Health check for {}: {:?}", canister.name(), status);
+            HEALTH.with(|health| {
+                health
+                    .borrow_mut()
+                    .insert(canister, (status, ic_cdk::api::time()))
+            });
+        }
+        Self::list()
+    }
+}
+
+// Calls `health` on the canister. A canister without that method (like the pulled `llm`
+// canister) is running if the call got as far as looking the method up, and one that
+// rejects the call itself ran it. Stopped canisters and ones without a wasm module are
+// rejected with `CanisterError` too, so only the method-not-found error (IC0536) counts.
+async fn ping(canister_id: Principal) -> HealthStatus {
+    match ic_cdk::call::<(), (String,)>(canister_id, "health", ()).await {
+        Ok(_) | Err((RejectionCode::CanisterReject, _)) => HealthStatus::Healthy,
+        Err((RejectionCode::CanisterError, message)) if is_method_not_found(&message) => {
+            HealthStatus::Healthy
+        }
+        Err((code, message)) => HealthStatus::Unreachable(format!("{:?}: {}", code, message)),
+    }
+}
+
+// The replica words it `Canister <id> has no update method 'health'`, with the error
+// code in front on newer versions
+fn is_method_not_found(message: &str) -> bool {
+    message.contains("IC0536") || message.contains("has no update method")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_method_not_found;
+
+    #[test]
+    fn only_a_missing_method_shows_a_canister_is_running() {
+        assert!(is_method_not_found(
+            "IC0536: Error from Canister ryjl3-tyaaa-aaaaa-aaaba-cai: Canister has no update method 'health'."
+        ));
+        assert!(is_method_not_found(
+            "Canister ryjl3-tyaaa-aaaaa-aaaba-cai has no update method 'health'"
+        ));
+        assert!(!is_method_not_found(
+            "IC0508: Canister ryjl3-tyaaa-aaaaa-aaaba-cai is stopped and therefore does not have a CallContextManager"
+        ));
+        assert!(!is_method_not_found(
+            "IC0537: Attempted to execute a message, but the canister contains no Wasm module."
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+// Typed table rows as sent over Candid, both for results and for bulk inserts
+
+use candid::{CandidType, Deserialize};
+use common::query::validate_column;
+use common::time::{format_timestamp, parse_date, parse_timestamp};
+use serde_json::{Map, Number, Value as JsonValue};
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    // Nanoseconds since the Unix epoch, like `ic_cdk::api::time()`
+    Timestamp(i64),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Cell {
+    pub column: String,
+    pub value: Value,
+}
+
+pub type Row = Vec<Cell>;
+
+impl Value {
+    fn to_json(&self) -> Result<JsonValue, String> {
+        Ok(match self {
+            Value::Null => JsonValue::Null,
+            Value::Bool(b) => JsonValue::Bool(*b),
+            Value::Int(i) => JsonValue::Number((*i).into()),
+            Value::Float(f) => JsonValue::Number(
+                Number::from_f64(*f).ok_or_else(|| format!("{} can't be stored as JSON", f))?,
+            ),
+            Value::Text(s) => JsonValue::String(s.clone()),
+            Value::Timestamp(nanos) => JsonValue::String(format_timestamp(*nanos)),
+        })
+    }
+
+    // Strings that look like a date-time become timestamps, and `2024-01-15` dates (Postgres
+    // `date` columns) timestamps at midnight UTC. Nested JSON (json/jsonb columns) has no
+    // variant of its own and is kept as its JSON text.
+    fn from_json(value: &JsonValue) -> Self {
+        match value {
+            JsonValue::Null => Value::Null,
+            JsonValue::Bool(b) => Value::Bool(*b),
+            JsonValue::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            JsonValue::String(s) => match parse_timestamp(s).or_else(|| parse_date(s)) {
+                Some(nanos) => Value::Timestamp(nanos),
+                None => Value::Text(s.clone()),
+            },
+            JsonValue::Array(_) | JsonValue::Object(_) => Value::Text(value.to_string()),
+        }
+    }
+}
+
+// Rows of a PostgREST response body. `None` when the body isn't a JSON object or
+// array of objects, e.g. an RPC returning a scalar or an empty `return=minimal` body.
+pub fn rows_from_json(body: &str) -> Option<Vec<Row>> {
+    let object_to_row = |value: &JsonValue| -> Option<Row> {
+        Some(
+            value
+                .as_object()?
+                .iter()
+                .map(|(column, value)| Cell {
+                    column: column.clone(),
+                    value: Value::from_json(value),
+                })
+                .collect(),
+        )
+    };
+
+    match serde_json::from_str::<JsonValue>(body).ok()? {
+        JsonValue::Array(items) => items.iter().map(object_to_row).collect(),
+        object @ JsonValue::Object(_) => Some(vec![object_to_row(&object)?]),
+        _ => None,
+    }
+}
+
+// Column lookups for turning a row into a typed record
+pub struct RowReader<'a> {
+    row: &'a Row,
+}
+
+impl<'a> RowReader<'a> {
+    pub fn new(row: &'a Row) -> Self {
+        Self { row }
+    }
+
+    fn value(&self, column: &str) -> &'a Value {
+        self.row
+            .iter()
+            .find(|cell| cell.column == column)
+            .map(|cell| &cell.value)
+            .unwrap_or(&Value::Null)
+    }
+
+    fn mismatch(column: &str, expected: &str, value: &Value) -> String {
+        format!(
+            "Column '{}' should be {} but is {:?}",
+            column, expected, value
+        )
+    }
+
+    pub fn int(&self, column: &str) -> Result<i64, String> {
+        match self.value(column) {
+            Value::Int(i) => Ok(*i),
+            value => Err(Self::mismatch(column, "an integer", value)),
+        }
+    }
+
+    pub fn opt_int(&self, column: &str) -> Result<Option<i64>, String> {
+        match self.value(column) {
+            Value::Null => Ok(None),
+            _ => self.int(column).map(Some),
+        }
+    }
+
+    pub fn bool(&self, column: &str) -> Result<bool, String> {
+        match self.value(column) {
+            Value::Bool(b) => Ok(*b),
+            value => Err(Self::mismatch(column, "a boolean", value)),
+        }
+    }
+
+    // Text columns that happen to hold a date-time still read as text
+    pub fn text(&self, column: &str) -> Result<String, String> {
+        match self.value(column) {
+            Value::Text(s) => Ok(s.clone()),
+            Value::Timestamp(nanos) => Ok(format_timestamp(*nanos)),
+            value => Err(Self::mismatch(column, "text", value)),
+        }
+    }
+
+    pub fn opt_text(&self, column: &str) -> Result<Option<String>, String> {
+        match self.value(column) {
+            Value::Null => Ok(None),
+            _ => self.text(column).map(Some),
+        }
+    }
+
+    pub fn opt_timestamp(&self, column: &str) -> Result<Option<i64>, String> {
+        match self.value(column) {
+            Value::Null => Ok(None),
+            Value::Timestamp(nanos) => Ok(Some(*nanos)),
+            value => Err(Self::mismatch(column, "a timestamp", value)),
+        }
+    }
+}
+
+// Encodes a row as a JSON object, rejecting invalid or repeated column names
+pub fn row_to_json(row: &Row) -> Result<String, String> {
+    if row.is_empty() {
+        return Err("Row has no columns".to_string());
+    }
+
+    let mut object = Map::new();
+    for cell in row {
+        validate_column(&cell.column)?;
+        let value = cell
+            .value
+            .to_json()
+            .map_err(|e| format!("Column '{}': {}", cell.column, e))?;
+        if object.insert(cell.column.clone(), value).is_some() {
+            return Err(format!("Column '{}' appears twice", cell.column));
+        }
+    }
+    Ok(JsonValue::Object(object).to_string())
+}
+
+// Column names of a row in sorted order, used to check that a batch is uniform
+pub fn columns(row: &Row) -> Vec<&str> {
+    let mut columns: Vec<&str> = row.iter().map(|cell| cell.column.as_str()).collect();
+    columns.sort_unstable();
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::{FromRow, Todo};
+
+    #[test]
+    fn reads_dates_as_timestamps_at_midnight() {
+        let rows = rows_from_json(
+            r#"[{"id":1,"title":"Pay rent","is_done":false,"due_date":"2024-01-15","created_at":"2024-01-10T08:30:00+00:00"}]"#,
+        )
+        .unwrap();
+
+        let due_date = rows[0]
+            .iter()
+            .find(|cell| cell.column == "due_date")
+            .unwrap();
+        assert_eq!(
+            due_date.value,
+            Value::Timestamp(parse_timestamp("2024-01-15T00:00:00Z").unwrap())
+        );
+        let todo = Todo::from_row(&rows[0]).unwrap();
+        assert_eq!(todo.due_date, parse_date("2024-01-15"));
+        assert_eq!(todo.created_at, parse_timestamp("2024-01-10T08:30:00Z"));
+        // Text that only looks a bit like a date stays text
+        assert_eq!(
+            rows_from_json(r#"[{"code":"2024-1-15"}]"#).unwrap()[0][0].value,
+            Value::Text("2024-1-15".to_string())
+        );
+    }
+}
@@ -0,0 +1,66 @@
+// Schema registry: the tables and columns the natural language pipeline may query.
+// Controllers edit it, it is kept in stable memory and sent to llm_service with
+// every parse request. Until a schema is set, the built-in example tables are used.
+
+use crate::storage::{self, Candid, Memory};
+use candid::{CandidType, Deserialize};
+use common::{ApiError, Schema, TableSchema};
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct StoredSchema {
+    schema: Option<Schema>,
+}
+
+thread_local! {
+    static SCHEMA: RefCell<StableCell<Candid<StoredSchema>, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::SCHEMA_MEMORY_ID), Candid::default())
+            .expect("failed to initialize schema registry in stable memory"),
+    );
+}
+
+pub struct SchemaRegistry;
+
+impl SchemaRegistry {
+    pub fn get() -> Schema {
+        SCHEMA
+            .with(|cell| cell.borrow().get().0.schema.clone())
+            .unwrap_or_else(Schema::builtin)
+    }
+
+    pub fn set(schema: Schema) -> Result<(), ApiError> {
+        schema.validate()?;
+        SCHEMA.with(|cell| {
+            cell.borrow_mut()
+                .set(Candid(StoredSchema {
+                    schema: Some(schema),
+                }))
+                .map(|_| ())
+                .map_err(|e| ApiError::storage(format!("Failed to store schema: {:?}", e)))
+        })
+    }
+
+    // Replaces the table with the same name or appends a new one
+    pub fn upsert_table(table: TableSchema) -> Result<(), ApiError> {
+        let mut schema = Self::get();
+        match schema.tables.iter_mut().find(|t| t.name == table.name) {
+            Some(existing) => *existing = table,
+            None => schema.tables.push(table),
+        }
+        Self::set(schema)
+    }
+
+    pub fn remove_table(name: &str) -> Result<(), ApiError> {
+        let mut schema = Self::get();
+        let before = schema.tables.len();
+        schema.tables.retain(|table| table.name != name);
+        if schema.tables.len() == before {
+            return Err(ApiError::invalid_input(format!(
+                "Table '{}' is not in the schema",
+                name
+            )));
+        }
+        Self::set(schema)
+    }
+}
@@ -0,0 +1,305 @@
+// Conversations with `prompt`, kept per caller in stable memory so follow-ups like
+// "now only the completed ones" can refine the query asked before them, and so a
+// conversation can be picked up again later.
+//
+// The session a caller used last is the one `prompt` continues; `resume_session` makes
+// an older one current again and `start_session` begins an empty one. Anonymous callers
+// all share one principal, so their prompts aren't kept.
+//
+// Sessions stay small: prompts are limited in length, stored messages are cut short and
+// a database reply is kept as the summary of its result rather than the rows. Each
+// session has a summary stored next to it, so listing sessions or finding the current
+// one doesn't read every message.
+
+use crate::answer::Answer;
+use crate::row::Value;
+use crate::storage::{self, Candid, Memory};
+use crate::supabase::SupabaseResponse;
+use candid::{CandidType, Deserialize, Principal};
+use common::{ApiError, PostgrestQuery};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+
+// The least recently used sessions of a caller are dropped beyond this
+const MAX_SESSIONS: usize = 20;
+// Oldest messages are dropped beyond this; the last query is kept regardless
+const MAX_MESSAGES: usize = 50;
+// Longer prompts are rejected
+const MAX_PROMPT_CHARS: usize = 2_000;
+// Longer replies are cut short when stored
+const MESSAGE_CHARS: usize = 4_000;
+const TITLE_CHARS: usize = 60;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub id: u64,
+    // The first prompt, shortened
+    pub title: String,
+    // Nanoseconds since the epoch
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub messages: Vec<SessionMessage>,
+    // What the last database question ran as, for follow-ups to refine
+    pub last_query: Option<LastQuery>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SessionMessage {
+    // `user` or `assistant`
+    pub role: String,
+    pub content: String,
+    pub at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LastQuery {
+    pub question: String,
+    pub table: String,
+    pub query: PostgrestQuery,
+    // e.g. `12 rows` or `answer: 7`
+    pub summary: String,
+}
+
+// A session without its messages, as listed by `list_sessions`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SessionSummary {
+    pub id: u64,
+    pub title: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub message_count: u64,
+    pub last_query: Option<LastQuery>,
+}
+
+thread_local! {
+    static SESSIONS: RefCell<StableBTreeMap<(Principal, u64), Candid<Session>, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::SESSIONS_MEMORY_ID)));
+
+    // Kept in step with `SESSIONS` by `save` and `remove`
+    static SUMMARIES: RefCell<StableBTreeMap<(Principal, u64), Candid<SessionSummary>, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(
+            storage::SESSION_SUMMARIES_MEMORY_ID,
+        )));
+}
+
+pub struct Sessions;
+
+impl Sessions {
+    // Newest first
+    pub fn list(owner: Principal) -> Vec<SessionSummary> {
+        let mut sessions = Self::summaries(owner);
+        sessions.sort_by_key(|session| Reverse(session.updated_at));
+        sessions
+    }
+
+    pub fn start(owner: Principal) -> Result<SessionSummary, ApiError> {
+        ensure_signed_in(owner)?;
+        Ok(Self::create(owner, "New conversation").summary())
+    }
+
+    // Makes the session current again and returns its history
+    pub fn resume(owner: Principal, id: u64) -> Result<Session, ApiError> {
+        ensure_signed_in(owner)?;
+        let mut session = Self::get(owner, id)?;
+        session.updated_at = ic_cdk::api::time();
+        Self::save(owner, session.clone());
+        Ok(session)
+    }
+
+    pub fn delete(owner: Principal, id: u64) -> Result<(), ApiError> {
+        ensure_signed_in(owner)?;
+        Self::remove(owner, id).ok_or_else(|| not_found(id))
+    }
+
+    // The session `prompt` continues, with `message` added, or a new one starting with
+    // it. `None` for anonymous callers.
+    pub fn open(owner: Principal, message: &str) -> Option<Session> {
+        if owner == Principal::anonymous() {
+            return None;
+        }
+        let mut session = Self::summaries(owner)
+            .into_iter()
+            .max_by_key(|session| session.updated_at)
+            .and_then(|current| Self::get(owner, current.id).ok())
+            .unwrap_or_else(|| Self::create(owner, message));
+        if session.messages.is_empty() {
+            session.title = title(message);
+        }
+        session.push("user", message);
+        Self::save(owner, session.clone());
+        Some(session)
+    }
+
+    // Adds the reply to a session from `open`, and the query it ran if there was one;
+    // the reply to a query is kept as the summary of its result. The session may have
+    // been deleted while the reply was being made; it stays deleted.
+    pub fn record(owner: Principal, id: u64, reply: &str, query: Option<LastQuery>) {
+        let Ok(mut session) = Self::get(owner, id) else {
+            return;
+        };
+        match query {
+            Some(query) => {
+                session.push(
+                    "assistant",
+                    &format!("Ran a query on {}: {}", query.table, query.summary),
+                );
+                session.last_query = Some(query);
+            }
+            None => session.push("assistant", reply),
+        }
+        Self::save(owner, session);
+    }
+
+    fn create(owner: Principal, first_message: &str) -> Session {
+        let now = ic_cdk::api::time();
+        let id = storage::update_state(|state| {
+            let id = state.last_session_id.unwrap_or_default() + 1;
+            state.last_session_id = Some(id);
+            id
+        });
+
+        let mut owned = Self::summaries(owner);
+        owned.sort_by_key(|session| session.updated_at);
+        let excess = (owned.len() + 1).saturating_sub(MAX_SESSIONS);
+        for session in owned.iter().take(excess) {
+            Self::remove(owner, session.id);
+        }
+
+        let session = Session {
+            id,
+            title: title(first_message),
+            created_at: now,
+            updated_at: now,
+            messages: Vec::new(),
+            last_query: None,
+        };
+        Self::save(owner, session.clone());
+        session
+    }
+
+    fn get(owner: Principal, id: u64) -> Result<Session, ApiError> {
+        SESSIONS
+            .with(|sessions| sessions.borrow().get(&(owner, id)))
+            .map(|session| session.0)
+            .ok_or_else(|| not_found(id))
+    }
+
+    fn summaries(owner: Principal) -> Vec<SessionSummary> {
+        SUMMARIES.with(|summaries| {
+            summaries
+                .borrow()
+                .range((owner, 0)..=(owner, u64::MAX))
+                .map(|(_, summary)| summary.0)
+                .collect()
+        })
+    }
+
+    fn save(owner: Principal, session: Session) {
+        let summary = session.summary();
+        SUMMARIES.with(|summaries| {
+            summaries
+                .borrow_mut()
+                .insert((owner, session.id), Candid(summary))
+        });
+        SESSIONS.with(|sessions| {
+            sessions
+                .borrow_mut()
+                .insert((owner, session.id), Candid(session))
+        });
+    }
+
+    // The removed session, if there was one
+    fn remove(owner: Principal, id: u64) -> Option<()> {
+        SUMMARIES.with(|summaries| summaries.borrow_mut().remove(&(owner, id)));
+        SESSIONS
+            .with(|sessions| sessions.borrow_mut().remove(&(owner, id)))
+            .map(|_| ())
+    }
+}
+
+impl Session {
+    fn push(&mut self, role: &str, content: &str) {
+        let at = ic_cdk::api::time();
+        self.messages.push(SessionMessage {
+            role: role.to_string(),
+            content: shorten(content, MESSAGE_CHARS),
+            at,
+        });
+        let excess = self.messages.len().saturating_sub(MAX_MESSAGES);
+        self.messages.drain(..excess);
+        self.updated_at = at;
+    }
+
+    fn summary(&self) -> SessionSummary {
+        SessionSummary {
+            id: self.id,
+            title: self.title.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            message_count: self.messages.len() as u64,
+            last_query: self.last_query.clone(),
+        }
+    }
+}
+
+// What a response held, short enough to show in a session list
+pub fn summarize(response: &SupabaseResponse) -> String {
+    match &response.answer {
+        Some(Answer::Scalar(value)) => format!("answer: {}", value_text(value)),
+        Some(Answer::Groups(groups)) => format!("{} groups", groups.len()),
+        None => match &response.rows {
+            Some(rows) if rows.len() == 1 => "1 row".to_string(),
+            Some(rows) => format!("{} rows", rows.len()),
+            None => "no rows".to_string(),
+        },
+    }
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(value) => value.to_string(),
+        Value::Int(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Text(value) => value.clone(),
+        Value::Timestamp(value) => value.to_string(),
+    }
+}
+
+// Prompts too long to keep in a session are turned away before anything is stored
+pub fn check_prompt(prompt: &str) -> Result<(), ApiError> {
+    let chars = prompt.chars().count();
+    if chars > MAX_PROMPT_CHARS {
+        return Err(ApiError::invalid_input(format!(
+            "Prompt is {} characters long, the most is {}",
+            chars, MAX_PROMPT_CHARS
+        )));
+    }
+    Ok(())
+}
+
+fn title(message: &str) -> String {
+    shorten(message.trim(), TITLE_CHARS)
+}
+
+// The first `chars` characters of the text, marked with `…` when cut
+fn shorten(text: &str, chars: usize) -> String {
+    match text.char_indices().nth(chars) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+fn ensure_signed_in(owner: Principal) -> Result<(), ApiError> {
+    if owner == Principal::anonymous() {
+        return Err(ApiError::unauthorized(
+            "Sessions are kept per caller, sign in to keep conversations",
+        ));
+    }
+    Ok(())
+}
+
+fn not_found(id: u64) -> ApiError {
+    ApiError::invalid_input(format!("No session {} for this caller", id))
+}
@@ -0,0 +1,115 @@
+// Stable memory layout for state that has to survive canister upgrades
+//
+// Every stable structure lives in its own virtual memory. The layout version is
+// stored alongside the data and `migrate` brings older layouts up to date in
+// `post_upgrade`, before any other code reads stable memory.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableCell};
+use std::cell::RefCell;
+
+pub use common::stable::Candid;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Never reuse or renumber an id
+pub const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const REGISTRY_MEMORY_ID: MemoryId = MemoryId::new(1);
+const VERSION_MEMORY_ID: MemoryId = MemoryId::new(2);
+const STATE_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const SCHEMA_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const SESSIONS_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const SESSION_SUMMARIES_MEMORY_ID: MemoryId = MemoryId::new(6);
+
+// Bump this and add a step to `migrate` whenever stored data changes in a way
+// Candid can't decode transparently (adding an `Option` field doesn't need it)
+pub const STORAGE_VERSION: u32 = 1;
+
+// Small values that don't deserve their own stable structure
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct State {
+    pub counter: u64,
+    // Id of the last mutation plan handed out
+    pub last_plan_id: Option<u64>,
+    // Id of the last conversation session started
+    pub last_session_id: Option<u64>,
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    // Layouts written before versioning was introduced read as version 0
+    static VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(memory(VERSION_MEMORY_ID), 0)
+            .expect("failed to initialize storage version in stable memory"),
+    );
+
+    static STATE: RefCell<StableCell<Candid<State>, Memory>> = RefCell::new(
+        StableCell::init(memory(STATE_MEMORY_ID), Candid::default())
+            .expect("failed to initialize state in stable memory"),
+    );
+}
+
+pub fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(id))
+}
+
+pub fn version() -> u32 {
+    VERSION.with(|cell| *cell.borrow().get())
+}
+
+fn set_version(version: u32) -> Result<(), String> {
+    VERSION.with(|cell| {
+        cell.borrow_mut()
+            .set(version)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store storage version: {:?}", e))
+    })
+}
+
+// Called from `init`: a fresh canister starts at the current layout
+pub fn init() -> Result<(), String> {
+    set_version(STORAGE_VERSION)
+}
+
+// Called from `post_upgrade`: runs every migration step between the stored and current version
+pub fn migrate() -> Result<(), String> {
+    let mut version = version();
+    if version > STORAGE_VERSION {
+        return Err(format!(
+            "Stable memory has storage version {} but this build only supports up to {}",
+            version, STORAGE_VERSION
+        ));
+    }
+
+    while version < STORAGE_VERSION {
+        match version {
+            // Version 0 kept config and registry in their own cells, which are unchanged,
+            // and the counter on the heap, where it was lost on upgrade. State starts empty.
+            0 => {}
+            _ => unreachable!("missing migration from storage version {}", version),
+        }
+        version += 1;
+        set_version(version)?;
+        ic_cdk::println!("Migrated stable memory to storage version {}", version);
+    }
+
+    Ok(())
+}
+
+pub fn read_state<R>(f: impl FnOnce(&State) -> R) -> R {
+    STATE.with(|cell| f(&cell.borrow().get().0))
+}
+
+pub fn update_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().0.clone();
+        let result = f(&mut state);
+        cell.set(Candid(state))
+            .expect("failed to write state to stable memory");
+        result
+    })
+}
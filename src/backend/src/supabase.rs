@@ -0,0 +1,757 @@
+// Supabase REST (PostgREST) client used by every endpoint that talks to the database
+// Header construction, outcall setup, cycles and response handling live here so
+// changes to any of them land in one place.
+
+use crate::answer::{self, Answer};
+use crate::config::Config;
+use crate::plans::MutationPlan;
+use crate::row::{self, rows_from_json, Row};
+use candid::{CandidType, Deserialize};
+use common::query::validate_column;
+use common::{ApiError, PostgrestQuery};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, TransformContext,
+};
+
+// Cycles attached to each outcall
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+const MAX_RESPONSE_BYTES: u64 = 8192;
+// Pages are bounded by their row count rather than this limit, so give them room
+const PAGE_RESPONSE_BYTES: u64 = 512 * 1024;
+// The OpenAPI document describes every table, so allow the outcall maximum
+const OPENAPI_RESPONSE_BYTES: u64 = 2_000_000;
+const DEFAULT_PAGE_SIZE: u64 = 100;
+const MAX_PAGE_SIZE: u64 = 1000;
+// Outcall requests are capped at 2 MB; the rest is left for the URL and headers
+const MAX_REQUEST_BODY_BYTES: usize = 1_900_000;
+
+#[derive(CandidType, Deserialize)]
+pub struct SupabaseResponse {
+    // Raw response body
+    pub data: String,
+    // `data` decoded into typed rows when it holds JSON objects
+    pub rows: Option<Vec<Row>>,
+    // The value or groups an aggregate query asked for
+    pub answer: Option<Answer>,
+    // For a change asked for in natural language: the plan to confirm, with the rows
+    // it would affect in `rows`
+    pub plan: Option<MutationPlan>,
+}
+
+impl SupabaseResponse {
+    pub fn new(body: String) -> Self {
+        Self {
+            rows: rows_from_json(&body),
+            data: body,
+            answer: None,
+            plan: None,
+        }
+    }
+
+    // Sets `answer` when `query` has aggregates
+    pub fn with_answer(mut self, query: &PostgrestQuery) -> Self {
+        self.answer = answer::answer(query, self.rows.as_deref().unwrap_or_default());
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Patch,
+    Delete,
+    Head,
+}
+
+// A response that reached Supabase, whatever its status
+pub struct RawResponse {
+    pub status: u16,
+    pub headers: Vec<HttpHeader>,
+    pub body: String,
+}
+
+impl RawResponse {
+    // Turns non-2xx statuses into an `Upstream` error carrying the status and body
+    pub fn ensure_success(self) -> Result<Self, ApiError> {
+        if (200..300).contains(&self.status) {
+            Ok(self)
+        } else {
+            Err(ApiError::upstream(self.status, self.body))
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+            Method::Head => "HEAD",
+        }
+    }
+}
+
+// PATCH and DELETE without a filter hit every row of the table, so mutations must
+// carry at least one `column=operator.value` filter
+pub fn ensure_row_filter(filter: &str) -> Result<(), ApiError> {
+    let query: PostgrestQuery = filter
+        .parse()
+        .map_err(|e| ApiError::invalid_input(format!("Invalid filter '{}': {}", filter, e)))?;
+    if query.filters.is_empty() && query.groups.is_empty() {
+        return Err(ApiError::invalid_input(format!(
+            "Filter '{}' doesn't restrict any column, refusing to change the whole table",
+            filter
+        )));
+    }
+    Ok(())
+}
+
+// Query string for `on_conflict`, empty when PostgREST should use the primary key
+pub fn on_conflict_query(columns: &[String]) -> Result<String, ApiError> {
+    if columns.is_empty() {
+        return Ok(String::new());
+    }
+    for column in columns {
+        validate_column(column).map_err(ApiError::invalid_input)?;
+    }
+    Ok(format!("on_conflict={}", columns.join(",")))
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct BulkInsertOptions {
+    // Upsert on these columns instead of inserting; an empty list means the primary key
+    pub on_conflict: Option<Vec<String>>,
+    pub max_rows_per_batch: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct BatchResult {
+    // Index of the first row of the batch in the request
+    pub first_row: u64,
+    pub row_count: u64,
+    pub error: Option<ApiError>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct BulkInsertResult {
+    pub batches: Vec<BatchResult>,
+    pub inserted_rows: u64,
+    pub failed_rows: u64,
+}
+
+struct Batch {
+    first_row: usize,
+    rows: Vec<String>,
+}
+
+impl Batch {
+    fn body(&self) -> String {
+        format!("[{}]", self.rows.join(","))
+    }
+}
+
+// Groups JSON rows into batches whose array body stays within `max_bytes`. A row that
+// doesn't fit on its own still gets a batch, which is reported as failed without sending.
+fn split_into_batches(rows: Vec<String>, max_bytes: usize, max_rows: usize) -> Vec<Batch> {
+    let mut batches: Vec<Batch> = Vec::new();
+    let mut size = 0;
+
+    for (index, row) in rows.into_iter().enumerate() {
+        // Each row adds its own length plus a comma, the brackets add 2
+        let fits = batches
+            .last()
+            .is_some_and(|batch| batch.rows.len() < max_rows && size + row.len() < max_bytes);
+        if fits {
+            size += row.len() + 1;
+            batches.last_mut().unwrap().rows.push(row);
+        } else {
+            size = row.len() + 2;
+            batches.push(Batch {
+                first_row: index,
+                rows: vec![row],
+            });
+        }
+    }
+    batches
+}
+
+// Inserts (or upserts) typed rows in as few requests as the outcall size limit allows.
+// Batches are sent one after another and a failed batch doesn't stop the rest.
+pub async fn bulk_insert(
+    table: &str,
+    rows: &[Row],
+    options: BulkInsertOptions,
+) -> Result<BulkInsertResult, ApiError> {
+    let Some(first) = rows.first() else {
+        return Err(ApiError::invalid_input("No rows to insert"));
+    };
+    let expected_columns = row::columns(first);
+    let mut encoded = Vec::with_capacity(rows.len());
+    for (index, row) in rows.iter().enumerate() {
+        // PostgREST requires every object of a bulk insert to have the same keys
+        if row::columns(row) != expected_columns {
+            return Err(ApiError::invalid_input(format!(
+                "Row {} has columns {:?} but row 0 has {:?}",
+                index,
+                row::columns(row),
+                expected_columns
+            )));
+        }
+        encoded.push(
+            row::row_to_json(row)
+                .map_err(|e| ApiError::invalid_input(format!("Row {}: {}", index, e)))?,
+        );
+    }
+
+    let query = match &options.on_conflict {
+        Some(columns) => on_conflict_query(columns)?,
+        None => String::new(),
+    };
+    let max_rows = match options.max_rows_per_batch {
+        Some(0) => {
+            return Err(ApiError::invalid_input(
+                "max_rows_per_batch must be at least 1",
+            ))
+        }
+        Some(max) => max as usize,
+        None => usize::MAX,
+    };
+
+    let client = SupabaseClient::from_config()?;
+    let mut result = BulkInsertResult {
+        batches: Vec::new(),
+        inserted_rows: 0,
+        failed_rows: 0,
+    };
+
+    for batch in split_into_batches(encoded, MAX_REQUEST_BODY_BYTES, max_rows) {
+        let body = batch.body();
+        let error = if body.len() > MAX_REQUEST_BODY_BYTES {
+            Some(ApiError::invalid_input(format!(
+                "Row {} is {} bytes, over the {} byte request limit",
+                batch.first_row,
+                body.len(),
+                MAX_REQUEST_BODY_BYTES
+            )))
+        } else {
+            // Returning the rows could overflow the response limit, so ask for no body
+            let mut request = client
+                .from(table)
+                .method(Method::Post)
+                .query(&query)
+                .prefer("return=minimal");
+            if options.on_conflict.is_some() {
+                request = request.prefer("resolution=merge-duplicates");
+            }
+            request.json_body(body).send().await.err()
+        };
+
+        let row_count = batch.rows.len() as u64;
+        if error.is_some() {
+            result.failed_rows += row_count;
+        } else {
+            result.inserted_rows += row_count;
+        }
+        result.batches.push(BatchResult {
+            first_row: batch.first_row as u64,
+            row_count,
+            error,
+        });
+    }
+
+    Ok(result)
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct PageOptions {
+    // Row to start from, 0 by default; pass the previous page's `next_offset` to continue
+    pub offset: Option<u64>,
+    pub page_size: Option<u64>,
+    // Keep fetching pages until this many rows were read or the table is exhausted
+    pub row_budget: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Page {
+    // JSON array with the rows of every fetched page
+    pub data: String,
+    pub rows: Vec<Row>,
+    pub row_count: u64,
+    pub offset: u64,
+    // Where the next page starts, `None` once every row was read
+    pub next_offset: Option<u64>,
+    // Total rows matching the query, from `Content-Range`
+    pub total: Option<u64>,
+}
+
+// Total from a `Content-Range` value such as `0-24/3573` or `*/0`; `*` means unknown
+fn parse_content_range_total(content_range: &str) -> Option<u64> {
+    content_range.split_once('/')?.1.trim().parse().ok()
+}
+
+// Reads `query` page by page with `Range` headers, asking PostgREST for the exact count.
+// Limit and offset come from `options`, so the query itself must not set them.
+pub async fn fetch_pages(table: &str, query: &str, options: PageOptions) -> Result<Page, ApiError> {
+    let parsed: PostgrestQuery = query.parse().map_err(ApiError::invalid_input)?;
+    if parsed.limit.is_some() || parsed.offset.is_some() {
+        return Err(ApiError::invalid_input(
+            "Paginated queries take offset and page size from the options, remove limit/offset",
+        ));
+    }
+
+    let start = options.offset.unwrap_or(0);
+    let page_size = options
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let client = SupabaseClient::from_config()?;
+
+    let mut rows: Vec<serde_json::Value> = Vec::new();
+    let mut offset = start;
+    let mut total = None;
+    let mut last_page_full = false;
+
+    loop {
+        let wanted = match options.row_budget {
+            Some(budget) => page_size.min(budget.saturating_sub(rows.len() as u64)),
+            None => page_size,
+        };
+        if wanted == 0 {
+            break;
+        }
+
+        let response = client
+            .from(table)
+            .query(query)
+            .header("Range-Unit", "items")
+            .header("Range", &range(offset, wanted)?)
+            .prefer("count=exact")
+            .max_response_bytes(PAGE_RESPONSE_BYTES)
+            .send_raw()
+            .await?;
+
+        total = response
+            .header("content-range")
+            .and_then(parse_content_range_total);
+        // 416 means the offset is past the last row
+        if response.status == 416 {
+            last_page_full = false;
+            break;
+        }
+        let response = response.ensure_success()?;
+
+        let page: Vec<serde_json::Value> = serde_json::from_str(&response.body)
+            .map_err(|e| ApiError::parse(format!("Page is not a JSON array: {}", e)))?;
+        let fetched = page.len() as u64;
+        rows.extend(page);
+        offset += fetched;
+        last_page_full = fetched == wanted;
+
+        let exhausted = !last_page_full || total.is_some_and(|total| offset >= total);
+        if exhausted || options.row_budget.is_none() {
+            break;
+        }
+    }
+
+    let next_offset = match total {
+        Some(total) => (offset < total).then_some(offset),
+        None => last_page_full.then_some(offset),
+    };
+
+    let row_count = rows.len() as u64;
+    let data = serde_json::Value::Array(rows).to_string();
+    Ok(Page {
+        rows: rows_from_json(&data).unwrap_or_default(),
+        data,
+        row_count,
+        offset: start,
+        next_offset,
+        total,
+    })
+}
+
+// Counts the rows matching the query's filters with a `HEAD` request and
+// `Prefer: count=exact`, which works whether or not aggregates are enabled
+pub async fn count_rows(table: &str, query: &PostgrestQuery) -> Result<SupabaseResponse, ApiError> {
+    let filters = PostgrestQuery {
+        aggregates: Vec::new(),
+        ..query.clone()
+    };
+    let response = SupabaseClient::from_config()?
+        .from(table)
+        .method(Method::Head)
+        .postgrest(&filters)
+        .prefer("count=exact")
+        .send_raw()
+        .await?
+        .ensure_success()?;
+    let count = response
+        .header("content-range")
+        .and_then(parse_content_range_total)
+        .ok_or_else(|| ApiError::parse("Count response has no total in Content-Range"))?;
+
+    let rows = vec![answer::count_row(count)];
+    Ok(SupabaseResponse {
+        data: format!("[{{\"count\":{}}}]", count),
+        rows: Some(rows),
+        answer: None,
+        plan: None,
+    }
+    .with_answer(query))
+}
+
+// The first `limit` rows `query` matches and how many it matches in all, for previews
+pub async fn preview_rows(
+    table: &str,
+    query: &PostgrestQuery,
+    limit: u64,
+) -> Result<(SupabaseResponse, u64), ApiError> {
+    let response = SupabaseClient::from_config()?
+        .from(table)
+        .postgrest(&query.clone().limit(limit))
+        .prefer("count=exact")
+        .send_raw()
+        .await?
+        .ensure_success()?;
+    let total = response
+        .header("content-range")
+        .and_then(parse_content_range_total)
+        .ok_or_else(|| ApiError::parse("Preview response has no total in Content-Range"))?;
+    Ok((SupabaseResponse::new(response.body), total))
+}
+
+// `Range` header value for `count` rows from `offset`, both ends included; `count` is at
+// least 1
+fn range(offset: u64, count: u64) -> Result<String, ApiError> {
+    let last = offset
+        .checked_add(count - 1)
+        .ok_or_else(|| ApiError::invalid_input(format!("Offset {} is out of range", offset)))?;
+    Ok(format!("{}-{}", offset, last))
+}
+
+// Percent-encodes whatever can't appear in a URL query as is (spaces, `#`, quotes,
+// non-ASCII), keeping the separators and any `%XX` escapes the caller already wrote
+fn sanitize_query(raw: &str) -> String {
+    let bytes = raw.trim().trim_start_matches('?').as_bytes();
+    let mut sanitized = String::with_capacity(bytes.len());
+    for (i, &byte) in bytes.iter().enumerate() {
+        let escaped = byte == b'%'
+            && bytes.len() > i + 2
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit();
+        if escaped || byte.is_ascii_alphanumeric() || b"-._~!$'()*+,;=:@/?&".contains(&byte) {
+            sanitized.push(byte as char);
+        } else {
+            sanitized.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    sanitized
+}
+
+pub struct SupabaseClient {
+    base_url: String,
+    anon_key: String,
+    // Where PATCH and DELETE go, see `Config::mutation_gateway_url`
+    mutation_gateway_url: Option<String>,
+}
+
+pub struct RequestBuilder<'a> {
+    client: &'a SupabaseClient,
+    method: Method,
+    path: String,
+    query: String,
+    prefer: Vec<String>,
+    headers: Vec<HttpHeader>,
+    max_response_bytes: u64,
+    body: Option<Vec<u8>>,
+}
+
+impl SupabaseClient {
+    pub fn from_config() -> Result<Self, ApiError> {
+        Ok(Self {
+            base_url: Config::supabase_url()?,
+            anon_key: Config::supabase_anon_key()?,
+            mutation_gateway_url: Config::mutation_gateway_url(),
+        })
+    }
+
+    // Fails like a PATCH or DELETE would, so callers can stop before consuming anything
+    pub fn ensure_mutations(&self) -> Result<(), ApiError> {
+        transport(
+            Method::Patch,
+            &self.base_url,
+            self.mutation_gateway_url.as_deref(),
+        )
+        .map(|_| ())
+    }
+
+    // Request against `/rest/v1/{table}`, GET unless changed with `method`
+    pub fn from(&self, table: &str) -> RequestBuilder<'_> {
+        self.request(
+            Method::Get,
+            format!("/rest/v1/{}", urlencoding::encode(table)),
+        )
+    }
+
+    // POST to `/rest/v1/rpc/{function}`, the body holds the named arguments
+    pub fn rpc(&self, function: &str) -> RequestBuilder<'_> {
+        self.request(Method::Post, format!("/rest/v1/rpc/{}", function))
+    }
+
+    // GET `/rest/v1/`, where PostgREST serves the OpenAPI description of the database
+    pub fn openapi(&self) -> RequestBuilder<'_> {
+        self.request(Method::Get, "/rest/v1/".to_string())
+            .max_response_bytes(OPENAPI_RESPONSE_BYTES)
+    }
+
+    fn request(&self, method: Method, path: String) -> RequestBuilder<'_> {
+        RequestBuilder {
+            client: self,
+            method,
+            path,
+            query: String::new(),
+            prefer: Vec::new(),
+            headers: Vec::new(),
+            max_response_bytes: MAX_RESPONSE_BYTES,
+            body: None,
+        }
+    }
+}
+
+impl RequestBuilder<'_> {
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    // Raw PostgREST query string, e.g. `select=*&is_done=eq.true`. Its `&` and `=` are
+    // taken as written, so prefer `postgrest` when values come from user input.
+    pub fn query(mut self, query: &str) -> Self {
+        self.query = sanitize_query(query);
+        self
+    }
+
+    // Typed query with every filter value percent-encoded
+    pub fn postgrest(mut self, query: &PostgrestQuery) -> Self {
+        self.query = query.to_url_query();
+        self
+    }
+
+    // Adds a `Prefer` option such as `return=representation`; several are joined with commas
+    pub fn prefer(mut self, preference: &str) -> Self {
+        self.prefer.push(preference.to_string());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push(HttpHeader {
+            name: name.to_string(),
+            value: value.to_string(),
+        });
+        self
+    }
+
+    pub fn max_response_bytes(mut self, max_response_bytes: u64) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    pub fn json_body(mut self, body: String) -> Self {
+        self.body = Some(body.into_bytes());
+        self
+    }
+
+    fn url(&self, base_url: &str) -> String {
+        if self.query.is_empty() {
+            format!("{}{}", base_url, self.path)
+        } else {
+            format!("{}{}?{}", base_url, self.path, self.query)
+        }
+    }
+
+    // Sends the request and returns the body of a 2xx response; everything else is an error
+    pub async fn send(self) -> Result<SupabaseResponse, ApiError> {
+        let response = self.send_raw().await?.ensure_success()?;
+        Ok(SupabaseResponse::new(response.body))
+    }
+
+    // Sends the request whatever status comes back; only a failed outcall is an error
+    pub async fn send_raw(self) -> Result<RawResponse, ApiError> {
+        let (method, base_url) = transport(
+            self.method,
+            &self.client.base_url,
+            self.client.mutation_gateway_url.as_deref(),
+        )?;
+        let url = self.url(base_url);
+        let mut headers = vec![
+            HttpHeader {
+                name: "apikey".to_string(),
+                value: self.client.anon_key.clone(),
+            },
+            HttpHeader {
+                name: "Authorization".to_string(),
+                value: format!("Bearer {}", self.client.anon_key),
+            },
+            HttpHeader {
+                name: "Content-Type".to_string(),
+                value: "application/json".to_string(),
+            },
+            HttpHeader {
+                name: "Accept".to_string(),
+                value: "application/json".to_string(),
+            },
+        ];
+        if !self.prefer.is_empty() {
+            headers.push(HttpHeader {
+                name: "Prefer".to_string(),
+                value: self.prefer.join(","),
+            });
+        }
+        headers.extend(self.headers);
+
+        if matches!(self.method, Method::Patch | Method::Delete) {
+            headers.push(HttpHeader {
+                name: "X-HTTP-Method-Override".to_string(),
+                value: self.method.as_str().to_string(),
+            });
+        }
+
+        ic_cdk::println!("Supabase request: {} {}", self.method.as_str(), url);
+
+        let request = CanisterHttpRequestArgument {
+            url,
+            method,
+            body: self.body,
+            max_response_bytes: Some(self.max_response_bytes),
+            transform: Some(TransformContext::from_name(
+                "transform".to_string(),
+                serde_json::to_vec(&()).unwrap(),
+            )),
+            headers,
+        };
+
+        match http_request(request, OUTCALL_CYCLES).await {
+            Ok((response,)) => {
+                let body = String::from_utf8(response.body)
+                    .map_err(|_| ApiError::parse("Failed to parse response body as UTF-8"))?;
+                let status: u16 = response.status.0.to_string().parse().unwrap_or(500);
+                ic_cdk::println!("Response status: {}, body: {}", status, body);
+
+                Ok(RawResponse {
+                    status,
+                    headers: response.headers,
+                    body,
+                })
+            }
+            Err((code, message)) => Err(ApiError::outcall(format!(
+                "HTTP request failed with code {:?}: {}",
+                code, message
+            ))),
+        }
+    }
+}
+
+// HTTPS outcalls only support GET, HEAD and POST. PATCH and DELETE go out as POST with
+// `X-HTTP-Method-Override` to the mutation gateway, never to Supabase itself: PostgREST
+// ignores the header and would run them as an insert or an empty POST.
+fn transport<'a>(
+    method: Method,
+    base_url: &'a str,
+    mutation_gateway_url: Option<&'a str>,
+) -> Result<(HttpMethod, &'a str), ApiError> {
+    match method {
+        Method::Get => Ok((HttpMethod::GET, base_url)),
+        Method::Post => Ok((HttpMethod::POST, base_url)),
+        Method::Head => Ok((HttpMethod::HEAD, base_url)),
+        Method::Patch | Method::Delete => match mutation_gateway_url {
+            Some(gateway) => Ok((HttpMethod::POST, gateway)),
+            None => Err(ApiError::config(
+                "Updates and deletes need a gateway that honours X-HTTP-Method-Override, call set_mutation_gateway_url",
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{range, sanitize_query, transport, Method};
+    use common::ErrorCode;
+    use ic_cdk::api::management_canister::http_request::HttpMethod;
+
+    const SUPABASE: &str = "https://project.supabase.co";
+    const GATEWAY: &str = "https://gateway.example.com";
+
+    #[test]
+    fn reads_go_straight_to_supabase() {
+        for (method, expected) in [
+            (Method::Get, HttpMethod::GET),
+            (Method::Post, HttpMethod::POST),
+            (Method::Head, HttpMethod::HEAD),
+        ] {
+            let (outcall, base_url) = transport(method, SUPABASE, Some(GATEWAY)).unwrap();
+            assert_eq!((outcall, base_url), (expected, SUPABASE));
+        }
+    }
+
+    #[test]
+    fn mutations_go_through_the_gateway() {
+        for method in [Method::Patch, Method::Delete] {
+            let (outcall, base_url) = transport(method, SUPABASE, Some(GATEWAY)).unwrap();
+            assert_eq!((outcall, base_url), (HttpMethod::POST, GATEWAY));
+        }
+    }
+
+    #[test]
+    fn mutations_without_a_gateway_are_refused() {
+        for method in [Method::Patch, Method::Delete] {
+            let error = transport(method, SUPABASE, None).unwrap_err();
+            assert_eq!(error.code, ErrorCode::Config);
+            assert!(error.message.contains("set_mutation_gateway_url"));
+        }
+    }
+
+    #[test]
+    fn ranges_end_within_u64() {
+        assert_eq!(range(0, 20).unwrap(), "0-19");
+        assert_eq!(
+            range(u64::MAX - 9, 10).unwrap(),
+            format!("{}-{}", u64::MAX - 9, u64::MAX)
+        );
+        let error = range(u64::MAX - 9, 11).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidInput);
+    }
+
+    #[test]
+    fn sanitize_keeps_valid_queries_unchanged() {
+        let query = "select=id,title&is_done=eq.true&title=ilike.*dog*&order=id.desc";
+        assert_eq!(sanitize_query(query), query);
+    }
+
+    #[test]
+    fn sanitize_encodes_characters_that_break_urls() {
+        assert_eq!(
+            sanitize_query("title=eq.buy milk #2"),
+            "title=eq.buy%20milk%20%232"
+        );
+        assert_eq!(
+            sanitize_query("title=eq.\"zażółć\""),
+            "title=eq.%22za%C5%BC%C3%B3%C5%82%C4%87%22"
+        );
+    }
+
+    #[test]
+    fn sanitize_keeps_existing_escapes() {
+        assert_eq!(sanitize_query("?title=eq.a%20b"), "title=eq.a%20b");
+        assert_eq!(sanitize_query("title=eq.100%"), "title=eq.100%25");
+    }
+}
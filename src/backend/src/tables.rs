@@ -0,0 +1,98 @@
+// Typed records for the tables the app knows about. Columns follow the schema in the
+// README; nullable columns are optional and timestamps are nanoseconds since the epoch.
+
+use crate::row::{Row, RowReader};
+use candid::{CandidType, Deserialize};
+
+pub trait FromRow: Sized {
+    // Table the record is read from
+    const TABLE: &'static str;
+
+    fn from_row(row: &Row) -> Result<Self, String>;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Todo {
+    pub id: i64,
+    pub title: String,
+    pub description: Option<String>,
+    pub is_done: bool,
+    pub due_date: Option<i64>,
+    pub status: Option<String>,
+    pub created_at: Option<i64>,
+    pub user_id: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub email: Option<String>,
+    pub created_at: Option<i64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Post {
+    pub id: i64,
+    pub title: String,
+    pub content: Option<String>,
+    pub user_id: Option<i64>,
+    pub created_at: Option<i64>,
+}
+
+impl FromRow for Todo {
+    const TABLE: &'static str = "todos";
+
+    fn from_row(row: &Row) -> Result<Self, String> {
+        let row = RowReader::new(row);
+        Ok(Todo {
+            id: row.int("id")?,
+            title: row.text("title")?,
+            description: row.opt_text("description")?,
+            is_done: row.bool("is_done")?,
+            due_date: row.opt_timestamp("due_date")?,
+            status: row.opt_text("status")?,
+            created_at: row.opt_timestamp("created_at")?,
+            user_id: row.opt_text("user_id")?,
+        })
+    }
+}
+
+impl FromRow for User {
+    const TABLE: &'static str = "users";
+
+    fn from_row(row: &Row) -> Result<Self, String> {
+        let row = RowReader::new(row);
+        Ok(User {
+            id: row.int("id")?,
+            name: row.text("name")?,
+            email: row.opt_text("email")?,
+            created_at: row.opt_timestamp("created_at")?,
+        })
+    }
+}
+
+impl FromRow for Post {
+    const TABLE: &'static str = "posts";
+
+    fn from_row(row: &Row) -> Result<Self, String> {
+        let row = RowReader::new(row);
+        Ok(Post {
+            id: row.int("id")?,
+            title: row.text("title")?,
+            content: row.opt_text("content")?,
+            user_id: row.opt_int("user_id")?,
+            created_at: row.opt_timestamp("created_at")?,
+        })
+    }
+}
+
+// Reads every row of a response into records, naming the first row that doesn't fit
+pub fn records<T: FromRow>(rows: &[Row]) -> Result<Vec<T>, String> {
+    rows.iter()
+        .enumerate()
+        .map(|(index, row)| {
+            T::from_row(row).map_err(|e| format!("{} row {}: {}", T::TABLE, index, e))
+        })
+        .collect()
+}
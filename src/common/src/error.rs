@@ -0,0 +1,99 @@
+// Error type returned by every fallible endpoint of the backend and llm_service canisters
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    // Required configuration (URL, API key, canister id) is missing or invalid
+    Config,
+    // The caller isn't allowed to call the method
+    Unauthorized,
+    // An argument was rejected before anything was sent
+    InvalidInput,
+    // The HTTPS outcall didn't reach the upstream service
+    Outcall,
+    // The upstream service answered with a non-2xx status
+    Upstream,
+    // A call to another canister failed
+    CanisterCall,
+    // A natural language question or an upstream body couldn't be parsed
+    Parse,
+    // Writing to stable memory failed
+    Storage,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    // Whether sending the same request again may succeed
+    pub retryable: bool,
+    // HTTP status of the upstream response for `Upstream` errors
+    pub upstream_status: Option<u16>,
+}
+
+impl ApiError {
+    fn new(code: ErrorCode, message: impl Into<String>, retryable: bool) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            retryable,
+            upstream_status: None,
+        }
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Config, message, false)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Unauthorized, message, false)
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidInput, message, false)
+    }
+
+    pub fn outcall(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Outcall, message, true)
+    }
+
+    // Rate limits and server errors are worth retrying, client errors are not
+    pub fn upstream(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            upstream_status: Some(status),
+            ..Self::new(
+                ErrorCode::Upstream,
+                message,
+                status == 408 || status == 429 || status >= 500,
+            )
+        }
+    }
+
+    pub fn canister_call(message: impl Into<String>, retryable: bool) -> Self {
+        Self::new(ErrorCode::CanisterCall, message, retryable)
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Parse, message, false)
+    }
+
+    pub fn storage(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Storage, message, false)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.upstream_status {
+            Some(status) => write!(
+                f,
+                "{:?} error (HTTP {}): {}",
+                self.code, status, self.message
+            ),
+            None => write!(f, "{:?} error: {}", self.code, self.message),
+        }
+    }
+}
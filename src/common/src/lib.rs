@@ -0,0 +1,22 @@
+// Types shared by the backend and llm_service canisters
+
+pub mod error;
+pub mod mutation;
+pub mod query;
+pub mod rpc;
+pub mod schema;
+pub mod secret;
+pub mod stable;
+pub mod time;
+pub mod validate;
+
+pub use error::{ApiError, ErrorCode};
+pub use mutation::{Assignment, Mutation, MutationAction};
+pub use query::{
+    Aggregate, AggregateFunction, Condition, Embed, Filter, FilterGroup, FilterOperator,
+    FilterValue, LogicOperator, OrderBy, PostgrestQuery, QueryParseResult, SortDirection,
+};
+pub use rpc::{ParseContext, RpcCall};
+pub use schema::{ColumnReference, ColumnSchema, ColumnType, Schema, TableSchema};
+pub use secret::mask_secret;
+pub use validate::{validate_mutation, validate_query, QueryIssue, ValidatedQuery};
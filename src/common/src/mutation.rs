@@ -0,0 +1,98 @@
+// Changes asked for in natural language, "mark 'Walk the dog' as done" or "delete all
+// archived todos". Parsers return them next to a query whose filters pick the rows to
+// change; the backend only applies them once the caller confirms a preview.
+
+use crate::query::{validate_column, FilterValue};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Mutation {
+    pub action: MutationAction,
+    // New values for an update, empty for a delete
+    pub set: Vec<Assignment>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MutationAction {
+    Update,
+    Delete,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub column: String,
+    pub value: FilterValue,
+}
+
+impl Mutation {
+    pub fn delete() -> Self {
+        Self {
+            action: MutationAction::Delete,
+            set: Vec::new(),
+        }
+    }
+
+    pub fn update(set: Vec<Assignment>) -> Self {
+        Self {
+            action: MutationAction::Update,
+            set,
+        }
+    }
+
+    // An update has to change something and a delete can't carry values
+    pub fn check(&self) -> Result<(), String> {
+        match self.action {
+            MutationAction::Update if self.set.is_empty() => {
+                Err("An update needs at least one column to set".to_string())
+            }
+            MutationAction::Delete if !self.set.is_empty() => {
+                Err("A delete can't set columns".to_string())
+            }
+            _ => {
+                for assignment in &self.set {
+                    validate_column(&assignment.column)?;
+                    if matches!(assignment.value, FilterValue::List(_)) {
+                        return Err(format!("'{}' can't be set to a list", assignment.column));
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl MutationAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MutationAction::Update => "update",
+            MutationAction::Delete => "delete",
+        }
+    }
+}
+
+impl Assignment {
+    pub fn new(column: impl Into<String>, value: impl Into<FilterValue>) -> Self {
+        Self {
+            column: column.into(),
+            value: value.into(),
+        }
+    }
+}
+
+// `update set is_done=true, status=archived` or `delete`
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.action.as_str())?;
+        if !self.set.is_empty() {
+            let set: Vec<String> = self
+                .set
+                .iter()
+                .map(|assignment| format!("{}={}", assignment.column, assignment.value))
+                .collect();
+            write!(f, " set {}", set.join(", "))?;
+        }
+        Ok(())
+    }
+}
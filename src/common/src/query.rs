@@ -0,0 +1,1219 @@
+// Typed model of the PostgREST query strings we send to Supabase,
+// e.g. `select=id,title&is_done=eq.true&order=created_at.desc&limit=5`
+
+use crate::mutation::Mutation;
+use crate::rpc::RpcCall;
+use crate::validate::QueryIssue;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct PostgrestQuery {
+    // Empty means `select=*`, or only the aggregates when there are any
+    pub select: Vec<String>,
+    // `count()`, `budget.sum()`; the selected columns are what they are grouped by
+    pub aggregates: Vec<Aggregate>,
+    // Rows of related tables, `users(name,email)`, rendered after the columns
+    pub embeds: Vec<Embed>,
+    pub filters: Vec<Filter>,
+    // `or=(...)`/`and=(...)` parameters, ANDed with `filters` like any other parameter
+    pub groups: Vec<FilterGroup>,
+    pub order: Vec<OrderBy>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub column: String,
+    pub operator: FilterOperator,
+    pub negated: bool,
+    pub value: FilterValue,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOperator {
+    Eq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Like,
+    Ilike,
+    Is,
+    In,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum FilterValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    List(Vec<FilterValue>),
+}
+
+// A PostgREST logic tree like `or=(is_done.eq.true,not.and(id.gt.5,id.lt.9))`
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct FilterGroup {
+    pub operator: LogicOperator,
+    pub negated: bool,
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicOperator {
+    And,
+    Or,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum Condition {
+    Filter(Filter),
+    Group(FilterGroup),
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub column: String,
+    pub direction: SortDirection,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+// A PostgREST aggregate in `select`: `count()` counts rows, `budget.avg()` works on a
+// column. Needs `db-aggregates-enabled` on the PostgREST side.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    // `None` only for `count()`
+    pub column: Option<String>,
+}
+
+// A related table embedded in each row through a foreign key, `users(name)` for the
+// author of a post or `todos(*)` for the todos of a user. `inner` (`users!inner(*)`)
+// drops rows without a match, which filters on the embed (`users.name=eq.Alice`) need
+// to filter the parent rows.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Embed {
+    pub relation: String,
+    // Empty means `*`
+    pub columns: Vec<String>,
+    pub inner: bool,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+// Result of turning a natural language question into a query for one table.
+// `query` is the rendered PostgREST string, `parsed` the same query in typed form.
+// When the question maps to a Postgres function, `rpc` is set instead of `parsed`
+// and `table`/`query` show the function path and arguments.
+// Questions that can't be parsed are reported as an `ApiError` with the `Parse` code.
+// `repairs` lists what schema validation changed in the query, if anything.
+// `mutation` is set when the question asks to change rows; the query's filters pick them.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct QueryParseResult {
+    pub table: String,
+    pub query: String,
+    pub parsed: Option<PostgrestQuery>,
+    pub rpc: Option<RpcCall>,
+    pub repairs: Option<Vec<QueryIssue>>,
+    pub mutation: Option<Mutation>,
+}
+
+impl QueryParseResult {
+    pub fn success(table: impl Into<String>, query: PostgrestQuery) -> Self {
+        Self {
+            table: table.into(),
+            query: query.to_string(),
+            parsed: Some(query),
+            rpc: None,
+            repairs: None,
+            mutation: None,
+        }
+    }
+
+    pub fn with_repairs(mut self, repairs: Vec<QueryIssue>) -> Self {
+        self.repairs = (!repairs.is_empty()).then_some(repairs);
+        self
+    }
+
+    pub fn with_mutation(mut self, mutation: Mutation) -> Self {
+        self.mutation = Some(mutation);
+        self
+    }
+
+    pub fn rpc(function: impl Into<String>, args: impl Into<String>) -> Self {
+        let call = RpcCall {
+            function: function.into(),
+            args: args.into(),
+        };
+        Self {
+            table: format!("rpc/{}", call.function),
+            query: call.args.clone(),
+            parsed: None,
+            rpc: Some(call),
+            repairs: None,
+            mutation: None,
+        }
+    }
+}
+
+impl PostgrestQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn select<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.select = columns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn aggregate(mut self, aggregate: Aggregate) -> Self {
+        self.aggregates.push(aggregate);
+        self
+    }
+
+    pub fn embed(mut self, embed: Embed) -> Self {
+        self.embeds.push(embed);
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    // Just the number of matching rows, which a `HEAD` request with `count=exact`
+    // answers without aggregates enabled
+    pub fn is_plain_count(&self) -> bool {
+        self.select.is_empty() && self.aggregates == [Aggregate::count()]
+    }
+
+    // `select=*`, `select=id,title`, `select=status,count()` or `select=*,users(name)`
+    fn select_param(&self) -> String {
+        let mut items = self.select.clone();
+        if items.is_empty() && self.aggregates.is_empty() {
+            items.push("*".to_string());
+        }
+        items.extend(self.aggregates.iter().map(Aggregate::to_string));
+        items.extend(self.embeds.iter().map(Embed::to_string));
+        format!("select={}", items.join(","))
+    }
+
+    pub fn group(mut self, group: FilterGroup) -> Self {
+        self.groups.push(group);
+        self
+    }
+
+    pub fn order_by(mut self, column: impl Into<String>, direction: SortDirection) -> Self {
+        self.order.push(OrderBy {
+            column: column.into(),
+            direction,
+        });
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+}
+
+impl Filter {
+    pub fn new(
+        column: impl Into<String>,
+        operator: FilterOperator,
+        value: impl Into<FilterValue>,
+    ) -> Self {
+        Self {
+            column: column.into(),
+            operator,
+            negated: false,
+            value: value.into(),
+        }
+    }
+
+    pub fn eq(column: impl Into<String>, value: impl Into<FilterValue>) -> Self {
+        Self::new(column, FilterOperator::Eq, value)
+    }
+
+    // Case-insensitive substring match, rendered as `ilike.*term*`
+    pub fn contains(column: impl Into<String>, term: &str) -> Self {
+        Self::new(column, FilterOperator::Ilike, format!("*{}*", term))
+    }
+
+    pub fn is_null(column: impl Into<String>) -> Self {
+        Self::new(column, FilterOperator::Is, FilterValue::Null)
+    }
+
+    pub fn not_null(column: impl Into<String>) -> Self {
+        Self::is_null(column).negate()
+    }
+
+    pub fn negate(mut self) -> Self {
+        self.negated = !self.negated;
+        self
+    }
+}
+
+impl FilterGroup {
+    // Rows matching at least one of the conditions
+    pub fn any(conditions: Vec<Condition>) -> Self {
+        Self {
+            operator: LogicOperator::Or,
+            negated: false,
+            conditions,
+        }
+    }
+
+    // Rows matching every condition
+    pub fn all(conditions: Vec<Condition>) -> Self {
+        Self {
+            operator: LogicOperator::And,
+            negated: false,
+            conditions,
+        }
+    }
+
+    pub fn negate(mut self) -> Self {
+        self.negated = !self.negated;
+        self
+    }
+
+    // Every filter in the tree, however deeply nested
+    pub fn filters_mut(&mut self) -> Vec<&mut Filter> {
+        self.conditions
+            .iter_mut()
+            .flat_map(|condition| match condition {
+                Condition::Filter(filter) => vec![filter],
+                Condition::Group(group) => group.filters_mut(),
+            })
+            .collect()
+    }
+}
+
+impl LogicOperator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogicOperator::And => "and",
+            LogicOperator::Or => "or",
+        }
+    }
+}
+
+impl AggregateFunction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+        }
+    }
+}
+
+impl Aggregate {
+    // `count()`, the number of rows
+    pub fn count() -> Self {
+        Self {
+            function: AggregateFunction::Count,
+            column: None,
+        }
+    }
+
+    pub fn of(function: AggregateFunction, column: impl Into<String>) -> Self {
+        Self {
+            function,
+            column: Some(column.into()),
+        }
+    }
+}
+
+impl FilterOperator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterOperator::Eq => "eq",
+            FilterOperator::Neq => "neq",
+            FilterOperator::Gt => "gt",
+            FilterOperator::Gte => "gte",
+            FilterOperator::Lt => "lt",
+            FilterOperator::Lte => "lte",
+            FilterOperator::Like => "like",
+            FilterOperator::Ilike => "ilike",
+            FilterOperator::Is => "is",
+            FilterOperator::In => "in",
+        }
+    }
+}
+
+impl FromStr for FilterOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eq" => Ok(FilterOperator::Eq),
+            "neq" => Ok(FilterOperator::Neq),
+            "gt" => Ok(FilterOperator::Gt),
+            "gte" => Ok(FilterOperator::Gte),
+            "lt" => Ok(FilterOperator::Lt),
+            "lte" => Ok(FilterOperator::Lte),
+            "like" => Ok(FilterOperator::Like),
+            "ilike" => Ok(FilterOperator::Ilike),
+            "is" => Ok(FilterOperator::Is),
+            "in" => Ok(FilterOperator::In),
+            other => Err(format!("Unknown filter operator '{}'", other)),
+        }
+    }
+}
+
+impl From<bool> for FilterValue {
+    fn from(value: bool) -> Self {
+        FilterValue::Bool(value)
+    }
+}
+
+impl From<i64> for FilterValue {
+    fn from(value: i64) -> Self {
+        FilterValue::Int(value)
+    }
+}
+
+impl From<f64> for FilterValue {
+    fn from(value: f64) -> Self {
+        FilterValue::Float(value)
+    }
+}
+
+impl From<&str> for FilterValue {
+    fn from(value: &str) -> Self {
+        FilterValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for FilterValue {
+    fn from(value: String) -> Self {
+        FilterValue::Text(value)
+    }
+}
+
+impl FilterValue {
+    // Infer the most specific value type from a raw PostgREST literal
+    fn infer(raw: &str) -> Self {
+        match raw {
+            "null" => FilterValue::Null,
+            "true" => FilterValue::Bool(true),
+            "false" => FilterValue::Bool(false),
+            _ => match (raw.parse::<i64>(), raw.parse::<f64>()) {
+                (Ok(int), _) => FilterValue::Int(int),
+                (_, Ok(float)) if raw.contains('.') => FilterValue::Float(float),
+                _ => FilterValue::Text(raw.to_string()),
+            },
+        }
+    }
+}
+
+// Rendering to PostgREST query strings
+
+impl fmt::Display for FilterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterValue::Null => write!(f, "null"),
+            FilterValue::Bool(value) => write!(f, "{}", value),
+            FilterValue::Int(value) => write!(f, "{}", value),
+            FilterValue::Float(value) => write!(f, "{}", value),
+            FilterValue::Text(value) => write!(f, "{}", value),
+            FilterValue::List(values) => {
+                let items: Vec<String> = values.iter().map(FilterValue::list_item).collect();
+                write!(f, "({})", items.join(","))
+            }
+        }
+    }
+}
+
+// Characters PostgREST gives a meaning inside lists and logic trees
+const RESERVED: &[char] = &[',', '.', ':', '(', ')', '"', '\\'];
+
+impl FilterValue {
+    // A list item as PostgREST reads it: text containing reserved characters or
+    // surrounding spaces goes in double quotes, with `"` and `\` escaped
+    fn list_item(&self) -> String {
+        match self {
+            FilterValue::Text(text)
+                if text.contains(RESERVED) || text.trim() != text || text.is_empty() =>
+            {
+                format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            other => other.to_string(),
+        }
+    }
+
+    // The value percent-encoded for a URL, after any PostgREST quoting
+    fn encoded(&self) -> String {
+        match self {
+            FilterValue::List(values) => {
+                let items: Vec<String> = values
+                    .iter()
+                    .map(|value| urlencoding::encode(&value.list_item()).into_owned())
+                    .collect();
+                format!("({})", items.join(","))
+            }
+            other => urlencoding::encode(&other.to_string()).into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.column)?;
+        if self.negated {
+            write!(f, "not.")?;
+        }
+        write!(f, "{}.{}", self.operator.as_str(), self.value)
+    }
+}
+
+impl Filter {
+    // `column=[not.]operator.value` with the value percent-encoded, so search terms
+    // containing `&`, `#` or spaces can't break out of the parameter
+    pub fn to_url_param(&self) -> String {
+        let negation = if self.negated { "not." } else { "" };
+        format!(
+            "{}={}{}.{}",
+            self.column,
+            negation,
+            self.operator.as_str(),
+            self.value.encoded()
+        )
+    }
+}
+
+// Inside a logic tree a filter reads `column.[not.]operator.value`, and text values
+// are quoted like list items because `,`, `.` and brackets have a meaning there.
+// `encode` percent-encodes each value for a URL.
+impl Filter {
+    fn tree_item(&self, encode: bool) -> String {
+        let negation = if self.negated { "not." } else { "" };
+        let value = match (&self.value, encode) {
+            (FilterValue::List(_), true) => self.value.encoded(),
+            (FilterValue::List(_), false) => self.value.to_string(),
+            (value, true) => urlencoding::encode(&value.list_item()).into_owned(),
+            (value, false) => value.list_item(),
+        };
+        format!(
+            "{}.{}{}.{}",
+            self.column,
+            negation,
+            self.operator.as_str(),
+            value
+        )
+    }
+}
+
+impl FilterGroup {
+    // `(item,item)`, the part after `or=` or `or`
+    fn tree_items(&self, encode: bool) -> String {
+        let items: Vec<String> = self
+            .conditions
+            .iter()
+            .map(|condition| match condition {
+                Condition::Filter(filter) => filter.tree_item(encode),
+                Condition::Group(group) => group.nested(encode),
+            })
+            .collect();
+        format!("({})", items.join(","))
+    }
+
+    fn prefix(&self) -> String {
+        let negation = if self.negated { "not." } else { "" };
+        format!("{}{}", negation, self.operator.as_str())
+    }
+
+    // `[not.]or(...)` inside another group
+    fn nested(&self, encode: bool) -> String {
+        format!("{}{}", self.prefix(), self.tree_items(encode))
+    }
+
+    // `[not.]or=(...)` as a query parameter, with values percent-encoded
+    pub fn to_url_param(&self) -> String {
+        format!("{}={}", self.prefix(), self.tree_items(true))
+    }
+}
+
+impl fmt::Display for FilterGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.prefix(), self.tree_items(false))
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.column {
+            Some(column) => write!(f, "{}.{}()", column, self.function.as_str()),
+            None => write!(f, "{}()", self.function.as_str()),
+        }
+    }
+}
+
+impl Embed {
+    pub fn new<I, S>(relation: impl Into<String>, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            relation: relation.into(),
+            columns: columns.into_iter().map(Into::into).collect(),
+            inner: false,
+        }
+    }
+
+    pub fn inner(mut self) -> Self {
+        self.inner = true;
+        self
+    }
+}
+
+impl fmt::Display for Embed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = if self.inner { "!inner" } else { "" };
+        let columns = if self.columns.is_empty() {
+            "*".to_string()
+        } else {
+            self.columns.join(",")
+        };
+        write!(f, "{}{}({})", self.relation, inner, columns)
+    }
+}
+
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            SortDirection::Asc => "asc",
+            SortDirection::Desc => "desc",
+        };
+        write!(f, "{}.{}", self.column, direction)
+    }
+}
+
+impl fmt::Display for PostgrestQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![self.select_param()];
+
+        parts.extend(self.filters.iter().map(|filter| filter.to_string()));
+        parts.extend(self.groups.iter().map(|group| group.to_string()));
+
+        if !self.order.is_empty() {
+            let order: Vec<String> = self.order.iter().map(|o| o.to_string()).collect();
+            parts.push(format!("order={}", order.join(",")));
+        }
+        if let Some(limit) = self.limit {
+            parts.push(format!("limit={}", limit));
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("offset={}", offset));
+        }
+
+        write!(f, "{}", parts.join("&"))
+    }
+}
+
+impl PostgrestQuery {
+    // The query string to put in a request URL. `to_string` gives the readable form;
+    // this one percent-encodes filter values. Column names are validated identifiers
+    // and need no encoding.
+    pub fn to_url_query(&self) -> String {
+        let mut parts = vec![self.select_param()];
+
+        parts.extend(self.filters.iter().map(Filter::to_url_param));
+        parts.extend(self.groups.iter().map(FilterGroup::to_url_param));
+
+        if !self.order.is_empty() {
+            let order: Vec<String> = self.order.iter().map(|o| o.to_string()).collect();
+            parts.push(format!("order={}", order.join(",")));
+        }
+        if let Some(limit) = self.limit {
+            parts.push(format!("limit={}", limit));
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("offset={}", offset));
+        }
+
+        parts.join("&")
+    }
+}
+
+// Parsing PostgREST query strings back into the typed model
+
+// Column names are plain identifiers, which keeps them safe to splice into a URL
+pub fn validate_column(column: &str) -> Result<(), String> {
+    let valid = !column.is_empty()
+        && column
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid column name '{}'", column))
+    }
+}
+
+// Filters may also target a column of an embedded table, `users.name`
+fn validate_filter_column(column: &str) -> Result<(), String> {
+    match column.split_once('.') {
+        Some((relation, column)) => {
+            validate_column(relation)?;
+            validate_column(column)
+        }
+        None => validate_column(column),
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    // Parses a single `column=[not.]operator.value` pair
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, expression) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected 'column=operator.value' but got '{}'", s))?;
+        validate_filter_column(column)?;
+
+        let (negated, expression) = match expression.strip_prefix("not.") {
+            Some(rest) => (true, rest),
+            None => (false, expression),
+        };
+
+        let (operator, raw_value) = expression
+            .split_once('.')
+            .ok_or_else(|| format!("Missing operator in filter '{}'", s))?;
+        let operator: FilterOperator = operator.parse()?;
+
+        let value = match operator {
+            FilterOperator::Is => match raw_value {
+                "null" => FilterValue::Null,
+                "true" => FilterValue::Bool(true),
+                "false" => FilterValue::Bool(false),
+                other => {
+                    return Err(format!(
+                        "'is' only accepts null, true or false but got '{}'",
+                        other
+                    ))
+                }
+            },
+            FilterOperator::In => {
+                let inner = raw_value
+                    .strip_prefix('(')
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .ok_or_else(|| {
+                        format!("'in' expects a list like (1,2,3) but got '{}'", raw_value)
+                    })?;
+                FilterValue::List(split_list(inner)?)
+            }
+            FilterOperator::Like | FilterOperator::Ilike => {
+                FilterValue::Text(raw_value.to_string())
+            }
+            _ => FilterValue::infer(raw_value),
+        };
+
+        Ok(Filter {
+            column: column.to_string(),
+            operator,
+            negated,
+            value,
+        })
+    }
+}
+
+// Splits the inside of an `in.(...)` list, honouring double-quoted items
+fn split_list(inner: &str) -> Result<Vec<FilterValue>, String> {
+    let mut items = Vec::new();
+    let mut chars = inner.chars().peekable();
+
+    loop {
+        while chars.next_if_eq(&' ').is_some() {}
+        let item = if chars.next_if_eq(&'"').is_some() {
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('\\') => text.extend(chars.next()),
+                    Some('"') => break,
+                    Some(c) => text.push(c),
+                    None => return Err(format!("Unterminated quote in list '({})'", inner)),
+                }
+            }
+            while chars.next_if_eq(&' ').is_some() {}
+            FilterValue::Text(text)
+        } else {
+            let mut raw = String::new();
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                raw.push(c);
+            }
+            FilterValue::infer(raw.trim())
+        };
+        items.push(item);
+
+        match chars.next() {
+            Some(',') => {}
+            None => break,
+            Some(c) => {
+                return Err(format!(
+                    "Unexpected '{}' after a quoted item in list '({})'",
+                    c, inner
+                ))
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+impl FromStr for FilterGroup {
+    type Err = String;
+
+    // Parses `[not.]or(...)`/`[not.]and(...)`, or `[not.]or=(...)` as a query parameter
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negated, rest) = match s.strip_prefix("not.") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (operator, items) = if let Some(items) = rest.strip_prefix("or") {
+            (LogicOperator::Or, items)
+        } else if let Some(items) = rest.strip_prefix("and") {
+            (LogicOperator::And, items)
+        } else {
+            return Err(format!("Expected 'or(...)' or 'and(...)' but got '{}'", s));
+        };
+        let inner = items
+            .strip_prefix('=')
+            .unwrap_or(items)
+            .strip_prefix('(')
+            .and_then(|rest| rest.strip_suffix(')'))
+            .ok_or_else(|| format!("Expected a bracketed list of conditions in '{}'", s))?;
+
+        let conditions = split_conditions(inner)?
+            .into_iter()
+            .map(|item| {
+                let is_group = ["or(", "and(", "not.or(", "not.and("]
+                    .iter()
+                    .any(|prefix| item.starts_with(prefix));
+                if is_group {
+                    item.parse().map(Condition::Group)
+                } else {
+                    parse_tree_filter(item).map(Condition::Filter)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if conditions.is_empty() {
+            return Err(format!("'{}' has no conditions", s));
+        }
+
+        Ok(FilterGroup {
+            operator,
+            negated,
+            conditions,
+        })
+    }
+}
+
+// Splits the inside of a logic tree at the commas that aren't quoted or nested
+fn split_conditions(inner: &str) -> Result<Vec<&str>, String> {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("Unbalanced ')' in '({})'", inner))?
+            }
+            ',' if !quoted && depth == 0 => {
+                items.push(inner[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted || depth > 0 {
+        return Err(format!("Unterminated quote or bracket in '({})'", inner));
+    }
+    items.push(inner[start..].trim());
+
+    Ok(items.into_iter().filter(|item| !item.is_empty()).collect())
+}
+
+// `column.[not.]operator.value`, the filter form used inside logic trees
+fn parse_tree_filter(item: &str) -> Result<Filter, String> {
+    let (column, expression) = item
+        .split_once('.')
+        .ok_or_else(|| format!("Expected 'column.operator.value' but got '{}'", item))?;
+    let mut filter: Filter = format!("{}={}", column, expression).parse()?;
+    if let FilterValue::Text(text) = &filter.value {
+        if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+            match split_list(text)?.pop() {
+                Some(FilterValue::Text(unquoted)) => filter.value = FilterValue::Text(unquoted),
+                _ => return Err(format!("Invalid quoted value in '{}'", item)),
+            }
+        }
+    }
+    Ok(filter)
+}
+
+impl FromStr for OrderBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, direction) = match s.split_once('.') {
+            Some((column, "asc")) => (column, SortDirection::Asc),
+            Some((column, "desc")) => (column, SortDirection::Desc),
+            Some((_, other)) => return Err(format!("Unknown sort direction '{}'", other)),
+            None => (s, SortDirection::Asc),
+        };
+        validate_column(column)?;
+        Ok(OrderBy {
+            column: column.to_string(),
+            direction,
+        })
+    }
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    // `count()`, `column.count()`, `column.sum()`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let call = s
+            .strip_suffix("()")
+            .ok_or_else(|| format!("Invalid aggregate '{}'", s))?;
+        let (column, name) = match call.rsplit_once('.') {
+            Some((column, name)) => (Some(column), name),
+            None => (None, call),
+        };
+        let function = match name {
+            "count" => AggregateFunction::Count,
+            "sum" => AggregateFunction::Sum,
+            "avg" => AggregateFunction::Avg,
+            "min" => AggregateFunction::Min,
+            "max" => AggregateFunction::Max,
+            other => return Err(format!("Unknown aggregate function '{}'", other)),
+        };
+        match column {
+            Some(column) => {
+                validate_column(column)?;
+                Ok(Aggregate::of(function, column))
+            }
+            None if function == AggregateFunction::Count => Ok(Aggregate::count()),
+            None => Err(format!("Aggregate '{}' needs a column", s)),
+        }
+    }
+}
+
+impl FromStr for Embed {
+    type Err = String;
+
+    // `users(*)`, `users(name,email)`, `users!inner(name)`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (relation, columns) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| format!("Invalid embedded resource '{}'", s))?;
+        let (relation, inner) = match relation.strip_suffix("!inner") {
+            Some(relation) => (relation, true),
+            None => (relation, false),
+        };
+        validate_column(relation)?;
+        let columns: Vec<&str> = match columns.trim() {
+            "*" => Vec::new(),
+            columns => columns.split(',').map(str::trim).collect(),
+        };
+        for column in &columns {
+            validate_column(column)?;
+        }
+        Ok(Embed {
+            relation: relation.to_string(),
+            columns: columns.into_iter().map(String::from).collect(),
+            inner,
+        })
+    }
+}
+
+// `count()` and `budget.sum()` are aggregates, `users(name)` and `users()` embeds
+fn is_aggregate(item: &str) -> bool {
+    item.strip_suffix("()")
+        .is_some_and(|call| call == "count" || call.contains('.'))
+}
+
+impl FromStr for PostgrestQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut query = PostgrestQuery::new();
+
+        for part in s.trim().trim_start_matches('?').split('&') {
+            if part.is_empty() {
+                continue;
+            }
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Expected 'key=value' but got '{}'", part))?;
+
+            match key {
+                "select" => {
+                    for item in split_conditions(value)?.into_iter().map(str::trim) {
+                        if item == "*" {
+                            continue;
+                        } else if is_aggregate(item) {
+                            query.aggregates.push(item.parse()?);
+                        } else if item.ends_with(')') {
+                            query.embeds.push(item.parse()?);
+                        } else {
+                            validate_column(item)?;
+                            query.select.push(item.to_string());
+                        }
+                    }
+                }
+                "order" => {
+                    for item in value.split(',') {
+                        query.order.push(item.trim().parse()?);
+                    }
+                }
+                "limit" => {
+                    query.limit = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid limit '{}'", value))?,
+                    );
+                }
+                "offset" => {
+                    query.offset = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid offset '{}'", value))?,
+                    );
+                }
+                "or" | "and" | "not.or" | "not.and" => query.groups.push(part.parse()?),
+                _ => query.filters.push(part.parse()?),
+            }
+        }
+
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The value part of a `column=operator.value` URL parameter, decoded
+    fn decoded_value(param: &str) -> String {
+        let (_, value) = param.split_once('=').unwrap();
+        urlencoding::decode(value).unwrap().into_owned()
+    }
+
+    #[test]
+    fn hostile_search_terms_stay_inside_their_parameter() {
+        for term in [
+            "a&select=password",
+            "dogs#cats",
+            "buy milk, eggs",
+            "50% off",
+            "x=y",
+            "(a).b:c",
+            "\"quoted\"",
+            "back\\slash",
+            "zażółć gęślą jaźń",
+            "?limit=1000000",
+        ] {
+            let query = PostgrestQuery::new().filter(Filter::contains("title", term));
+            let url_query = query.to_url_query();
+
+            let params: Vec<&str> = url_query.split('&').collect();
+            assert_eq!(params.len(), 2, "term {:?} gave {}", term, url_query);
+            assert!(!url_query.contains(['#', ' ', '"', '?']), "{}", url_query);
+            assert_eq!(decoded_value(params[1]), format!("ilike.*{}*", term));
+        }
+    }
+
+    #[test]
+    fn filter_values_are_percent_encoded() {
+        let filter = Filter::eq("title", "buy milk & eggs #2");
+        assert_eq!(
+            filter.to_url_param(),
+            "title=eq.buy%20milk%20%26%20eggs%20%232"
+        );
+        assert_eq!(
+            Filter::not_null("due_date").to_url_param(),
+            "due_date=not.is.null"
+        );
+    }
+
+    #[test]
+    fn list_items_with_reserved_characters_are_quoted() {
+        let filter = Filter::new(
+            "title",
+            FilterOperator::In,
+            FilterValue::List(vec![
+                FilterValue::Text("Hebdon, John".to_string()),
+                FilterValue::Text("say \"hi\"".to_string()),
+                FilterValue::Text("plain".to_string()),
+                FilterValue::Int(3),
+            ]),
+        );
+
+        assert_eq!(
+            filter.to_string(),
+            r#"title=in.("Hebdon, John","say \"hi\"",plain,3)"#
+        );
+        assert_eq!(
+            filter.to_url_param(),
+            "title=in.(%22Hebdon%2C%20John%22,%22say%20%5C%22hi%5C%22%22,plain,3)"
+        );
+    }
+
+    #[test]
+    fn quoted_list_items_round_trip() {
+        let filter = Filter::new(
+            "title",
+            FilterOperator::In,
+            FilterValue::List(vec![
+                FilterValue::Text("a,b".to_string()),
+                FilterValue::Text("(x)".to_string()),
+                FilterValue::Text("back\\slash \"q\"".to_string()),
+                FilterValue::Text(" padded ".to_string()),
+                FilterValue::Int(7),
+            ]),
+        );
+
+        let parsed: Filter = filter.to_string().parse().unwrap();
+        assert_eq!(parsed, filter);
+    }
+
+    #[test]
+    fn unterminated_quotes_are_rejected() {
+        assert!(r#"title=in.("open,b)"#.parse::<Filter>().is_err());
+        assert!(r#"title=in.("a"b)"#.parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn url_query_matches_display_for_safe_values() {
+        let query = PostgrestQuery::new()
+            .select(["id", "title"])
+            .filter(Filter::eq("is_done", true))
+            .order_by("created_at", SortDirection::Desc)
+            .limit(5);
+
+        assert_eq!(query.to_url_query(), query.to_string());
+    }
+
+    #[test]
+    fn logic_trees_render_and_round_trip() {
+        let query = PostgrestQuery::new()
+            .filter(Filter::eq("status", "open"))
+            .group(FilterGroup::any(vec![
+                Condition::Filter(Filter::eq("is_done", true)),
+                Condition::Group(
+                    FilterGroup::all(vec![
+                        Condition::Filter(Filter::not_null("due_date")),
+                        Condition::Filter(Filter::contains("title", "a, b").negate()),
+                    ])
+                    .negate(),
+                ),
+            ]));
+
+        assert_eq!(
+            query.to_string(),
+            r#"select=*&status=eq.open&or=(is_done.eq.true,not.and(due_date.not.is.null,title.not.ilike."*a, b*"))"#
+        );
+        assert_eq!(
+            query.to_url_query(),
+            "select=*&status=eq.open&or=(is_done.eq.true,not.and(due_date.not.is.null,title.not.ilike.%22%2Aa%2C%20b%2A%22))"
+        );
+        let parsed: PostgrestQuery = query.to_string().parse().unwrap();
+        assert_eq!(parsed, query);
+    }
+
+    #[test]
+    fn aggregates_render_and_round_trip() {
+        let query = PostgrestQuery::new()
+            .select(["status"])
+            .aggregate(Aggregate::count())
+            .aggregate(Aggregate::of(AggregateFunction::Avg, "budget"));
+
+        assert_eq!(query.to_string(), "select=status,count(),budget.avg()");
+        assert_eq!(query.to_url_query(), query.to_string());
+        let parsed: PostgrestQuery = query.to_string().parse().unwrap();
+        assert_eq!(parsed, query);
+
+        let count: PostgrestQuery = "select=count()&is_done=eq.true".parse().unwrap();
+        assert!(count.is_plain_count());
+        assert!(!parsed.is_plain_count());
+        assert!("select=sum()".parse::<PostgrestQuery>().is_err());
+        assert!("select=id.median()".parse::<PostgrestQuery>().is_err());
+    }
+
+    #[test]
+    fn embeds_render_and_round_trip() {
+        let query = PostgrestQuery::new()
+            .embed(Embed::new("users", ["email"]).inner())
+            .filter(Filter::contains("users.name", "alice"));
+
+        assert_eq!(
+            query.to_string(),
+            "select=*,users!inner(email)&users.name=ilike.*alice*"
+        );
+        let parsed: PostgrestQuery = query.to_string().parse().unwrap();
+        assert_eq!(parsed, query);
+
+        let parsed: PostgrestQuery = "select=id,title,todos(*),count()".parse().unwrap();
+        assert_eq!(parsed.select, ["id", "title"]);
+        assert_eq!(parsed.embeds, [Embed::new("todos", Vec::<String>::new())]);
+        assert_eq!(parsed.aggregates, [Aggregate::count()]);
+        assert_eq!(parsed.to_string(), "select=id,title,count(),todos(*)");
+        assert!("select=users(name".parse::<PostgrestQuery>().is_err());
+        assert!("select=users(na;me)".parse::<PostgrestQuery>().is_err());
+        assert!("users.na;me=eq.1".parse::<PostgrestQuery>().is_err());
+    }
+
+    #[test]
+    fn malformed_logic_trees_are_rejected() {
+        assert!("or=(is_done.eq.true".parse::<PostgrestQuery>().is_err());
+        assert!("or=()".parse::<PostgrestQuery>().is_err());
+        assert!("and=(id.eq.1,title.eq.\"open)"
+            .parse::<PostgrestQuery>()
+            .is_err());
+        assert!("or=(id)".parse::<PostgrestQuery>().is_err());
+    }
+}
@@ -0,0 +1,49 @@
+// Postgres functions exposed by PostgREST at `/rest/v1/rpc/{function}` that the
+// natural language pipeline may call instead of reading a table
+
+use crate::query::validate_column;
+use crate::schema::Schema;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RpcCall {
+    pub function: String,
+    // JSON object with the function's named arguments
+    pub args: String,
+}
+
+// What a parser may target: the tables in `schema` and the allowlisted functions
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ParseContext {
+    pub allowed_rpcs: Vec<String>,
+    // Callers that don't send a schema get the built-in one
+    pub schema: Option<Schema>,
+    // Nanoseconds since the epoch and the time zone that relative dates like
+    // "yesterday" are resolved in; the receiving canister fills in its own time
+    pub now: Option<u64>,
+    pub time_zone: Option<String>,
+}
+
+impl ParseContext {
+    pub fn schema(&self) -> Schema {
+        self.schema.clone().unwrap_or_else(Schema::builtin)
+    }
+}
+
+// Function names follow the same rules as column names
+pub fn validate_function_name(function: &str) -> Result<(), String> {
+    validate_column(function).map_err(|_| format!("Invalid function name '{}'", function))
+}
+
+// Finds an allowlisted function mentioned in a lowercased question, either by its
+// name or with underscores read as spaces ("get overdue todos" for `get_overdue_todos`)
+pub fn match_rpc<'a>(query_lower: &str, allowed_rpcs: &'a [String]) -> Option<&'a str> {
+    allowed_rpcs
+        .iter()
+        .find(|function| {
+            let function = function.to_lowercase();
+            query_lower.contains(&function) || query_lower.contains(&function.replace('_', " "))
+        })
+        .map(String::as_str)
+}
@@ -0,0 +1,504 @@
+// Description of the tables the natural language pipeline may query. The backend keeps
+// it in stable memory and passes it to llm_service, so the LLM prompt, the rule-based
+// parsers and output validation all work from the same list of tables and columns.
+
+use crate::error::ApiError;
+use crate::query::validate_column;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    Float,
+    Text,
+    Boolean,
+    Timestamp,
+    Date,
+    Uuid,
+    Json,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub description: Option<String>,
+    // Other words users may use for the column, e.g. "completed" for `is_done`
+    pub synonyms: Vec<String>,
+    // Foreign key, e.g. `posts.user_id` referencing `users.id`. It lets queries on one
+    // table embed the related rows of the other, `select=title,users(name)`.
+    pub references: Option<ColumnReference>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ColumnReference {
+    pub table: String,
+    pub column: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TableSchema {
+    pub name: String,
+    pub description: Option<String>,
+    // Other words users may use for the table, e.g. "task" for `todos`
+    pub synonyms: Vec<String>,
+    pub columns: Vec<ColumnSchema>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct Schema {
+    pub tables: Vec<TableSchema>,
+}
+
+impl ColumnType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Text => "text",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Timestamp => "timestamp",
+            ColumnType::Date => "date",
+            ColumnType::Uuid => "uuid",
+            ColumnType::Json => "json",
+        }
+    }
+}
+
+impl ColumnSchema {
+    pub fn new(name: impl Into<String>, column_type: ColumnType) -> Self {
+        Self {
+            name: name.into(),
+            column_type,
+            description: None,
+            synonyms: Vec::new(),
+            references: None,
+        }
+    }
+
+    pub fn synonyms<I, S>(mut self, synonyms: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.synonyms = synonyms.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn references(mut self, table: impl Into<String>, column: impl Into<String>) -> Self {
+        self.references = Some(ColumnReference {
+            table: table.into(),
+            column: column.into(),
+        });
+        self
+    }
+
+    // `title (text)` or `user_id (integer, references users.id)`, with the description
+    // appended when there is one
+    fn describe(&self) -> String {
+        let mut details = vec![self.column_type.as_str().to_string()];
+        if let Some(reference) = &self.references {
+            details.push(format!(
+                "references {}.{}",
+                reference.table, reference.column
+            ));
+        }
+        details.extend(self.description.clone());
+        format!("{} ({})", self.name, details.join(", "))
+    }
+}
+
+impl TableSchema {
+    pub fn new(name: impl Into<String>, columns: Vec<ColumnSchema>) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            synonyms: Vec::new(),
+            columns,
+        }
+    }
+
+    pub fn synonyms<I, S>(mut self, synonyms: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.synonyms = synonyms.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn column(&self, name: &str) -> Option<&ColumnSchema> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    pub fn has_column(&self, name: &str) -> bool {
+        self.column(name).is_some()
+    }
+
+    // First column of the given type, e.g. the text column to search in
+    pub fn first_column_of(&self, column_type: ColumnType) -> Option<&ColumnSchema> {
+        self.columns
+            .iter()
+            .find(|column| column.column_type == column_type)
+    }
+
+    // Words that refer to this table in a question: its name, the name without a
+    // trailing "s" and the synonyms
+    pub fn terms(&self) -> Vec<String> {
+        let name = self.name.to_lowercase();
+        let mut terms = vec![name.clone()];
+        if let Some(singular) = name.strip_suffix('s').filter(|s| !s.is_empty()) {
+            terms.push(singular.to_string());
+        }
+        terms.extend(self.synonyms.iter().map(|synonym| synonym.to_lowercase()));
+        terms
+    }
+
+    fn validate(&self) -> Result<(), ApiError> {
+        validate_column(&self.name).map_err(ApiError::invalid_input)?;
+        if self.columns.is_empty() {
+            return Err(ApiError::invalid_input(format!(
+                "Table '{}' must have at least one column",
+                self.name
+            )));
+        }
+        for (i, column) in self.columns.iter().enumerate() {
+            validate_column(&column.name).map_err(ApiError::invalid_input)?;
+            if let Some(reference) = &column.references {
+                validate_column(&reference.table).map_err(ApiError::invalid_input)?;
+                validate_column(&reference.column).map_err(ApiError::invalid_input)?;
+            }
+            if self.columns[..i].iter().any(|c| c.name == column.name) {
+                return Err(ApiError::invalid_input(format!(
+                    "Table '{}' lists column '{}' twice",
+                    self.name, column.name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Schema {
+    // The tables of the example Supabase project, used until controllers set their own
+    pub fn builtin() -> Self {
+        use ColumnType::*;
+
+        Self {
+            tables: vec![
+                TableSchema::new(
+                    "todos",
+                    vec![
+                        ColumnSchema::new("id", Integer),
+                        ColumnSchema::new("title", Text),
+                        ColumnSchema::new("description", Text),
+                        ColumnSchema::new("is_done", Boolean).synonyms([
+                            "completed",
+                            "done",
+                            "finished",
+                        ]),
+                        ColumnSchema::new("due_date", Timestamp).synonyms(["due", "deadline"]),
+                        ColumnSchema::new("status", Text),
+                        // A uuid, like the rows `create_test_todos` writes
+                        ColumnSchema::new("user_id", Uuid)
+                            .synonyms(["owner"])
+                            .references("users", "id"),
+                        ColumnSchema::new("created_at", Timestamp),
+                    ],
+                )
+                .synonyms(["task", "tasks"]),
+                TableSchema::new(
+                    "users",
+                    vec![
+                        ColumnSchema::new("id", Integer),
+                        ColumnSchema::new("name", Text),
+                        ColumnSchema::new("email", Text),
+                        ColumnSchema::new("created_at", Timestamp),
+                    ],
+                ),
+                TableSchema::new(
+                    "posts",
+                    vec![
+                        ColumnSchema::new("id", Integer),
+                        ColumnSchema::new("title", Text),
+                        ColumnSchema::new("content", Text),
+                        ColumnSchema::new("user_id", Integer)
+                            .synonyms(["author"])
+                            .references("users", "id"),
+                        ColumnSchema::new("created_at", Timestamp),
+                    ],
+                ),
+            ],
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ApiError> {
+        for (i, table) in self.tables.iter().enumerate() {
+            table.validate()?;
+            if self.tables[..i].iter().any(|t| t.name == table.name) {
+                return Err(ApiError::invalid_input(format!(
+                    "Table '{}' is listed twice",
+                    table.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn table(&self, name: &str) -> Option<&TableSchema> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+
+    // Tables whose rows can be embedded in rows of `table`: those it references
+    // (a post's user) and those referencing it (a user's posts)
+    pub fn related_tables(&self, table: &str) -> Vec<&TableSchema> {
+        self.tables
+            .iter()
+            .filter(|other| other.name != table && self.are_related(table, &other.name))
+            .collect()
+    }
+
+    pub fn are_related(&self, table: &str, other: &str) -> bool {
+        let references = |from: &str, to: &str| {
+            self.table(from).is_some_and(|from| {
+                from.columns.iter().any(|column| {
+                    column
+                        .references
+                        .as_ref()
+                        .is_some_and(|reference| reference.table == to)
+                })
+            })
+        };
+        references(table, other) || references(other, table)
+    }
+
+    pub fn table_names(&self) -> Vec<&str> {
+        self.tables
+            .iter()
+            .map(|table| table.name.as_str())
+            .collect()
+    }
+
+    // Finds the table a lowercased question is about. Tables are tried in registry
+    // order, so earlier tables win when a question mentions several.
+    pub fn match_table(&self, query_lower: &str) -> Option<&TableSchema> {
+        self.tables.iter().find(|table| {
+            table
+                .terms()
+                .iter()
+                .any(|term| query_lower.contains(term.as_str()))
+        })
+    }
+
+    // Schema section of the LLM system prompt, one line per table
+    pub fn describe(&self) -> String {
+        self.tables
+            .iter()
+            .map(|table| {
+                let columns: Vec<String> = table.columns.iter().map(|c| c.describe()).collect();
+                let mut line = format!("- {}: {}", table.name, columns.join(", "));
+                if let Some(description) = &table.description {
+                    line.push_str(&format!(". {}", description));
+                }
+                if !table.synonyms.is_empty() {
+                    line.push_str(&format!(" (also called: {})", table.synonyms.join(", ")));
+                }
+                let column_synonyms: Vec<String> = table
+                    .columns
+                    .iter()
+                    .filter(|column| !column.synonyms.is_empty())
+                    .map(|column| format!("{} = {}", column.synonyms.join("/"), column.name))
+                    .collect();
+                if !column_synonyms.is_empty() {
+                    line.push_str(&format!(" [{}]", column_synonyms.join("; ")));
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // Example questions for the LLM system prompt, written against these tables so the
+    // model isn't taught columns that don't exist. Kinds of question a table has no
+    // column for are left out.
+    pub fn examples(&self) -> String {
+        let Some(table) = self.tables.first() else {
+            return String::new();
+        };
+        let name = &table.name;
+        let text = table.first_column_of(ColumnType::Text).map(|c| &c.name);
+        let flag = table.first_column_of(ColumnType::Boolean).map(|c| &c.name);
+        let date = table
+            .first_column_of(ColumnType::Timestamp)
+            .or_else(|| table.first_column_of(ColumnType::Date))
+            .map(|c| &c.name);
+
+        let mut examples = vec![example(&format!("get all {}", name), name, "select=*", "")];
+        if let Some(flag) = flag {
+            examples.push(example(
+                &format!("show {} where {} is true", name, flag),
+                name,
+                &format!("select=*&{}=eq.true", flag),
+                "",
+            ));
+            examples.push(example(
+                &format!("how many {} have {} false", name, flag),
+                name,
+                &format!("select=count()&{}=eq.false", flag),
+                "",
+            ));
+        }
+        if let Some(text) = text {
+            examples.push(example(
+                &format!("find {} with {} containing work", name, text),
+                name,
+                &format!("select=*&{}=ilike.*work*", text),
+                "",
+            ));
+            examples.push(example(
+                &format!("top twenty {} alphabetically by {}", name, text),
+                name,
+                &format!("select=*&order={}.asc&limit=20", text),
+                "",
+            ));
+        }
+        if let Some(date) = date {
+            examples.push(example(
+                &format!("{} without a {}", name, date),
+                name,
+                &format!("select=*&{}=is.null", date),
+                "",
+            ));
+        }
+        if let (Some(flag), Some(text)) = (flag, text) {
+            examples.push(example(
+                &format!(
+                    "set {} to true for {} with {} 'Walk the dog'",
+                    flag, name, text
+                ),
+                name,
+                &format!("select=*&{}=ilike.*Walk the dog*", text),
+                &format!(r#", "action": "update", "set": {{"{}": true}}"#, flag),
+            ));
+            examples.push(example(
+                &format!("delete all {} where {} is true", name, flag),
+                name,
+                &format!("select=*&{}=eq.true", flag),
+                r#", "action": "delete""#,
+            ));
+            if let Some(date) = date {
+                examples.push(example(
+                    &format!(
+                        "{} where {} is true or {} is empty but {} not like work",
+                        name, flag, date, text
+                    ),
+                    name,
+                    &format!(
+                        "select=*&{}=not.ilike.*work*&or=({}.eq.true,{}.is.null)",
+                        text, flag, date
+                    ),
+                    "",
+                ));
+            }
+        }
+        // "posts by Alice with author email": filter on and show a referenced table
+        let embed = self.tables.iter().find_map(|from| {
+            from.columns.iter().find_map(|column| {
+                let related = self.table(&column.references.as_ref()?.table)?;
+                let text = related.first_column_of(ColumnType::Text)?;
+                Some((&from.name, &related.name, &text.name))
+            })
+        });
+        if let Some((from, related, text)) = embed {
+            examples.push(example(
+                &format!(
+                    "{} whose {} {} is Alice, with that {}",
+                    from, related, text, text
+                ),
+                from,
+                &format!(
+                    "select=*,{}!inner({})&{}.{}=ilike.*Alice*",
+                    related, text, related, text
+                ),
+                "",
+            ));
+        }
+        examples.join("\n")
+    }
+}
+
+// One example line: the question and the JSON answer, with `extra` fields before `error`
+fn example(question: &str, table: &str, query: &str, extra: &str) -> String {
+    format!(
+        r#""{}" → {{"table": "{}", "query": "{}"{}, "error": null}}"#,
+        question, table, query, extra
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples_use_the_columns_of_the_schema() {
+        let schema = Schema {
+            tables: vec![
+                TableSchema::new(
+                    "orders",
+                    vec![
+                        ColumnSchema::new("id", ColumnType::Integer),
+                        ColumnSchema::new("reference", ColumnType::Text),
+                        ColumnSchema::new("paid", ColumnType::Boolean),
+                        ColumnSchema::new("shipped_on", ColumnType::Date),
+                        ColumnSchema::new("customer_id", ColumnType::Integer)
+                            .references("customers", "id"),
+                    ],
+                ),
+                TableSchema::new(
+                    "customers",
+                    vec![
+                        ColumnSchema::new("id", ColumnType::Integer),
+                        ColumnSchema::new("company", ColumnType::Text),
+                    ],
+                ),
+            ],
+        };
+
+        let examples = schema.examples();
+
+        assert!(examples.contains(
+            r#""show orders where paid is true" → {"table": "orders", "query": "select=*&paid=eq.true", "error": null}"#
+        ));
+        assert!(examples.contains(
+            r#"{"table": "orders", "query": "select=*&reference=ilike.*Walk the dog*", "action": "update", "set": {"paid": true}, "error": null}"#
+        ));
+        assert!(examples.contains("or=(paid.eq.true,shipped_on.is.null)"));
+        assert!(
+            examples.contains("select=*,customers!inner(company)&customers.company=ilike.*Alice*")
+        );
+        for builtin in ["todos", "is_done", "due_date", "users", "title"] {
+            assert!(
+                !examples.contains(builtin),
+                "{} leaked into {}",
+                builtin,
+                examples
+            );
+        }
+    }
+
+    #[test]
+    fn examples_leave_out_questions_without_a_matching_column() {
+        let schema = Schema {
+            tables: vec![TableSchema::new(
+                "logs",
+                vec![ColumnSchema::new("id", ColumnType::Integer)],
+            )],
+        };
+
+        assert_eq!(
+            schema.examples(),
+            r#""get all logs" → {"table": "logs", "query": "select=*", "error": null}"#
+        );
+    }
+}
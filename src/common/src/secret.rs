@@ -0,0 +1,12 @@
+// Helpers for showing credentials without revealing them
+
+// Keeps the first and last four characters of long secrets, e.g. `eyJh…x9Qk`
+pub fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 12 {
+        return "*".repeat(chars.len());
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
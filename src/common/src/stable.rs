@@ -0,0 +1,26 @@
+// Stable memory helpers used by the canisters' storage layers
+
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+
+// Stores any Candid type in a stable structure by encoding it with Candid
+#[derive(Default, Clone)]
+pub struct Candid<T>(pub T);
+
+impl<T> Storable for Candid<T>
+where
+    T: CandidType + DeserializeOwned,
+{
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(&self.0).expect("failed to encode stable value"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Candid(Decode!(bytes.as_ref(), T).expect("failed to decode stable value"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
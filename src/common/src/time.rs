@@ -0,0 +1,254 @@
+// Calendar arithmetic for the timestamps Postgres returns, without pulling in a date crate
+
+pub const NANOS_PER_SECOND: i64 = 1_000_000_000;
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+// Days between 1970-01-01 and the given date of the proleptic Gregorian calendar
+// (Howard Hinnant's `days_from_civil`)
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Inverse of `days_from_civil`
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn number<T: std::str::FromStr>(s: &str, len: usize) -> Option<T> {
+    (s.len() == len && s.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| s.parse().ok())
+        .flatten()
+}
+
+// Offset in seconds from `Z`, `+02`, `+0530` or `-05:30`
+fn parse_offset(s: &str) -> Option<i64> {
+    if s.is_empty() || s == "Z" {
+        return Some(0);
+    }
+    let sign = match s.as_bytes()[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits = s[1..].replace(':', "");
+    if !digits.is_ascii() {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (number::<i64>(&digits, 2)?, 0),
+        4 => (
+            number::<i64>(&digits[..2], 2)?,
+            number::<i64>(&digits[2..], 2)?,
+        ),
+        _ => return None,
+    };
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+// Offset in seconds of a fixed time zone written as `UTC`, `UTC+2`, `GMT-05:30`,
+// `+02:00` or `-0500`. Named zones with daylight saving time aren't supported.
+pub fn parse_time_zone(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let upper = s.to_uppercase();
+    let offset = upper
+        .strip_prefix("UTC")
+        .or_else(|| upper.strip_prefix("GMT"))
+        .unwrap_or(&upper);
+    // `+2` and `-5:30` have a one-digit hour
+    let offset = match offset.find(':').unwrap_or(offset.len()) {
+        2 if offset.is_ascii() => format!("{}0{}", &offset[..1], &offset[1..]),
+        _ => offset.to_string(),
+    };
+    let seconds = parse_offset(&offset)?;
+    (seconds.abs() <= 14 * 3600).then_some(seconds)
+}
+
+// `2024-01-15` for a number of days since 1970-01-01
+pub fn format_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Nanoseconds since the Unix epoch for midnight UTC of a `2024-01-15` date, `None` for
+// dates nanoseconds in an i64 can't reach
+pub fn parse_date(s: &str) -> Option<i64> {
+    let mut parts = s.trim().split('-');
+    let year: i64 = number(parts.next()?, 4)?;
+    let month: u32 = number(parts.next()?, 2)?;
+    let day: u32 = number(parts.next()?, 2)?;
+    if parts.next().is_some()
+        || !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+    {
+        return None;
+    }
+    days_from_civil(year, month, day)
+        .checked_mul(SECONDS_PER_DAY)?
+        .checked_mul(NANOS_PER_SECOND)
+}
+
+// Nanoseconds since the Unix epoch for an ISO 8601 date-time such as
+// `2024-01-15T10:30:00`, `2024-01-15 10:30:00.123456` or `2024-01-15T10:30:00+02:00`.
+// Values without an offset (Postgres `timestamp`) are read as UTC, and values outside
+// what nanoseconds in an i64 can hold (about 1678 to 2262) are `None`.
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    // Every string of a response goes through here, so anything that can't be a
+    // timestamp has to be turned away before the byte offsets below are used
+    if s.len() < 19 || !s.is_ascii() {
+        return None;
+    }
+    let (date, rest) = s.split_at(10);
+    let (separator, rest) = rest.split_at(1);
+    if separator != "T" && separator != " " {
+        return None;
+    }
+
+    let mut date_parts = date.split('-');
+    let year: i64 = number(date_parts.next()?, 4)?;
+    let month: u32 = number(date_parts.next()?, 2)?;
+    let day: u32 = number(date_parts.next()?, 2)?;
+
+    let (time, zone) = rest.split_at(8);
+    let mut time_parts = time.split(':');
+    let hour: i64 = number(time_parts.next()?, 2)?;
+    let minute: i64 = number(time_parts.next()?, 2)?;
+    let second: i64 = number(time_parts.next()?, 2)?;
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let (fraction, zone) = match zone.strip_prefix('.') {
+        Some(rest) => {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        }
+        None => ("", zone),
+    };
+    let mut nanos: i64 = 0;
+    for (i, digit) in fraction.bytes().take(9).enumerate() {
+        nanos += i64::from(digit - b'0') * 10_i64.pow(8 - i as u32);
+    }
+
+    let seconds =
+        days_from_civil(year, month, day) * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second
+            - parse_offset(zone)?;
+    seconds.checked_mul(NANOS_PER_SECOND)?.checked_add(nanos)
+}
+
+// UTC ISO 8601 form of a timestamp, e.g. `2024-01-15T10:30:00.5Z`
+pub fn format_timestamp(nanos: i64) -> String {
+    let seconds = nanos.div_euclid(NANOS_PER_SECOND);
+    let fraction = nanos.rem_euclid(NANOS_PER_SECOND);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+    let time = seconds.rem_euclid(SECONDS_PER_DAY);
+    let mut formatted = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    );
+    if fraction > 0 {
+        formatted.push_str(format!(".{:09}", fraction).trim_end_matches('0'));
+    }
+    formatted.push('Z');
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps() {
+        let day = days_from_civil(2024, 1, 15) * SECONDS_PER_DAY;
+        let at = |seconds: i64| Some((day + seconds) * NANOS_PER_SECOND);
+
+        assert_eq!(parse_timestamp("2024-01-15T10:30:00"), at(37_800));
+        assert_eq!(
+            parse_timestamp("2024-01-15 10:30:00.5"),
+            at(37_800).map(|nanos| nanos + 500_000_000)
+        );
+        assert_eq!(parse_timestamp("2024-01-15T10:30:00+02:00"), at(30_600));
+        assert_eq!(parse_timestamp("2024-01-15T10:30:00Z"), at(37_800));
+        assert_eq!(parse_timestamp("2024-01-15"), None);
+        assert_eq!(parse_timestamp("2024-13-15T10:30:00"), None);
+    }
+
+    #[test]
+    fn parses_dates_within_the_nanosecond_range() {
+        assert_eq!(
+            parse_date("2024-01-15"),
+            Some(days_from_civil(2024, 1, 15) * SECONDS_PER_DAY * NANOS_PER_SECOND)
+        );
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("0000-01-01"), None);
+        assert_eq!(parse_date("9999-12-31"), None);
+        assert_eq!(parse_date("2024-1-15"), None);
+    }
+
+    #[test]
+    fn rejects_days_the_month_does_not_have() {
+        assert_eq!(parse_date("2026-02-30"), None);
+        assert_eq!(parse_date("2026-02-29"), None);
+        assert!(parse_date("2024-02-29").is_some());
+        assert!(parse_date("2000-02-29").is_some());
+        assert_eq!(parse_date("2100-02-29"), None);
+        assert_eq!(parse_date("2026-04-31"), None);
+        assert_eq!(parse_timestamp("2026-02-30T10:00:00"), None);
+    }
+
+    #[test]
+    fn rejects_text_with_multibyte_characters() {
+        assert_eq!(parse_timestamp("123456789é12345678"), None);
+        assert_eq!(parse_timestamp("2024-01-1é 10:30:00"), None);
+        assert_eq!(parse_timestamp("2024-01-15T10:3ż:00"), None);
+        assert_eq!(parse_timestamp("2024-01-15T10:30:00+1ł"), None);
+        assert_eq!(parse_timestamp("Zażółć gęślą jaźń, zróbże"), None);
+        assert_eq!(parse_time_zone("+1é"), None);
+    }
+
+    #[test]
+    fn rejects_timestamps_outside_the_nanosecond_range() {
+        assert_eq!(parse_timestamp("0001-01-01T00:00:00"), None);
+        assert_eq!(parse_timestamp("9999-12-31T23:59:59"), None);
+        assert!(parse_timestamp("1700-01-01T00:00:00").is_some());
+        assert!(parse_timestamp("2262-01-01T00:00:00").is_some());
+    }
+}
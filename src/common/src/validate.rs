@@ -0,0 +1,1057 @@
+// Checks a parsed query against the schema before it is sent to Supabase. LLM output
+// often uses a table's singular name, a synonym instead of the column or `%` wildcards;
+// those are repaired. Anything that can't be repaired safely rejects the whole query,
+// with one issue per wrong part so the caller sees exactly what was wrong.
+
+use crate::error::ApiError;
+use crate::mutation::Mutation;
+use crate::query::{
+    AggregateFunction, Filter, FilterGroup, FilterOperator, FilterValue, PostgrestQuery,
+};
+use crate::schema::{ColumnSchema, ColumnType, Schema, TableSchema};
+use crate::time::{parse_date, parse_timestamp};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct QueryIssue {
+    // The offending part, e.g. `table`, `select titel` or `filter is_done=eq.yes`
+    pub part: String,
+    pub problem: String,
+    // What was changed to fix it; `None` means the query was rejected
+    pub repair: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatedQuery {
+    pub table: String,
+    pub query: PostgrestQuery,
+    pub repairs: Vec<QueryIssue>,
+}
+
+// Returns the query with every repair applied, or every issue found when at least
+// one of them couldn't be repaired
+pub fn validate_query(
+    schema: &Schema,
+    table: &str,
+    query: &PostgrestQuery,
+) -> Result<ValidatedQuery, Vec<QueryIssue>> {
+    let mut issues = Vec::new();
+
+    let Some(table_schema) = resolve_table(schema, table) else {
+        return Err(vec![QueryIssue {
+            part: "table".to_string(),
+            problem: format!(
+                "Unknown table '{}', expected one of: {}",
+                table,
+                schema.table_names().join(", ")
+            ),
+            repair: None,
+        }]);
+    };
+    if table_schema.name != table {
+        issues.push(repaired(
+            "table",
+            format!("Unknown table '{}'", table),
+            format!("used '{}'", table_schema.name),
+        ));
+    }
+
+    let mut validated = query.clone();
+
+    for column in validated.select.iter_mut() {
+        let part = format!("select {}", column);
+        if let Some(name) = check_column(table_schema, column, &part, &mut issues) {
+            *column = name;
+        }
+    }
+
+    for aggregate in validated.aggregates.iter_mut() {
+        let part = format!("select {}", aggregate);
+        let Some(name) = aggregate.column.as_ref() else {
+            continue;
+        };
+        let Some(name) = check_column(table_schema, name, &part, &mut issues) else {
+            continue;
+        };
+        if let Some(column) = table_schema.column(&name) {
+            check_aggregate(column, aggregate.function, &part, &mut issues);
+        }
+        aggregate.column = Some(name);
+    }
+
+    for embed in validated.embeds.iter_mut() {
+        let part = format!("select {}", embed);
+        let Some(related) =
+            check_relation(schema, table_schema, &embed.relation, &part, &mut issues)
+        else {
+            continue;
+        };
+        for column in embed.columns.iter_mut() {
+            if let Some(name) = check_column(related, column, &part, &mut issues) {
+                *column = name;
+            }
+        }
+        embed.relation = related.name.clone();
+    }
+
+    // Filters inside `or`/`and` groups get the same checks as top-level ones
+    let grouped = validated
+        .groups
+        .iter_mut()
+        .flat_map(FilterGroup::filters_mut);
+    for filter in validated.filters.iter_mut().chain(grouped) {
+        let part = format!("filter {}", filter);
+        // `users.name` filters on an embedded table
+        let (filter_table, prefix, column_name) = match filter.column.split_once('.') {
+            Some((relation, column_name)) => {
+                let embedded = resolve_table(schema, relation).filter(|related| {
+                    validated
+                        .embeds
+                        .iter()
+                        .any(|embed| embed.relation == related.name)
+                });
+                let Some(related) = embedded else {
+                    issues.push(rejected(
+                        &part,
+                        format!(
+                            "'{}' is not embedded in the select, so it can't be filtered on",
+                            relation
+                        ),
+                    ));
+                    continue;
+                };
+                (related, format!("{}.", related.name), column_name)
+            }
+            None => (table_schema, String::new(), filter.column.as_str()),
+        };
+        let Some(name) = check_column(filter_table, column_name, &part, &mut issues) else {
+            continue;
+        };
+        filter.column = format!("{}{}", prefix, name);
+        if let Some(column) = filter_table.column(&name) {
+            check_filter(column, filter, &part, &mut issues);
+        }
+    }
+
+    for order in validated.order.iter_mut() {
+        let part = format!("order {}", order);
+        if let Some(name) = check_column(table_schema, &order.column, &part, &mut issues) {
+            order.column = name;
+        }
+    }
+
+    // Aggregated rows only have the grouped columns to sort by
+    if !validated.aggregates.is_empty() {
+        let select = validated.select.clone();
+        validated.order.retain(|order| {
+            let grouped = select.contains(&order.column);
+            if !grouped {
+                issues.push(repaired(
+                    &format!("order {}", order),
+                    format!(
+                        "'{}' is not grouped by, so it can't order aggregated rows",
+                        order.column
+                    ),
+                    "removed the ordering".to_string(),
+                ));
+            }
+            grouped
+        });
+    }
+
+    if issues.iter().any(|issue| issue.repair.is_none()) {
+        return Err(issues);
+    }
+    Ok(ValidatedQuery {
+        table: table_schema.name.clone(),
+        query: validated,
+        repairs: issues,
+    })
+}
+
+// Checks what a mutation sets against the columns of `table`, which `validate_query`
+// already resolved, and that `query` only picks rows: PATCH and DELETE take plain
+// filters, without aggregates or embedded tables
+pub fn validate_mutation(
+    schema: &Schema,
+    table: &str,
+    query: &PostgrestQuery,
+    mutation: &Mutation,
+) -> Result<(Mutation, Vec<QueryIssue>), Vec<QueryIssue>> {
+    let mut issues = Vec::new();
+    let part = format!("mutation {}", mutation);
+
+    if let Err(problem) = mutation.check() {
+        issues.push(rejected(&part, problem));
+    }
+    if !query.aggregates.is_empty() || !query.embeds.is_empty() {
+        issues.push(rejected(
+            &part,
+            "Rows to change can only be picked with filters on the table itself".to_string(),
+        ));
+    }
+    if query.limit.is_some() || query.offset.is_some() {
+        issues.push(rejected(
+            &part,
+            "Changes can't be limited to a number of rows, pick the rows with filters".to_string(),
+        ));
+    }
+    if query.filters.is_empty() && query.groups.is_empty() {
+        issues.push(rejected(
+            &part,
+            "No filter says which rows to change, refusing to change the whole table".to_string(),
+        ));
+    }
+
+    let mut validated = mutation.clone();
+    if let Some(table_schema) = schema.table(table) {
+        for assignment in validated.set.iter_mut() {
+            let part = format!("set {}={}", assignment.column, assignment.value);
+            let Some(name) = check_column(table_schema, &assignment.column, &part, &mut issues)
+            else {
+                continue;
+            };
+            assignment.column = name;
+            let Some(column) = table_schema.column(&assignment.column) else {
+                continue;
+            };
+            // Setting a column to null is fine, the database checks NOT NULL
+            if assignment.value == FilterValue::Null {
+                continue;
+            }
+            let before = assignment.value.clone();
+            match coerce_value(&mut assignment.value, column) {
+                Ok(()) if assignment.value != before => issues.push(repaired(
+                    &part,
+                    format!(
+                        "'{}' is not a {} value",
+                        before,
+                        column.column_type.as_str()
+                    ),
+                    format!("changed to {}", assignment.value),
+                )),
+                Ok(()) => {}
+                Err(problem) => issues.push(rejected(&part, problem)),
+            }
+        }
+    }
+
+    if issues.iter().any(|issue| issue.repair.is_none()) {
+        return Err(issues);
+    }
+    Ok((validated, issues))
+}
+
+// One line per issue, for error messages and logs
+pub fn describe_issues(issues: &[QueryIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| match &issue.repair {
+            Some(repair) => format!("{}: {} ({})", issue.part, issue.problem, repair),
+            None => format!("{}: {}", issue.part, issue.problem),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// Error for a query the validator rejected
+pub fn rejection_error(issues: &[QueryIssue]) -> ApiError {
+    ApiError::invalid_input(format!(
+        "Query doesn't match the schema: {}",
+        describe_issues(issues)
+    ))
+}
+
+fn rejected(part: &str, problem: String) -> QueryIssue {
+    QueryIssue {
+        part: part.to_string(),
+        problem,
+        repair: None,
+    }
+}
+
+fn repaired(part: &str, problem: String, repair: String) -> QueryIssue {
+    QueryIssue {
+        part: part.to_string(),
+        problem,
+        repair: Some(repair),
+    }
+}
+
+fn resolve_table<'a>(schema: &'a Schema, name: &str) -> Option<&'a TableSchema> {
+    let lower = name.to_lowercase();
+    schema
+        .table(name)
+        .or_else(|| {
+            schema
+                .tables
+                .iter()
+                .find(|table| table.terms().contains(&lower))
+        })
+        .or_else(|| closest(&schema.tables, &lower, |table| &table.name))
+}
+
+fn resolve_column<'a>(table: &'a TableSchema, name: &str) -> Option<&'a ColumnSchema> {
+    let lower = name.to_lowercase();
+    table
+        .column(name)
+        .or_else(|| {
+            table.columns.iter().find(|column| {
+                column.name.to_lowercase() == lower
+                    || column
+                        .synonyms
+                        .iter()
+                        .any(|synonym| synonym.to_lowercase().replace(' ', "_") == lower)
+            })
+        })
+        .or_else(|| closest(&table.columns, &lower, |column| &column.name))
+}
+
+// Resolves an embedded table, which has to be related to `table` through a foreign key.
+// Returns the table to check the embedded columns against.
+fn check_relation<'a>(
+    schema: &'a Schema,
+    table: &TableSchema,
+    name: &str,
+    part: &str,
+    issues: &mut Vec<QueryIssue>,
+) -> Option<&'a TableSchema> {
+    match resolve_table(schema, name) {
+        Some(related) if schema.are_related(&table.name, &related.name) => {
+            if related.name != name {
+                issues.push(repaired(
+                    part,
+                    format!("Unknown table '{}'", name),
+                    format!("used '{}'", related.name),
+                ));
+            }
+            Some(related)
+        }
+        _ => {
+            let related: Vec<&str> = schema
+                .related_tables(&table.name)
+                .iter()
+                .map(|related| related.name.as_str())
+                .collect();
+            let expected = if related.is_empty() {
+                "it has no related tables".to_string()
+            } else {
+                format!("expected one of: {}", related.join(", "))
+            };
+            issues.push(rejected(
+                part,
+                format!(
+                    "Table '{}' has no relationship to '{}', {}",
+                    table.name, name, expected
+                ),
+            ));
+            None
+        }
+    }
+}
+
+// Resolves a column name, recording a repair or a rejection. Returns the name to use.
+fn check_column(
+    table: &TableSchema,
+    name: &str,
+    part: &str,
+    issues: &mut Vec<QueryIssue>,
+) -> Option<String> {
+    match resolve_column(table, name) {
+        Some(column) if column.name == name => Some(column.name.clone()),
+        Some(column) => {
+            issues.push(repaired(
+                part,
+                format!("Table '{}' has no column '{}'", table.name, name),
+                format!("used '{}'", column.name),
+            ));
+            Some(column.name.clone())
+        }
+        None => {
+            let columns: Vec<&str> = table.columns.iter().map(|c| c.name.as_str()).collect();
+            issues.push(rejected(
+                part,
+                format!(
+                    "Table '{}' has no column '{}', expected one of: {}",
+                    table.name,
+                    name,
+                    columns.join(", ")
+                ),
+            ));
+            None
+        }
+    }
+}
+
+fn check_filter(
+    column: &ColumnSchema,
+    filter: &mut Filter,
+    part: &str,
+    issues: &mut Vec<QueryIssue>,
+) {
+    let column_type = column.column_type;
+
+    // `eq.null` never matches anything in SQL, `is.null` is what was meant
+    if filter.value == FilterValue::Null
+        && matches!(filter.operator, FilterOperator::Eq | FilterOperator::Neq)
+    {
+        if filter.operator == FilterOperator::Neq {
+            filter.negated = !filter.negated;
+        }
+        filter.operator = FilterOperator::Is;
+        issues.push(repaired(
+            part,
+            "Comparing with null using eq/neq matches no rows".to_string(),
+            format!("changed to {}", filter),
+        ));
+        return;
+    }
+
+    if !operator_allowed(filter.operator, column_type) {
+        issues.push(rejected(
+            part,
+            format!(
+                "Operator '{}' can't be used on {} column '{}'",
+                filter.operator.as_str(),
+                column_type.as_str(),
+                column.name
+            ),
+        ));
+        return;
+    }
+
+    match filter.operator {
+        FilterOperator::Is => {
+            let valid = match filter.value {
+                FilterValue::Null => true,
+                FilterValue::Bool(_) => column_type == ColumnType::Boolean,
+                _ => false,
+            };
+            if !valid {
+                issues.push(rejected(
+                    part,
+                    format!(
+                        "'is' on {} column '{}' only accepts null",
+                        column_type.as_str(),
+                        column.name
+                    ),
+                ));
+            }
+        }
+        FilterOperator::Like | FilterOperator::Ilike => {
+            if let FilterValue::Text(pattern) = &filter.value {
+                if pattern.contains('%') {
+                    filter.value = FilterValue::Text(pattern.replace('%', "*"));
+                    issues.push(repaired(
+                        part,
+                        "PostgREST patterns use '*' as the wildcard, not '%'".to_string(),
+                        format!("changed to {}", filter),
+                    ));
+                }
+            }
+        }
+        FilterOperator::In => {
+            let before = filter.value.clone();
+            let FilterValue::List(values) = &mut filter.value else {
+                issues.push(rejected(part, "'in' expects a list of values".to_string()));
+                return;
+            };
+            let mut problems = Vec::new();
+            for value in values.iter_mut() {
+                if let Err(problem) = coerce_value(value, column) {
+                    problems.push(problem);
+                }
+            }
+            if !problems.is_empty() {
+                issues.push(rejected(part, problems.join(", ")));
+            } else if filter.value != before {
+                issues.push(repaired(
+                    part,
+                    format!(
+                        "'{}' holds values that are not {}",
+                        before,
+                        column_type.as_str()
+                    ),
+                    format!("changed to {}", filter),
+                ));
+            }
+        }
+        _ => {
+            let before = filter.value.clone();
+            match coerce_value(&mut filter.value, column) {
+                Ok(()) if filter.value != before => issues.push(repaired(
+                    part,
+                    format!("'{}' is not a {} value", before, column_type.as_str()),
+                    format!("changed to {}", filter),
+                )),
+                Ok(()) => {}
+                Err(problem) => issues.push(rejected(part, problem)),
+            }
+        }
+    }
+}
+
+// `sum` and `avg` need numbers; `min` and `max` anything with an order
+fn check_aggregate(
+    column: &ColumnSchema,
+    function: AggregateFunction,
+    part: &str,
+    issues: &mut Vec<QueryIssue>,
+) {
+    let allowed = match function {
+        AggregateFunction::Count => true,
+        AggregateFunction::Sum | AggregateFunction::Avg => {
+            matches!(column.column_type, ColumnType::Integer | ColumnType::Float)
+        }
+        AggregateFunction::Min | AggregateFunction::Max => !matches!(
+            column.column_type,
+            ColumnType::Boolean | ColumnType::Json | ColumnType::Uuid
+        ),
+    };
+    if !allowed {
+        issues.push(rejected(
+            part,
+            format!(
+                "'{}' can't be used on {} column '{}'",
+                function.as_str(),
+                column.column_type.as_str(),
+                column.name
+            ),
+        ));
+    }
+}
+
+fn operator_allowed(operator: FilterOperator, column_type: ColumnType) -> bool {
+    use FilterOperator::*;
+
+    match operator {
+        Eq | Neq | In | Is => true,
+        Like | Ilike => column_type == ColumnType::Text,
+        Gt | Gte | Lt | Lte => matches!(
+            column_type,
+            ColumnType::Integer
+                | ColumnType::Float
+                | ColumnType::Text
+                | ColumnType::Timestamp
+                | ColumnType::Date
+        ),
+    }
+}
+
+// Checks that a comparison value fits the column type, converting the values an LLM
+// commonly gets slightly wrong (`yes` or `1` for a boolean)
+fn coerce_value(value: &mut FilterValue, column: &ColumnSchema) -> Result<(), String> {
+    let expected = column.column_type.as_str();
+    let mismatch = |value: &FilterValue| {
+        format!(
+            "'{}' is not a {} value for column '{}'",
+            value, expected, column.name
+        )
+    };
+
+    match (column.column_type, &*value) {
+        (ColumnType::Text, _) | (ColumnType::Json, _) => Ok(()),
+        (_, FilterValue::List(_)) | (_, FilterValue::Null) => Err(mismatch(value)),
+        (ColumnType::Integer, FilterValue::Int(_)) => Ok(()),
+        (ColumnType::Float, FilterValue::Int(_) | FilterValue::Float(_)) => Ok(()),
+        (ColumnType::Boolean, FilterValue::Bool(_)) => Ok(()),
+        (ColumnType::Boolean, FilterValue::Int(int @ (0 | 1))) => {
+            *value = FilterValue::Bool(*int == 1);
+            Ok(())
+        }
+        (ColumnType::Boolean, FilterValue::Text(text)) => {
+            match text.to_lowercase().as_str() {
+                "t" | "yes" | "y" | "on" => *value = FilterValue::Bool(true),
+                "f" | "no" | "n" | "off" => *value = FilterValue::Bool(false),
+                _ => return Err(mismatch(value)),
+            }
+            Ok(())
+        }
+        (ColumnType::Timestamp, FilterValue::Text(text))
+        | (ColumnType::Date, FilterValue::Text(text))
+            if parse_timestamp(text).is_some() || parse_date(text).is_some() =>
+        {
+            Ok(())
+        }
+        (ColumnType::Uuid, FilterValue::Text(text)) if is_uuid(text) => Ok(()),
+        _ => Err(mismatch(value)),
+    }
+}
+
+fn is_uuid(text: &str) -> bool {
+    text.len() == 36
+        && text.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+// The only candidate within two edits of `name`, for typos like `titel` or `is_dne`.
+// Short names are left alone so `id` never turns into another two-letter column.
+fn closest<'a, T>(candidates: &'a [T], name: &str, key: impl Fn(&T) -> &String) -> Option<&'a T> {
+    if name.len() < 4 {
+        return None;
+    }
+    let mut matches = candidates
+        .iter()
+        .filter(|candidate| edit_distance(&key(candidate).to_lowercase(), name) <= 2);
+    let first = matches.next()?;
+    matches.next().is_none().then_some(first)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mutation::Assignment;
+    use crate::query::{Aggregate, Condition, Embed, SortDirection};
+
+    // The example tables plus one with the column types they don't have
+    fn schema() -> Schema {
+        let mut schema = Schema::builtin();
+        schema.tables.push(TableSchema::new(
+            "projects",
+            vec![
+                ColumnSchema::new("id", ColumnType::Uuid),
+                ColumnSchema::new("budget", ColumnType::Float),
+                ColumnSchema::new("starts_on", ColumnType::Date),
+                ColumnSchema::new("settings", ColumnType::Json),
+            ],
+        ));
+        schema
+    }
+
+    fn filtered(filter: Filter) -> PostgrestQuery {
+        PostgrestQuery::new().filter(filter)
+    }
+
+    fn repairs(table: &str, query: PostgrestQuery) -> ValidatedQuery {
+        validate_query(&schema(), table, &query).expect("query should be repaired")
+    }
+
+    fn rejections(table: &str, query: PostgrestQuery) -> Vec<QueryIssue> {
+        validate_query(&schema(), table, &query).expect_err("query should be rejected")
+    }
+
+    #[test]
+    fn accepts_a_matching_query_unchanged() {
+        let query = PostgrestQuery::new()
+            .select(["title", "is_done"])
+            .filter(Filter::eq("is_done", true))
+            .order_by("due_date", SortDirection::Desc)
+            .limit(5);
+
+        let validated = repairs("todos", query.clone());
+
+        assert_eq!(validated.table, "todos");
+        assert_eq!(validated.query, query);
+        assert!(validated.repairs.is_empty());
+    }
+
+    #[test]
+    fn repairs_table_synonyms_singulars_and_typos() {
+        for (table, expected) in [("task", "todos"), ("todo", "todos"), ("usres", "users")] {
+            let validated = repairs(table, PostgrestQuery::new());
+
+            assert_eq!(validated.table, expected);
+            assert_eq!(validated.repairs[0].part, "table");
+            assert_eq!(
+                validated.repairs[0].repair,
+                Some(format!("used '{}'", expected))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_tables() {
+        let issues = rejections("invoices", PostgrestQuery::new());
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].part, "table");
+        assert!(issues[0].problem.contains("todos, users, posts"));
+        assert_eq!(issues[0].repair, None);
+    }
+
+    #[test]
+    fn repairs_column_synonyms_case_and_typos() {
+        let query = PostgrestQuery::new()
+            .select(["Title", "titel"])
+            .filter(Filter::eq("completed", true))
+            .order_by("deadline", SortDirection::Asc);
+
+        let validated = repairs("todos", query);
+
+        assert_eq!(validated.query.select, vec!["title", "title"]);
+        assert_eq!(validated.query.filters[0].column, "is_done");
+        assert_eq!(validated.query.order[0].column, "due_date");
+        assert_eq!(validated.repairs.len(), 4);
+    }
+
+    #[test]
+    fn rejects_unknown_columns_with_one_issue_each() {
+        let query = PostgrestQuery::new()
+            .select(["priority"])
+            .filter(Filter::eq("ix", 1i64))
+            .order_by("rank", SortDirection::Asc);
+
+        let issues = rejections("todos", query);
+
+        let parts: Vec<&str> = issues.iter().map(|issue| issue.part.as_str()).collect();
+        assert_eq!(
+            parts,
+            ["select priority", "filter ix=eq.1", "order rank.asc"]
+        );
+        assert!(issues[0].problem.contains("expected one of: id, title"));
+    }
+
+    #[test]
+    fn checks_aggregate_functions_against_column_types() {
+        let allowed = PostgrestQuery::new()
+            .aggregate(Aggregate::count())
+            .aggregate(Aggregate::of(AggregateFunction::Avg, "budget"))
+            .aggregate(Aggregate::of(AggregateFunction::Max, "starts_on"));
+        assert!(repairs("projects", allowed).repairs.is_empty());
+
+        let issues = rejections(
+            "projects",
+            PostgrestQuery::new()
+                .aggregate(Aggregate::of(AggregateFunction::Sum, "starts_on"))
+                .aggregate(Aggregate::of(AggregateFunction::Min, "id")),
+        );
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].problem.contains("'sum' can't be used on date"));
+        assert!(issues[1].problem.contains("'min' can't be used on uuid"));
+    }
+
+    #[test]
+    fn drops_orderings_aggregated_rows_do_not_have() {
+        let query = PostgrestQuery::new()
+            .select(["status"])
+            .aggregate(Aggregate::count())
+            .order_by("status", SortDirection::Asc)
+            .order_by("title", SortDirection::Asc);
+
+        let validated = repairs("todos", query);
+
+        assert_eq!(validated.query.order.len(), 1);
+        assert_eq!(validated.query.order[0].column, "status");
+        assert_eq!(
+            validated.repairs[0].repair.as_deref(),
+            Some("removed the ordering")
+        );
+    }
+
+    #[test]
+    fn checks_embeds_and_their_filters() {
+        let query = PostgrestQuery::new()
+            .embed(Embed::new("user", ["nmae"]).inner())
+            .filter(Filter::contains("users.name", "alice"));
+
+        let validated = repairs("posts", query);
+
+        assert_eq!(validated.query.embeds[0].relation, "users");
+        assert_eq!(validated.query.embeds[0].columns, vec!["name"]);
+        assert_eq!(validated.repairs.len(), 2);
+    }
+
+    #[test]
+    fn rejects_embeds_of_unrelated_tables() {
+        let issues = rejections(
+            "projects",
+            PostgrestQuery::new().embed(Embed::new("users", ["name"])),
+        );
+
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].problem.contains("it has no related tables"));
+    }
+
+    #[test]
+    fn rejects_filters_on_tables_that_are_not_embedded() {
+        let issues = rejections("posts", filtered(Filter::contains("users.name", "alice")));
+
+        assert!(issues[0].problem.contains("'users' is not embedded"));
+    }
+
+    #[test]
+    fn checks_filters_inside_groups() {
+        let query = PostgrestQuery::new().group(FilterGroup::any(vec![
+            Condition::Filter(Filter::eq("done", "yes")),
+            Condition::Group(FilterGroup::all(vec![Condition::Filter(Filter::eq(
+                "id", "seven",
+            ))])),
+        ]));
+
+        let issues = rejections("todos", query);
+
+        // `done` is the synonym of `is_done` and `yes` a boolean, `seven` isn't a number
+        assert_eq!(issues.len(), 3);
+        assert!(issues[..2].iter().all(|issue| issue.repair.is_some()));
+        assert!(issues[2].problem.contains("'seven' is not a integer value"));
+    }
+
+    #[test]
+    fn turns_comparisons_with_null_into_is() {
+        let validated = repairs("todos", filtered(Filter::eq("due_date", FilterValue::Null)));
+        assert_eq!(validated.query.filters[0], Filter::is_null("due_date"));
+
+        let not_null = Filter::new("due_date", FilterOperator::Neq, FilterValue::Null);
+        let validated = repairs("todos", filtered(not_null));
+        assert_eq!(validated.query.filters[0], Filter::not_null("due_date"));
+        assert_eq!(
+            validated.repairs[0].repair.as_deref(),
+            Some("changed to due_date=not.is.null")
+        );
+    }
+
+    #[test]
+    fn rejects_operators_the_column_type_has_no_use_for() {
+        for (table, filter) in [
+            ("todos", Filter::new("id", FilterOperator::Ilike, "*1*")),
+            ("todos", Filter::new("is_done", FilterOperator::Gt, true)),
+            ("projects", Filter::new("id", FilterOperator::Lt, "a")),
+        ] {
+            let issues = rejections(table, filtered(filter));
+
+            assert!(issues[0].problem.starts_with("Operator"), "{:?}", issues);
+        }
+    }
+
+    #[test]
+    fn accepts_is_with_null_or_a_boolean_only() {
+        assert!(repairs(
+            "todos",
+            filtered(Filter::new("is_done", FilterOperator::Is, true))
+        )
+        .repairs
+        .is_empty());
+
+        let issues = rejections(
+            "todos",
+            filtered(Filter::new("title", FilterOperator::Is, true)),
+        );
+        assert!(issues[0].problem.contains("only accepts null"));
+    }
+
+    #[test]
+    fn turns_percent_wildcards_into_asterisks() {
+        let like = Filter::new("title", FilterOperator::Like, "%dog%");
+
+        let validated = repairs("todos", filtered(like));
+
+        assert_eq!(validated.query.filters[0].value, FilterValue::from("*dog*"));
+        assert_eq!(
+            validated.repairs[0].repair.as_deref(),
+            Some("changed to title=like.*dog*")
+        );
+    }
+
+    #[test]
+    fn coerces_in_lists_and_records_the_repair() {
+        let values = FilterValue::List(vec!["yes".into(), FilterValue::Int(0)]);
+        let filter = Filter::new("is_done", FilterOperator::In, values);
+
+        let validated = repairs("todos", filtered(filter));
+
+        assert_eq!(
+            validated.query.filters[0].value,
+            FilterValue::List(vec![true.into(), false.into()])
+        );
+        assert_eq!(validated.repairs.len(), 1);
+        assert_eq!(
+            validated.repairs[0].repair.as_deref(),
+            Some("changed to is_done=in.(true,false)")
+        );
+    }
+
+    #[test]
+    fn accepts_in_lists_that_already_fit() {
+        let values = FilterValue::List(vec![FilterValue::Int(1), FilterValue::Int(2)]);
+
+        let validated = repairs(
+            "todos",
+            filtered(Filter::new("id", FilterOperator::In, values)),
+        );
+
+        assert!(validated.repairs.is_empty());
+    }
+
+    #[test]
+    fn rejects_in_without_a_list_or_with_wrong_items() {
+        let issues = rejections(
+            "todos",
+            filtered(Filter::new("id", FilterOperator::In, 1i64)),
+        );
+        assert_eq!(issues[0].problem, "'in' expects a list of values");
+
+        let values = FilterValue::List(vec![FilterValue::Int(1), "two".into(), "three".into()]);
+        let issues = rejections(
+            "todos",
+            filtered(Filter::new("id", FilterOperator::In, values)),
+        );
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].problem.contains("'two'"));
+        assert!(issues[0].problem.contains("'three'"));
+    }
+
+    #[test]
+    fn coerces_boolean_words_and_digits() {
+        for (value, expected) in [
+            (FilterValue::from("yes"), true),
+            (FilterValue::from("OFF"), false),
+            (FilterValue::Int(1), true),
+            (FilterValue::Int(0), false),
+        ] {
+            let validated = repairs("todos", filtered(Filter::eq("is_done", value)));
+
+            assert_eq!(
+                validated.query.filters[0].value,
+                FilterValue::Bool(expected)
+            );
+            assert_eq!(validated.repairs.len(), 1);
+        }
+        let issues = rejections(
+            "todos",
+            filtered(Filter::eq("is_done", FilterValue::Int(2))),
+        );
+        assert!(issues[0].problem.contains("is not a boolean value"));
+    }
+
+    #[test]
+    fn checks_values_against_column_types() {
+        for (table, filter) in [
+            ("todos", Filter::eq("id", FilterValue::Int(3))),
+            ("projects", Filter::eq("budget", FilterValue::Int(3))),
+            ("projects", Filter::eq("budget", FilterValue::Float(2.5))),
+            ("todos", Filter::eq("due_date", "2026-03-01T10:00:00Z")),
+            (
+                "todos",
+                Filter::new("due_date", FilterOperator::Lt, "2026-03-01"),
+            ),
+            ("projects", Filter::eq("starts_on", "2026-03-01")),
+            (
+                "projects",
+                Filter::eq("id", "123e4567-e89b-12d3-a456-426614174000"),
+            ),
+            ("projects", Filter::eq("settings", "{}")),
+        ] {
+            let validated = repairs(table, filtered(filter));
+
+            assert!(validated.repairs.is_empty(), "{:?}", validated.repairs);
+        }
+
+        for (table, filter) in [
+            ("todos", Filter::eq("id", FilterValue::Float(2.5))),
+            ("projects", Filter::eq("budget", "lots")),
+            ("todos", Filter::eq("due_date", "tomorrow")),
+            ("projects", Filter::eq("starts_on", "2026-02-30")),
+            ("projects", Filter::eq("id", "123e4567-e89b-12d3-a456")),
+        ] {
+            let issues = rejections(table, filtered(filter));
+
+            assert!(issues[0].problem.contains("is not a"), "{:?}", issues);
+        }
+    }
+
+    #[test]
+    fn repairs_what_an_update_sets() {
+        let mutation = Mutation::update(vec![
+            Assignment::new("completed", "yes"),
+            Assignment::new("due_date", FilterValue::Null),
+        ]);
+
+        let (validated, repairs) = validate_mutation(
+            &schema(),
+            "todos",
+            &filtered(Filter::eq("id", FilterValue::Int(5))),
+            &mutation,
+        )
+        .unwrap();
+
+        assert_eq!(validated.set[0], Assignment::new("is_done", true));
+        assert_eq!(
+            validated.set[1],
+            Assignment::new("due_date", FilterValue::Null)
+        );
+        assert_eq!(repairs.len(), 2);
+    }
+
+    #[test]
+    fn rejects_changes_that_are_not_picked_by_filters() {
+        let by_id = filtered(Filter::eq("id", FilterValue::Int(5)));
+        for (query, mutation, problem) in [
+            (
+                PostgrestQuery::new(),
+                Mutation::delete(),
+                "refusing to change the whole table",
+            ),
+            (
+                by_id.clone().limit(1),
+                Mutation::delete(),
+                "can't be limited",
+            ),
+            (
+                by_id.clone().embed(Embed::new("users", ["name"])),
+                Mutation::delete(),
+                "filters on the table itself",
+            ),
+            (
+                by_id.clone(),
+                Mutation::update(Vec::new()),
+                "needs at least one column",
+            ),
+            (
+                by_id.clone(),
+                Mutation::update(vec![Assignment::new("priority", 1i64)]),
+                "has no column 'priority'",
+            ),
+            (
+                by_id.clone(),
+                Mutation::update(vec![Assignment::new("id", "five")]),
+                "is not a integer value",
+            ),
+        ] {
+            let issues = validate_mutation(&schema(), "todos", &query, &mutation).unwrap_err();
+
+            assert!(
+                issues.iter().any(|issue| issue.problem.contains(problem)),
+                "{:?}",
+                issues
+            );
+        }
+    }
+
+    #[test]
+    fn describes_issues_on_one_line() {
+        let issues = vec![
+            repaired(
+                "table",
+                "Unknown table 'todo'".to_string(),
+                "used 'todos'".to_string(),
+            ),
+            rejected("select priority", "No such column".to_string()),
+        ];
+
+        assert_eq!(
+            describe_issues(&issues),
+            "table: Unknown table 'todo' (used 'todos'); select priority: No such column"
+        );
+        assert_eq!(
+            rejection_error(&issues).code,
+            crate::ErrorCode::InvalidInput
+        );
+    }
+}
@@ -2,6 +2,8 @@ import React, { useState } from "react";
 import {
   querySupabaseWithNaturalLanguage,
   parseNaturalLanguageQuery,
+  formatFilter,
+  QueryParseResult,
 } from "../services/backendService";
 
 interface DataItem {
@@ -28,7 +30,9 @@ export function NaturalLanguageQuery({
   const [query, setQuery] = useState("");
   const [data, setData] = useState<DataItem[]>([]);
   const [lastQuery, setLastQuery] = useState("");
-  const [parseResult, setParseResult] = useState<string>("");
+  const [parseResult, setParseResult] = useState<QueryParseResult | null>(
+    null,
+  );
   const [isLoading, setIsLoading] = useState(false);
 
   const exampleQueries = [
@@ -53,7 +57,7 @@ export function NaturalLanguageQuery({
     try {
       // Show what the query parses to
       const parsed = await parseNaturalLanguageQuery(query);
-      setParseResult(`Table: ${parsed.table}, Query: ${parsed.query}`);
+      setParseResult(parsed);
 
       // Execute the actual query
       const result = await querySupabaseWithNaturalLanguage(query);
@@ -64,7 +68,7 @@ export function NaturalLanguageQuery({
         err instanceof Error ? err.message : "Unknown error occurred";
       onError?.(errorMessage);
       setData([]);
-      setParseResult("");
+      setParseResult(null);
     } finally {
       setIsLoading(false);
       setLoading?.(false);
@@ -160,7 +164,22 @@ export function NaturalLanguageQuery({
                 <p className="mb-1 text-xs font-medium text-yellow-800">
                   Parsed Query:
                 </p>
-                <p className="text-sm text-yellow-700">{parseResult}</p>
+                <p className="text-sm text-yellow-700">
+                  Table: {parseResult.table}, Query: {parseResult.query}
+                </p>
+                {parseResult.parsed[0] &&
+                  parseResult.parsed[0].filters.length > 0 && (
+                    <ul className="mt-2 space-y-1">
+                      {parseResult.parsed[0].filters.map((filter, index) => (
+                        <li
+                          key={index}
+                          className="rounded bg-yellow-100 px-2 py-1 font-mono text-xs text-yellow-800"
+                        >
+                          {formatFilter(filter)}
+                        </li>
+                      ))}
+                    </ul>
+                  )}
               </div>
             )}
 
@@ -5,15 +5,196 @@ export interface ChatMessage {
   content: string;
 }
 
+export type Value =
+  | { Null: null }
+  | { Bool: boolean }
+  | { Int: bigint }
+  | { Float: number }
+  | { Text: string }
+  | { Timestamp: bigint };
+
+export interface Cell {
+  column: string;
+  value: Value;
+}
+
+export type Row = Cell[];
+
+// Result of an aggregate query: one value, or one entry per group
+export interface AnswerGroup {
+  key: Cell[];
+  values: Cell[];
+}
+
+export type Answer = { Scalar: Value } | { Groups: AnswerGroup[] };
+
+// A change asked for in natural language, waiting for confirm_mutation
+export interface MutationPlan {
+  id: bigint;
+  table: string;
+  mutation: Mutation;
+  filter: string;
+  affected_rows: bigint;
+  expires_at: bigint;
+}
+
 export interface SupabaseResponse {
-  data: [] | [string];
-  error: [] | [string];
+  data: string;
+  rows: [] | [Row[]];
+  answer: [] | [Answer];
+  plan: [] | [MutationPlan];
+}
+
+export interface ApiError {
+  code: Record<string, null>;
+  message: string;
+  retryable: boolean;
+  upstream_status: [] | [number];
+}
+
+export type FilterValue =
+  | { Null: null }
+  | { Bool: boolean }
+  | { Int: bigint }
+  | { Float: number }
+  | { Text: string }
+  | { List: FilterValue[] };
+
+export interface Filter {
+  column: string;
+  operator: Record<string, null>;
+  negated: boolean;
+  value: FilterValue;
+}
+
+export interface OrderBy {
+  column: string;
+  direction: { Asc: null } | { Desc: null };
+}
+
+// An `or=(...)`/`and=(...)` parameter, ANDed with the plain filters
+export interface FilterGroup {
+  operator: { And: null } | { Or: null };
+  negated: boolean;
+  conditions: Condition[];
+}
+
+export type Condition = { Filter: Filter } | { Group: FilterGroup };
+
+// `count()` or `column.sum()`; selected columns group it
+export interface Aggregate {
+  function: Record<string, null>;
+  column: [] | [string];
+}
+
+export interface Embed {
+  relation: string;
+  columns: string[];
+  inner: boolean;
+}
+
+export interface PostgrestQuery {
+  select: string[];
+  aggregates: Aggregate[];
+  embeds: Embed[];
+  filters: Filter[];
+  groups: FilterGroup[];
+  order: OrderBy[];
+  limit: [] | [bigint];
+  offset: [] | [bigint];
+}
+
+export interface Assignment {
+  column: string;
+  value: FilterValue;
+}
+
+export interface Mutation {
+  action: { Update: null } | { Delete: null };
+  set: Assignment[];
+}
+
+export interface RpcCall {
+  function: string;
+  args: string;
+}
+
+// A part of a parsed query that didn't match the schema and how it was fixed
+export interface QueryIssue {
+  part: string;
+  problem: string;
+  repair: [] | [string];
 }
 
 export interface QueryParseResult {
   table: string;
   query: string;
-  error: [] | [string];
+  parsed: [] | [PostgrestQuery];
+  rpc: [] | [RpcCall];
+  repairs: [] | [QueryIssue[]];
+  mutation: [] | [Mutation];
+}
+
+export interface SessionMessage {
+  role: string;
+  content: string;
+  at: bigint;
+}
+
+export interface LastQuery {
+  question: string;
+  table: string;
+  query: PostgrestQuery;
+  summary: string;
+}
+
+export interface Session {
+  id: bigint;
+  title: string;
+  created_at: bigint;
+  updated_at: bigint;
+  messages: SessionMessage[];
+  last_query: [] | [LastQuery];
+}
+
+export interface SessionSummary {
+  id: bigint;
+  title: string;
+  created_at: bigint;
+  updated_at: bigint;
+  message_count: bigint;
+  last_query: [] | [LastQuery];
+}
+
+// Timestamps are nanoseconds since the epoch and come back as ISO strings
+export function valueToJson(value: Value): unknown {
+  if ("Null" in value) return null;
+  if ("Bool" in value) return value.Bool;
+  if ("Int" in value) return Number(value.Int);
+  if ("Float" in value) return value.Float;
+  if ("Text" in value) return value.Text;
+  return new Date(Number(value.Timestamp / 1_000_000n)).toISOString();
+}
+
+export function rowToObject(row: Row): Record<string, unknown> {
+  return Object.fromEntries(
+    row.map((cell) => [cell.column, valueToJson(cell.value)]),
+  );
+}
+
+export function formatFilterValue(value: FilterValue): string {
+  if ("Null" in value) return "null";
+  if ("Bool" in value) return String(value.Bool);
+  if ("Int" in value) return value.Int.toString();
+  if ("Float" in value) return String(value.Float);
+  if ("Text" in value) return value.Text;
+  return `(${value.List.map(formatFilterValue).join(", ")})`;
+}
+
+export function formatFilter(filter: Filter): string {
+  const operator = Object.keys(filter.operator)[0].toLowerCase();
+  const negation = filter.negated ? "not " : "";
+  return `${filter.column} ${negation}${operator} ${formatFilterValue(filter.value)}`;
 }
 
 export async function greet(name: string): Promise<string> {
@@ -54,7 +235,13 @@ export async function setCount(value: bigint): Promise<bigint> {
 
 export async function chat(messages: ChatMessage[]): Promise<string> {
   try {
-    return await backend.chat(messages);
+    const result = await backend.chat(messages);
+
+    if ("Ok" in result) {
+      return result.Ok;
+    } else {
+      throw new Error(result.Err.message);
+    }
   } catch (error) {
     console.error("Failed to chat:", error);
     throw error;
@@ -69,19 +256,14 @@ export async function querySupabaseWithNaturalLanguage(
 
     if ("Ok" in result) {
       const response = result.Ok;
-      // Handle optional array types properly
-      if (response.error && response.error.length > 0 && response.error[0]) {
-        throw new Error(response.error[0]);
+      if (response.rows.length > 0 && response.rows[0]) {
+        return response.rows[0].map(rowToObject);
       }
 
-      if (response.data && response.data.length > 0 && response.data[0]) {
-        const data = JSON.parse(response.data[0]);
-        return Array.isArray(data) ? data : [data];
-      }
-
-      return [];
+      const data = JSON.parse(response.data);
+      return Array.isArray(data) ? data : [data];
     } else {
-      throw new Error(result.Err);
+      throw new Error(result.Err.message);
     }
   } catch (error) {
     console.error("Failed to query Supabase with natural language:", error);
@@ -98,10 +280,90 @@ export async function parseNaturalLanguageQuery(
     if ("Ok" in result) {
       return result.Ok;
     } else {
-      throw new Error(result.Err);
+      throw new Error(result.Err.message);
     }
   } catch (error) {
     console.error("Failed to parse natural language query:", error);
     throw error;
   }
 }
+
+export async function confirmMutation(planId: bigint): Promise<SupabaseResponse> {
+  try {
+    const result = await backend.confirm_mutation(planId);
+
+    if ("Ok" in result) {
+      return result.Ok;
+    } else {
+      throw new Error(result.Err.message);
+    }
+  } catch (error) {
+    console.error("Failed to confirm mutation:", error);
+    throw error;
+  }
+}
+
+export async function cancelMutation(planId: bigint): Promise<void> {
+  try {
+    const result = await backend.cancel_mutation(planId);
+
+    if ("Err" in result) {
+      throw new Error(result.Err.message);
+    }
+  } catch (error) {
+    console.error("Failed to cancel mutation:", error);
+    throw error;
+  }
+}
+
+export async function listSessions(): Promise<SessionSummary[]> {
+  try {
+    return await backend.list_sessions();
+  } catch (error) {
+    console.error("Failed to list sessions:", error);
+    throw error;
+  }
+}
+
+export async function startSession(): Promise<SessionSummary> {
+  try {
+    const result = await backend.start_session();
+
+    if ("Ok" in result) {
+      return result.Ok;
+    } else {
+      throw new Error(result.Err.message);
+    }
+  } catch (error) {
+    console.error("Failed to start session:", error);
+    throw error;
+  }
+}
+
+export async function resumeSession(sessionId: bigint): Promise<Session> {
+  try {
+    const result = await backend.resume_session(sessionId);
+
+    if ("Ok" in result) {
+      return result.Ok;
+    } else {
+      throw new Error(result.Err.message);
+    }
+  } catch (error) {
+    console.error("Failed to resume session:", error);
+    throw error;
+  }
+}
+
+export async function deleteSession(sessionId: bigint): Promise<void> {
+  try {
+    const result = await backend.delete_session(sessionId);
+
+    if ("Err" in result) {
+      throw new Error(result.Err.message);
+    }
+  } catch (error) {
+    console.error("Failed to delete session:", error);
+    throw error;
+  }
+}
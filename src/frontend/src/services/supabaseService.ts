@@ -12,24 +12,15 @@ export async function fetchSupabaseData(
   try {
     const response = await backend.fetch_from_supabase(table, query);
     if ("Ok" in response) {
-      const result = response.Ok;
-
-      // Handle Candid optional types: [] | [T] where [] means None and [T] means Some(T)
-      const data = result.data.length > 0 ? result.data[0] : null;
-      const error = result.error.length > 0 ? result.error[0] : undefined;
-
-      if (data) {
-        try {
-          const parsedData = JSON.parse(data);
-          return { data: parsedData, error };
-        } catch {
-          // If parsing fails, return as string
-          return { data, error };
-        }
+      const data = response.Ok.data;
+      try {
+        return { data: JSON.parse(data) };
+      } catch {
+        // If parsing fails, return as string
+        return { data };
       }
-      return { data: null, error };
     } else {
-      return { error: response.Err };
+      return { error: response.Err.message };
     }
   } catch (error) {
     console.error("Failed to fetch from Supabase:", error);
@@ -45,24 +36,15 @@ export async function insertSupabaseData(
     const jsonData = JSON.stringify(data);
     const response = await backend.insert_to_supabase(table, jsonData);
     if ("Ok" in response) {
-      const result = response.Ok;
-
-      // Handle Candid optional types: [] | [T] where [] means None and [T] means Some(T)
-      const data = result.data.length > 0 ? result.data[0] : null;
-      const error = result.error.length > 0 ? result.error[0] : undefined;
-
-      if (data) {
-        try {
-          const parsedData = JSON.parse(data);
-          return { data: parsedData, error };
-        } catch {
-          // If parsing fails, return as string
-          return { data, error };
-        }
+      const data = response.Ok.data;
+      try {
+        return { data: JSON.parse(data) };
+      } catch {
+        // If parsing fails, return as string
+        return { data };
       }
-      return { data: null, error };
     } else {
-      return { error: response.Err };
+      return { error: response.Err.message };
     }
   } catch (error) {
     console.error("Failed to insert to Supabase:", error);
@@ -0,0 +1,98 @@
+// Configuration for the external LLM APIs
+// The Groq API key and the provider chain are set through the init/upgrade argument
+// or the controller-only setters and kept in stable memory instead of being compiled
+// into the wasm
+
+use crate::provider::LlmSettings;
+use crate::storage::{self, Candid, Memory};
+use candid::{CandidType, Deserialize};
+use common::{mask_secret, ApiError};
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct StoredConfig {
+    groq_api_key: Option<String>,
+    llm_settings: Option<LlmSettings>,
+}
+
+// Init and upgrade argument. Fields left as `null` keep their current value.
+#[derive(CandidType, Deserialize, Default)]
+pub struct InitArgs {
+    pub groq_api_key: Option<String>,
+    pub llm_settings: Option<LlmSettings>,
+}
+
+// Configuration as shown to controllers, with secrets masked
+#[derive(CandidType, Deserialize)]
+pub struct ConfigView {
+    pub groq_api_key: Option<String>,
+    pub llm_settings: LlmSettings,
+}
+
+thread_local! {
+    static CONFIG: RefCell<StableCell<Candid<StoredConfig>, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::CONFIG_MEMORY_ID), Candid::default())
+            .expect("failed to initialize config in stable memory"),
+    );
+}
+
+pub struct Config;
+
+impl Config {
+    pub fn groq_api_key() -> Result<String, ApiError> {
+        Self::read(|config| config.groq_api_key.clone()).ok_or_else(|| {
+            ApiError::config("GROQ_API_KEY is not configured, call set_groq_api_key")
+        })
+    }
+
+    pub fn apply(args: InitArgs) -> Result<(), ApiError> {
+        if let Some(api_key) = args.groq_api_key {
+            Self::set_groq_api_key(api_key)?;
+        }
+        if let Some(settings) = args.llm_settings {
+            Self::set_llm_settings(settings)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_groq_api_key(api_key: String) -> Result<(), ApiError> {
+        let api_key = api_key.trim().to_string();
+        if api_key.is_empty() {
+            return Err(ApiError::invalid_input("Groq API key must not be empty"));
+        }
+        Self::update(|config| config.groq_api_key = Some(api_key))
+    }
+
+    // Groq with the model and sampling options that used to be hardcoded, until set
+    pub fn llm_settings() -> LlmSettings {
+        Self::read(|config| config.llm_settings.clone().unwrap_or_default())
+    }
+
+    pub fn set_llm_settings(settings: LlmSettings) -> Result<(), ApiError> {
+        settings.validate()?;
+        Self::update(|config| config.llm_settings = Some(settings))
+    }
+
+    pub fn view() -> ConfigView {
+        Self::read(|config| ConfigView {
+            groq_api_key: config.groq_api_key.as_deref().map(mask_secret),
+            llm_settings: config.llm_settings.clone().unwrap_or_default().masked(),
+        })
+    }
+
+    fn read<R>(f: impl FnOnce(&StoredConfig) -> R) -> R {
+        CONFIG.with(|cell| f(&cell.borrow().get().0))
+    }
+
+    fn update(f: impl FnOnce(&mut StoredConfig)) -> Result<(), ApiError> {
+        CONFIG.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let mut config = cell.get().0.clone();
+            f(&mut config);
+            cell.set(Candid(config))
+                .map(|_| ())
+                .map_err(|e| ApiError::storage(format!("Failed to store config: {:?}", e)))
+        })
+    }
+}
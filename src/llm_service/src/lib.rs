@@ -1,8 +1,18 @@
 use candid::{CandidType, Deserialize};
-use ic_cdk::api::management_canister::http_request::{
-    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
-    TransformContext,
+use common::rpc::validate_function_name;
+use common::time::{format_date, parse_time_zone, NANOS_PER_SECOND, SECONDS_PER_DAY};
+use common::validate::describe_issues;
+use common::{
+    validate_mutation, validate_query, ApiError, Assignment, FilterValue, Mutation, ParseContext,
+    PostgrestQuery, QueryParseResult,
 };
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+
+mod config;
+mod provider;
+mod storage;
+use config::{Config, ConfigView, InitArgs};
+use provider::LlmSettings;
 
 #[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct ChatMessage {
@@ -20,51 +30,127 @@ pub enum ChatRole {
     System,
 }
 
-#[derive(CandidType, Deserialize, Debug)]
-pub struct QueryParseResult {
-    pub table: String,
-    pub query: String,
-    pub error: Option<String>,
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    apply_init_args(args);
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    apply_init_args(args);
+}
+
+fn apply_init_args(args: Option<InitArgs>) {
+    if let Some(args) = args {
+        if let Err(e) = Config::apply(args) {
+            ic_cdk::trap(&format!("Invalid init arguments: {}", e));
+        }
+    }
+}
+
+fn ensure_controller() -> Result<(), ApiError> {
+    if ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(ApiError::unauthorized(
+            "Only canister controllers can call this method",
+        ))
+    }
+}
+
+#[ic_cdk::update]
+fn set_groq_api_key(api_key: String) -> Result<(), ApiError> {
+    ensure_controller()?;
+    Config::set_groq_api_key(api_key)
+}
+
+#[ic_cdk::update]
+fn set_llm_settings(settings: LlmSettings) -> Result<(), ApiError> {
+    ensure_controller()?;
+    Config::set_llm_settings(settings)
+}
+
+#[ic_cdk::query]
+fn get_config() -> Result<ConfigView, ApiError> {
+    ensure_controller()?;
+    Ok(Config::view())
+}
+
+// Used by the backend to verify it is talking to a live llm_service canister
+#[ic_cdk::query]
+fn health() -> String {
+    "ok".to_string()
 }
 
 // Główna funkcja do parsowania natural language na SQL
 #[ic_cdk::update]
-async fn parse_natural_language_to_sql(user_query: String) -> Result<QueryParseResult, String> {
+async fn parse_natural_language_to_sql(
+    user_query: String,
+    context: Option<ParseContext>,
+) -> Result<QueryParseResult, ApiError> {
     ic_cdk::println!("Parsing query: {}", user_query);
+    let mut context = context.unwrap_or_default();
+    context.now.get_or_insert_with(ic_cdk::api::time);
 
     // Stwórz prompt systemowy dla SQL parsing
-    let system_prompt = r#"You are a SQL query generator for a PostgreSQL database accessed via Supabase REST API.
+    let system_prompt = format!(
+        r#"You are a SQL query generator for a PostgreSQL database accessed via Supabase REST API.
 
 Database schema:
-- todos: id (integer), title (text), description (text), is_done (boolean), due_date (timestamp), status (text), created_at (timestamp)
-- users: id (integer), name (text), email (text), created_at (timestamp)  
-- posts: id (integer), title (text), content (text), user_id (integer), created_at (timestamp)
+{}
 
-Convert natural language to Supabase PostgREST format:
-- "select=*" for all columns
-- "select=id,title" for specific columns  
-- "is_done=eq.true" for boolean filters
-- "due_date=not.is.null" for non-null filters
-- "due_date=is.null" for null filters
-- "title=ilike.*search*" for text search (ALWAYS use asterisks * not percent signs %)
+Only use the tables and columns listed above.
 
-IMPORTANT: For text search, ALWAYS use asterisks (*) format: "title=ilike.*word*"
-NEVER use percent signs (%) format: "title=ilike.%word%"
+Convert natural language to Supabase PostgREST format, with column_a, column_b and other_table standing for columns and tables listed above:
+- "select=*" for all columns
+- "select=column_a,column_b" for specific columns
+- "select=count()" to count rows, "select=column_a.sum()" (or .avg(), .min(), .max()) to aggregate a column; columns selected next to an aggregate group it, e.g. "select=column_a,count()" counts per value of column_a
+- "select=*,other_table(column_a,column_b)" to add the related rows of a table joined by a "references" column, "other_table!inner(*)" to keep only rows that have a match; filter on them with "other_table.column_a=ilike.*ann*", only embed tables related to the queried one
+- "column_a=eq.true" for boolean filters
+- "column_a=not.is.null" for non-null filters
+- "column_a=is.null" for null filters
+- "column_a=ilike.*search*" for text search (ALWAYS use asterisks * not percent signs %)
+- "column_a=not.ilike.*work*" to negate any filter, put "not." before the operator
+- "or=(column_a.eq.true,column_b.is.null)" for alternatives; inside "or=(...)" and "and=(...)" write filters as column.operator.value and nest groups as or(...), and(...) or not.and(...)
+- Separate filters joined with "&" must all match
+- "order=column_a.asc,column_b.desc" to sort by several columns, "limit=20" for at most 20 rows and "offset=40" to skip rows; "page 3" with 20 per page is "limit=20&offset=40", 10 per page when no size is given
+- Dates are UTC timestamps: turn "due this week", "created yesterday" or "before March 3" into a range on a date or timestamp column, e.g. "column_a=gte.2026-01-05T00:00:00Z&column_a=lt.2026-01-06T00:00:00Z"
+- {}
+
+IMPORTANT: For text search, ALWAYS use asterisks (*) format: "column_a=ilike.*word*"
+NEVER use percent signs (%) format: "column_a=ilike.%word%"
 
 Respond ONLY with JSON in this exact format:
-{"table": "table_name", "query": "supabase_query_string", "error": null}
+{{"table": "table_name", "query": "supabase_query_string", "error": null}}
+
+To change rows instead of reading them, add "action": "update" with the new values in "set", or "action": "delete"; "query" then only holds the filters that pick the rows, never limit or order:
+{{"table": "table_name", "query": "select=*&column_a=eq.5", "action": "update", "set": {{"column_b": "new value"}}, "error": null}}
 
 Examples:
-"get all todos" → {"table": "todos", "query": "select=*", "error": null}
-"show completed todos" → {"table": "todos", "query": "select=*&is_done=eq.true", "error": null}
-"find incomplete todos" → {"table": "todos", "query": "select=*&is_done=eq.false", "error": null}
-"show todos with title like dog" → {"table": "todos", "query": "select=*&title=ilike.*dog*", "error": null}
-"find todos containing work" → {"table": "todos", "query": "select=*&title=ilike.*work*", "error": null}"#;
+{}"#,
+        context.schema().describe(),
+        local_date(&context),
+        context.schema().examples()
+    );
+
+    let system_prompt = if context.allowed_rpcs.is_empty() {
+        system_prompt
+    } else {
+        format!(
+            r#"{}
+
+Some questions are answered by Postgres functions instead of tables. Allowed functions: {}
+To call one, respond with {{"rpc": "function_name", "args": {{"param": "value"}}, "error": null}}
+Never call a function that is not in this list."#,
+            system_prompt,
+            context.allowed_rpcs.join(", ")
+        )
+    };
 
-    // Przygotuj wiadomości dla Groq
+    // Przygotuj wiadomości dla LLM
     let messages = vec![
         ChatMessage {
-            content: system_prompt.to_string(),
+            content: system_prompt,
             role: ChatRole::System,
         },
         ChatMessage {
@@ -73,285 +159,218 @@ Examples:
         },
     ];
 
-    // Spróbuj wywołać Groq API
-    match call_groq_api(messages).await {
-        Ok(llm_response) => {
-            // Sparsuj odpowiedź JSON
-            match serde_json::from_str::<QueryParseResult>(&llm_response) {
-                Ok(result) => {
-                    ic_cdk::println!(
-                        "Successfully parsed via Groq: table={}, query={}",
-                        result.table,
-                        result.query
-                    );
-                    Ok(result)
-                }
-                Err(_) => {
-                    ic_cdk::println!("Failed to parse Groq response, using fallback");
-                    parse_query_smart_fallback(user_query).await
-                }
-            }
+    // Providers whose output can't be parsed are skipped like failing ones
+    match provider::complete_with_fallback(&messages, |output| parse_llm_output(output, &context))
+        .await
+    {
+        Ok(Ok(result)) => {
+            ic_cdk::println!(
+                "Successfully parsed via LLM: table={}, query={}",
+                result.table,
+                result.query
+            );
+            Ok(result)
         }
+        Ok(Err(error)) => Err(error),
         Err(error) => {
-            ic_cdk::println!("Groq API failed: {}, using fallback", error);
-            parse_query_smart_fallback(user_query).await
+            ic_cdk::println!("All LLM providers failed: {}, using fallback", error);
+            nl_parser::parse(&user_query, &context)
         }
     }
 }
 
-// Wywołanie Groq API dla bardzo szybkiego LLM
-async fn call_groq_api(messages: Vec<ChatMessage>) -> Result<String, String> {
-    let api_url = "https://api.groq.com/openai/v1/chat/completions";
-
-    // Use environment variable for Groq API key
-    let groq_api_key =
-        option_env!("GROQ_API_KEY").ok_or("GROQ_API_KEY environment variable not set")?;
-
-    // Przygotuj payload dla Groq API
-    let payload = serde_json::json!({
-        "model": "llama-3.1-8b-instant", // Bardzo szybki model Groq
-        "messages": messages.iter().map(|msg| {
-            serde_json::json!({
-                "role": match msg.role {
-                    ChatRole::System => "system",
-                    ChatRole::User => "user",
-                    ChatRole::Assistant => "assistant"
-                },
-                "content": msg.content
-            })
-        }).collect::<Vec<_>>(),
-        "temperature": 0.1,
-        "max_tokens": 300,
-        "top_p": 1.0,
-        "stream": false
-    });
-
-    ic_cdk::println!("Calling Groq API with model: llama-3.1-8b-instant");
-
-    // Wykonaj HTTP request do Groq
-    let request = CanisterHttpRequestArgument {
-        url: api_url.to_string(),
-        method: HttpMethod::POST,
-        body: Some(payload.to_string().into_bytes()),
-        max_response_bytes: Some(2048),
-        transform: Some(TransformContext::from_name(
-            "transform".to_string(),
-            serde_json::json!({}).to_string().into_bytes(),
-        )),
-        headers: vec![
-            HttpHeader {
-                name: "Content-Type".to_string(),
-                value: "application/json".to_string(),
-            },
-            HttpHeader {
-                name: "Authorization".to_string(),
-                value: format!("Bearer {}", groq_api_key),
-            },
-        ],
-    };
-
-    match http_request(request, 25_000_000_000).await {
-        Ok((response,)) => {
-            let response_body = String::from_utf8(response.body)
-                .map_err(|_| "Invalid response encoding".to_string())?;
-
-            ic_cdk::println!("Groq API response: {}", response_body);
-
-            // Sparsuj odpowiedź Groq API
-            let api_response: serde_json::Value = serde_json::from_str(&response_body)
-                .map_err(|_| "Failed to parse Groq API response".to_string())?;
-
-            let content = api_response["choices"][0]["message"]["content"]
-                .as_str()
-                .ok_or("No content in Groq API response")?;
+// Today's date for the prompt, in the time zone questions are asked in
+fn local_date(context: &ParseContext) -> String {
+    let zone = context.time_zone.as_deref().unwrap_or("UTC");
+    let offset = parse_time_zone(zone).unwrap_or(0);
+    let now = context.now.unwrap_or_default() as i64 / NANOS_PER_SECOND;
+    format!(
+        "Today is {} in time zone {}, days start at midnight there",
+        format_date((now + offset).div_euclid(SECONDS_PER_DAY)),
+        zone
+    )
+}
 
-            Ok(content.to_string())
-        }
-        Err((code, message)) => {
-            ic_cdk::println!("Groq HTTP request failed: {} - {}", code as u32, message);
-            Err(format!("Groq API call failed: {}", message))
-        }
-    }
+// Shape of the JSON the LLM is asked to produce
+#[derive(Deserialize)]
+struct LlmOutput {
+    table: Option<String>,
+    query: Option<String>,
+    rpc: Option<String>,
+    args: Option<serde_json::Value>,
+    // `update` or `delete` for questions that change rows
+    action: Option<String>,
+    set: Option<serde_json::Map<String, serde_json::Value>>,
+    error: Option<String>,
 }
 
-// Bardzo inteligentny fallback parser bez potrzeby zewnętrznego LLM
-async fn parse_query_smart_fallback(user_query: String) -> Result<QueryParseResult, String> {
-    let query_lower = user_query.to_lowercase();
-    ic_cdk::println!("Smart parsing: {}", query_lower);
-
-    // Rozpoznaj tabelę
-    let table = if query_lower.contains("todo") || query_lower.contains("task") {
-        "todos"
-    } else if query_lower.contains("user") {
-        "users"
-    } else if query_lower.contains("post") {
-        "posts"
-    } else {
-        // Domyślnie todos dla zapytań ogólnych
-        "todos"
+// The change an `action` and `set` ask for
+fn llm_mutation(output: &LlmOutput) -> Result<Option<Mutation>, String> {
+    let set = || -> Result<Vec<Assignment>, String> {
+        output
+            .set
+            .iter()
+            .flatten()
+            .map(|(column, value)| {
+                let value = match value {
+                    serde_json::Value::Null => FilterValue::Null,
+                    serde_json::Value::Bool(value) => FilterValue::Bool(*value),
+                    serde_json::Value::Number(number) => match number.as_i64() {
+                        Some(int) => FilterValue::Int(int),
+                        None => FilterValue::Float(number.as_f64().unwrap_or_default()),
+                    },
+                    serde_json::Value::String(text) => FilterValue::Text(text.clone()),
+                    _ => return Err(format!("'{}' must be set to a single value", column)),
+                };
+                Ok(Assignment::new(column.as_str(), value))
+            })
+            .collect()
     };
-
-    // Zbuduj zapytanie Supabase
-    let mut query_parts: Vec<String> = vec![];
-
-    // Określ kolumny do wyboru
-    if query_lower.contains("only id") || query_lower.contains("just id") {
-        query_parts.push("select=id".to_string());
-    } else if query_lower.contains("only title") || query_lower.contains("just title") {
-        query_parts.push("select=title".to_string());
-    } else if query_lower.contains("id and title") {
-        query_parts.push("select=id,title".to_string());
-    } else {
-        query_parts.push("select=*".to_string());
+    match output.action.as_deref() {
+        None => Ok(None),
+        Some("update") => Ok(Some(Mutation::update(set()?))),
+        Some("delete") => Ok(Some(Mutation::delete())),
+        Some(other) => Err(format!("unknown action '{}'", other)),
     }
+}
 
-    // Filtry dla todos
-    if table == "todos" {
-        // Status filters
-        if query_lower.contains("completed")
-            || query_lower.contains("done")
-            || query_lower.contains("finished")
-        {
-            query_parts.push("is_done=eq.true".to_string());
-        } else if query_lower.contains("incomplete")
-            || query_lower.contains("not done")
-            || query_lower.contains("pending")
-            || query_lower.contains("unfinished")
-        {
-            query_parts.push("is_done=eq.false".to_string());
-        }
-
-        // Due date filters
-        if query_lower.contains("with due date")
-            || (query_lower.contains("due") && !query_lower.contains("no due"))
-        {
-            query_parts.push("due_date=not.is.null".to_string());
-        } else if query_lower.contains("no due date") || query_lower.contains("without due date") {
-            query_parts.push("due_date=is.null".to_string());
-        }
-
-        // Specific status
-        if query_lower.contains("status") {
-            if query_lower.contains("active") {
-                query_parts.push("status=eq.active".to_string());
-            } else if query_lower.contains("archived") {
-                query_parts.push("status=eq.archived".to_string());
-            }
-        }
+// Turns the raw LLM JSON into a result whose query is well-formed PostgREST and matches
+// the schema (after repairs) or whose function is on the allowlist. The outer `Err` rejects the output itself, the
+// inner one is the LLM telling us the question can't be answered.
+fn parse_llm_output(
+    llm_response: &str,
+    context: &ParseContext,
+) -> Result<Result<QueryParseResult, ApiError>, String> {
+    let output = serde_json::from_str::<LlmOutput>(llm_response)
+        .map_err(|e| format!("invalid JSON: {}", e))?;
+
+    if let Some(error) = output.error {
+        return Ok(Err(ApiError::parse(error)));
+    }
 
-        // Text search in title - handle specific patterns first
-        if query_lower.contains("title contains") || query_lower.contains("title like") {
-            // Extract the search term after "title contains" or "title like"
-            let patterns = ["title contains ", "title like "];
-            for pattern in &patterns {
-                if let Some(pos) = query_lower.find(pattern) {
-                    let remainder = &query_lower[pos + pattern.len()..];
-                    let search_term = if let Some(word_end) = remainder.find(' ') {
-                        remainder[..word_end].to_string()
-                    } else {
-                        remainder.to_string()
-                    };
-                    if !search_term.is_empty() {
-                        query_parts.push(format!("title=ilike.*{}*", search_term));
-                        break;
-                    }
-                }
-            }
-        }
-        // General text search fallback
-        else if let Some(search_term) = extract_search_term(&query_lower) {
-            query_parts.push(format!("title=ilike.*{}*", search_term));
+    if let Some(function) = output.rpc {
+        validate_function_name(&function)?;
+        if !context.allowed_rpcs.contains(&function) {
+            return Err(format!("function '{}' is not allowlisted", function));
         }
-
-        // ID specific queries
-        if let Some(id) = extract_id(&query_lower) {
-            query_parts.push(format!("id=eq.{}", id));
+        let args = output.args.unwrap_or_else(|| serde_json::json!({}));
+        if !args.is_object() {
+            return Err("function arguments must be a JSON object".to_string());
         }
+        return Ok(Ok(QueryParseResult::rpc(function, args.to_string())));
     }
 
-    // Sortowanie
-    if query_lower.contains("latest")
-        || query_lower.contains("newest")
-        || query_lower.contains("recent")
-    {
-        query_parts.push("order=created_at.desc".to_string());
-    } else if query_lower.contains("oldest") || query_lower.contains("first") {
-        query_parts.push("order=created_at.asc".to_string());
-    }
-
-    // Limit
-    if query_lower.contains("first 5") || query_lower.contains("top 5") {
-        query_parts.push("limit=5".to_string());
-    } else if query_lower.contains("first 10") || query_lower.contains("top 10") {
-        query_parts.push("limit=10".to_string());
-    }
-
-    let final_query = query_parts.join("&");
-
-    Ok(QueryParseResult {
-        table: table.to_string(),
-        query: final_query,
-        error: None,
-    })
+    let mutation = llm_mutation(&output)?;
+    let table = output.table.ok_or("missing table")?;
+    let query: PostgrestQuery = output.query.unwrap_or_default().parse()?;
+    let schema = context.schema();
+    let mut validated =
+        validate_query(&schema, &table, &query).map_err(|issues| describe_issues(&issues))?;
+    let Some(mutation) = mutation else {
+        return Ok(Ok(QueryParseResult::success(
+            validated.table,
+            validated.query,
+        )
+        .with_repairs(validated.repairs)));
+    };
+    let (mutation, repairs) =
+        validate_mutation(&schema, &validated.table, &validated.query, &mutation)
+            .map_err(|issues| describe_issues(&issues))?;
+    validated.repairs.extend(repairs);
+    Ok(Ok(QueryParseResult::success(
+        validated.table,
+        validated.query,
+    )
+    .with_repairs(validated.repairs)
+    .with_mutation(mutation)))
 }
 
-// Funkcja pomocnicza do wyciągania terminu wyszukiwania
-fn extract_search_term(query: &str) -> Option<String> {
-    // Proste parsowanie terminu w cudzysłowach
-    if let Some(start) = query.find('"') {
-        if let Some(end) = query[start + 1..].find('"') {
-            return Some(query[start + 1..start + 1 + end].to_string());
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::ErrorCode;
+
+    fn parse(output: &str) -> Result<Result<QueryParseResult, ApiError>, String> {
+        let context = ParseContext {
+            allowed_rpcs: vec!["search_todos".to_string()],
+            ..ParseContext::default()
+        };
+        parse_llm_output(output, &context)
     }
 
-    // Szukaj po słowach kluczowych
-    for keyword in &[
-        "with ",
-        "containing ",
-        "contains ",
-        "about ",
-        "titled ",
-        "named ",
-        "like ",
-    ] {
-        if let Some(pos) = query.find(keyword) {
-            let remainder = &query[pos + keyword.len()..];
-            if let Some(word_end) = remainder.find(' ') {
-                return Some(remainder[..word_end].to_string());
-            } else {
-                return Some(remainder.to_string());
-            }
-        }
+    #[test]
+    fn accepts_queries_that_match_the_schema() {
+        let result = parse(r#"{"table": "todos", "query": "select=*&is_done=eq.false"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.table, "todos");
+        assert_eq!(result.query, "select=*&is_done=eq.false");
+        assert_eq!(result.mutation, None);
     }
 
-    None
-}
-
-// Funkcja pomocnicza do wyciągania ID
-fn extract_id(query: &str) -> Option<u32> {
-    // Szukaj "id 1", "with id 5", etc.
-    for keyword in &["id ", "with id ", "having id "] {
-        if let Some(pos) = query.find(keyword) {
-            let remainder = &query[pos + keyword.len()..];
-            if let Some(word_end) = remainder.find(' ') {
-                if let Ok(id) = remainder[..word_end].parse::<u32>() {
-                    return Some(id);
-                }
-            } else if let Ok(id) = remainder.parse::<u32>() {
-                return Some(id);
-            }
-        }
+    #[test]
+    fn passes_on_questions_the_llm_could_not_answer() {
+        let error = parse(r#"{"error": "That is not about todos"}"#)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::Parse);
+        assert_eq!(error.message, "That is not about todos");
     }
 
-    // Szukaj liczby na końcu zdania
-    let words: Vec<&str> = query.split_whitespace().collect();
-    if let Some(last_word) = words.last() {
-        if let Ok(id) = last_word.parse::<u32>() {
-            return Some(id);
+    #[test]
+    fn rejects_output_that_is_malformed_or_off_the_schema() {
+        for (output, problem) in [
+            ("select * from todos", "invalid JSON"),
+            (r#"{"query": "select=*"}"#, "missing table"),
+            (
+                r#"{"table": "todos", "query": "select=*&colour=eq.red"}"#,
+                "colour",
+            ),
+            (
+                r#"{"table": "todos", "action": "truncate"}"#,
+                "unknown action 'truncate'",
+            ),
+            (
+                r#"{"table": "todos", "action": "update", "set": {"title": ["a", "b"]}}"#,
+                "'title' must be set to a single value",
+            ),
+            (
+                r#"{"rpc": "drop_everything"}"#,
+                "function 'drop_everything' is not allowlisted",
+            ),
+            (
+                r#"{"rpc": "search_todos", "args": [1, 2]}"#,
+                "function arguments must be a JSON object",
+            ),
+        ] {
+            let error = parse(output).unwrap_err();
+            assert!(error.contains(problem), "{}: {}", output, error);
         }
     }
 
-    None
+    #[test]
+    fn reads_calls_and_changes() {
+        let result = parse(r#"{"rpc": "search_todos", "args": {"term": "milk"}}"#)
+            .unwrap()
+            .unwrap();
+        let call = result.rpc.unwrap();
+        assert_eq!(call.function, "search_todos");
+        assert_eq!(call.args, r#"{"term":"milk"}"#);
+
+        let result = parse(
+            r#"{"table": "todos", "query": "select=*&id=eq.5", "action": "update", "set": {"is_done": true}}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(result.query, "select=*&id=eq.5");
+        assert_eq!(
+            result.mutation,
+            Some(Mutation::update(vec![Assignment::new(
+                "is_done",
+                FilterValue::Bool(true)
+            )]))
+        );
+    }
 }
 
 // Funkcja transformacji dla HTTP response
@@ -0,0 +1,592 @@
+// LLM backends behind one interface. Controllers configure an ordered chain of
+// providers and each request goes down the chain until one gives a usable answer.
+
+use crate::config::Config;
+use crate::{ChatMessage, ChatRole};
+use candid::{CandidType, Deserialize, Principal};
+use common::{mask_secret, ApiError};
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, TransformContext,
+};
+
+const GROQ_BASE_URL: &str = "https://api.groq.com/openai/v1";
+const OUTCALL_CYCLES: u128 = 25_000_000_000;
+const MAX_RESPONSE_BYTES: u64 = 8192;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProviderConfig {
+    // api.groq.com, authenticated with the key set by `set_groq_api_key`
+    Groq {
+        model: String,
+    },
+    // Any server implementing `POST {base_url}/chat/completions`, e.g. OpenAI or a
+    // local llama.cpp/Ollama server
+    OpenAiCompatible {
+        base_url: String,
+        model: String,
+        api_key: Option<String>,
+    },
+    // `v0_chat` of the on-chain `llm` canister
+    OnChain {
+        canister_id: Principal,
+        model: String,
+    },
+    // Always answers with `response`, for tests
+    Mock {
+        response: String,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct LlmSettings {
+    // Tried in order until one answers with usable output
+    pub providers: Vec<ProviderConfig>,
+    pub temperature: f64,
+    pub max_tokens: u32,
+}
+
+impl Default for LlmSettings {
+    fn default() -> Self {
+        Self {
+            providers: vec![ProviderConfig::Groq {
+                model: "llama-3.1-8b-instant".to_string(),
+            }],
+            temperature: 0.1,
+            max_tokens: 300,
+        }
+    }
+}
+
+impl LlmSettings {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.providers.is_empty() {
+            return Err(ApiError::invalid_input(
+                "At least one LLM provider is required",
+            ));
+        }
+        if !(0.0..=2.0).contains(&self.temperature) {
+            return Err(ApiError::invalid_input(
+                "Temperature must be between 0 and 2",
+            ));
+        }
+        if self.max_tokens == 0 {
+            return Err(ApiError::invalid_input("max_tokens must be at least 1"));
+        }
+        for provider in &self.providers {
+            provider.validate()?;
+        }
+        Ok(())
+    }
+
+    // Same settings with API keys masked, for `get_config`
+    pub fn masked(&self) -> Self {
+        Self {
+            providers: self.providers.iter().map(ProviderConfig::masked).collect(),
+            ..self.clone()
+        }
+    }
+}
+
+impl ProviderConfig {
+    fn validate(&self) -> Result<(), ApiError> {
+        match self {
+            ProviderConfig::Groq { model } | ProviderConfig::OnChain { model, .. }
+                if model.trim().is_empty() =>
+            {
+                Err(ApiError::invalid_input("Model must not be empty"))
+            }
+            ProviderConfig::OpenAiCompatible {
+                base_url, model, ..
+            } => {
+                // HTTPS outcalls can't reach plain HTTP servers
+                if !base_url.starts_with("https://") {
+                    return Err(ApiError::invalid_input(format!(
+                        "Base URL '{}' must start with https://",
+                        base_url
+                    )));
+                }
+                if model.trim().is_empty() {
+                    return Err(ApiError::invalid_input("Model must not be empty"));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn masked(&self) -> Self {
+        match self {
+            ProviderConfig::OpenAiCompatible {
+                base_url,
+                model,
+                api_key,
+            } => ProviderConfig::OpenAiCompatible {
+                base_url: base_url.clone(),
+                model: model.clone(),
+                api_key: api_key.as_deref().map(mask_secret),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+pub struct GenerationOptions {
+    pub temperature: f64,
+    pub max_tokens: u32,
+}
+
+pub trait LlmProvider {
+    // Short label for logs and error messages
+    fn name(&self) -> String;
+
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        options: &GenerationOptions,
+    ) -> Result<String, ApiError>;
+}
+
+pub struct GroqProvider {
+    pub model: String,
+}
+
+pub struct OpenAiCompatibleProvider {
+    pub base_url: String,
+    pub model: String,
+    pub api_key: Option<String>,
+}
+
+pub struct OnChainProvider {
+    pub canister_id: Principal,
+    pub model: String,
+}
+
+pub struct MockProvider {
+    pub response: String,
+}
+
+impl LlmProvider for GroqProvider {
+    fn name(&self) -> String {
+        format!("groq/{}", self.model)
+    }
+
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        options: &GenerationOptions,
+    ) -> Result<String, ApiError> {
+        OpenAiCompatibleProvider {
+            base_url: GROQ_BASE_URL.to_string(),
+            model: self.model.clone(),
+            api_key: Some(Config::groq_api_key()?),
+        }
+        .complete(messages, options)
+        .await
+    }
+}
+
+impl LlmProvider for OpenAiCompatibleProvider {
+    fn name(&self) -> String {
+        format!("{}/{}", self.base_url, self.model)
+    }
+
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        options: &GenerationOptions,
+    ) -> Result<String, ApiError> {
+        let payload = serde_json::json!({
+            "model": self.model,
+            "messages": messages.iter().map(|msg| {
+                serde_json::json!({
+                    "role": match msg.role {
+                        ChatRole::System => "system",
+                        ChatRole::User => "user",
+                        ChatRole::Assistant => "assistant"
+                    },
+                    "content": msg.content
+                })
+            }).collect::<Vec<_>>(),
+            "temperature": options.temperature,
+            "max_tokens": options.max_tokens,
+            "top_p": 1.0,
+            "stream": false
+        });
+
+        let mut headers = vec![HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/json".to_string(),
+        }];
+        if let Some(api_key) = &self.api_key {
+            headers.push(HttpHeader {
+                name: "Authorization".to_string(),
+                value: format!("Bearer {}", api_key),
+            });
+        }
+
+        ic_cdk::println!("Calling {}", self.name());
+
+        let request = CanisterHttpRequestArgument {
+            url: format!("{}/chat/completions", self.base_url.trim_end_matches('/')),
+            method: HttpMethod::POST,
+            body: Some(payload.to_string().into_bytes()),
+            max_response_bytes: Some(MAX_RESPONSE_BYTES),
+            transform: Some(TransformContext::from_name(
+                "transform".to_string(),
+                serde_json::json!({}).to_string().into_bytes(),
+            )),
+            headers,
+        };
+
+        match http_request(request, OUTCALL_CYCLES).await {
+            Ok((response,)) => {
+                let response_body = String::from_utf8(response.body)
+                    .map_err(|_| ApiError::parse("Invalid response encoding"))?;
+
+                ic_cdk::println!("{} response: {}", self.name(), response_body);
+
+                let status: u16 = response.status.0.to_string().parse().unwrap_or(500);
+                if !(200..300).contains(&status) {
+                    return Err(ApiError::upstream(status, response_body));
+                }
+
+                let api_response: serde_json::Value = serde_json::from_str(&response_body)
+                    .map_err(|_| ApiError::parse("Failed to parse chat completion response"))?;
+
+                api_response["choices"][0]["message"]["content"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| ApiError::parse("No content in chat completion response"))
+            }
+            Err((code, message)) => Err(ApiError::outcall(format!(
+                "{} call failed with code {:?}: {}",
+                self.name(),
+                code,
+                message
+            ))),
+        }
+    }
+}
+
+// Request type of the `llm` canister's `v0_chat`
+#[derive(CandidType, Deserialize)]
+struct ChatRequestV0 {
+    model: String,
+    messages: Vec<ChatMessage>,
+}
+
+impl LlmProvider for OnChainProvider {
+    fn name(&self) -> String {
+        format!("{}/{}", self.canister_id, self.model)
+    }
+
+    // `v0_chat` has no sampling options, so `options` is ignored
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        _options: &GenerationOptions,
+    ) -> Result<String, ApiError> {
+        let request = ChatRequestV0 {
+            model: self.model.clone(),
+            messages: messages.to_vec(),
+        };
+        ic_cdk::call::<(ChatRequestV0,), (String,)>(self.canister_id, "v0_chat", (request,))
+            .await
+            .map(|(response,)| response)
+            .map_err(|(code, message)| {
+                ApiError::canister_call(
+                    format!("{:?}: {}", code, message),
+                    code == RejectionCode::SysTransient,
+                )
+            })
+    }
+}
+
+impl LlmProvider for MockProvider {
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+
+    async fn complete(
+        &self,
+        _messages: &[ChatMessage],
+        _options: &GenerationOptions,
+    ) -> Result<String, ApiError> {
+        Ok(self.response.clone())
+    }
+}
+
+impl LlmProvider for ProviderConfig {
+    fn name(&self) -> String {
+        match self.clone() {
+            ProviderConfig::Groq { model } => GroqProvider { model }.name(),
+            ProviderConfig::OpenAiCompatible {
+                base_url,
+                model,
+                api_key,
+            } => OpenAiCompatibleProvider {
+                base_url,
+                model,
+                api_key,
+            }
+            .name(),
+            ProviderConfig::OnChain { canister_id, model } => {
+                OnChainProvider { canister_id, model }.name()
+            }
+            ProviderConfig::Mock { response } => MockProvider { response }.name(),
+        }
+    }
+
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        options: &GenerationOptions,
+    ) -> Result<String, ApiError> {
+        match self.clone() {
+            ProviderConfig::Groq { model } => {
+                GroqProvider { model }.complete(messages, options).await
+            }
+            ProviderConfig::OpenAiCompatible {
+                base_url,
+                model,
+                api_key,
+            } => {
+                OpenAiCompatibleProvider {
+                    base_url,
+                    model,
+                    api_key,
+                }
+                .complete(messages, options)
+                .await
+            }
+            ProviderConfig::OnChain { canister_id, model } => {
+                OnChainProvider { canister_id, model }
+                    .complete(messages, options)
+                    .await
+            }
+            ProviderConfig::Mock { response } => {
+                MockProvider { response }.complete(messages, options).await
+            }
+        }
+    }
+}
+
+// Goes down the configured chain until a provider answers and `accept` takes the answer.
+// Returns the last error when every provider fails.
+pub async fn complete_with_fallback<T>(
+    messages: &[ChatMessage],
+    accept: impl Fn(&str) -> Result<T, String>,
+) -> Result<T, ApiError> {
+    let settings = Config::llm_settings();
+    let options = GenerationOptions {
+        temperature: settings.temperature,
+        max_tokens: settings.max_tokens,
+    };
+    let (result, skipped) = first_usable(&settings.providers, messages, &options, accept).await;
+    for reason in skipped {
+        ic_cdk::println!("{}", reason);
+    }
+    result
+}
+
+// The answer of the first provider in `providers` whose output `accept` takes, or the
+// last error, along with why each provider tried before it was skipped
+async fn first_usable<P: LlmProvider, T>(
+    providers: &[P],
+    messages: &[ChatMessage],
+    options: &GenerationOptions,
+    accept: impl Fn(&str) -> Result<T, String>,
+) -> (Result<T, ApiError>, Vec<String>) {
+    let mut skipped = Vec::new();
+    let mut last_error = ApiError::config("No LLM provider is configured");
+    for provider in providers {
+        match provider.complete(messages, options).await {
+            Ok(output) => match accept(&output) {
+                Ok(value) => return (Ok(value), skipped),
+                Err(reason) => {
+                    skipped.push(format!("Rejected {} output ({})", provider.name(), reason));
+                    last_error = ApiError::parse(format!(
+                        "{} returned unusable output: {}",
+                        provider.name(),
+                        reason
+                    ));
+                }
+            },
+            Err(error) => {
+                skipped.push(format!("{} failed: {}", provider.name(), error));
+                last_error = error;
+            }
+        }
+    }
+    (Err(last_error), skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::ErrorCode;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    // Answers with `output`, or fails like an unreachable server when it is `None`
+    struct Scripted {
+        name: &'static str,
+        output: Option<&'static str>,
+    }
+
+    impl LlmProvider for Scripted {
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        async fn complete(
+            &self,
+            _messages: &[ChatMessage],
+            _options: &GenerationOptions,
+        ) -> Result<String, ApiError> {
+            self.output
+                .map(str::to_string)
+                .ok_or_else(|| ApiError::outcall(format!("{} is down", self.name)))
+        }
+    }
+
+    // Providers in these tests answer right away, so one poll finishes the chain
+    fn run<F: Future>(future: F) -> F::Output {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("provider chain did not finish"),
+        }
+    }
+
+    fn chain(providers: &[Scripted]) -> (Result<String, ApiError>, Vec<String>) {
+        let options = GenerationOptions {
+            temperature: 0.1,
+            max_tokens: 300,
+        };
+        let accept = |output: &str| match output {
+            "garbage" => Err("not JSON".to_string()),
+            output => Ok(output.to_string()),
+        };
+        run(first_usable(providers, &[], &options, accept))
+    }
+
+    fn openai(base_url: &str) -> LlmSettings {
+        LlmSettings {
+            providers: vec![ProviderConfig::OpenAiCompatible {
+                base_url: base_url.to_string(),
+                model: "gpt-4o-mini".to_string(),
+                api_key: None,
+            }],
+            ..LlmSettings::default()
+        }
+    }
+
+    #[test]
+    fn takes_the_first_usable_answer_in_order() {
+        let (result, skipped) = chain(&[
+            Scripted {
+                name: "down",
+                output: None,
+            },
+            Scripted {
+                name: "confused",
+                output: Some("garbage"),
+            },
+            Scripted {
+                name: "good",
+                output: Some("{}"),
+            },
+            Scripted {
+                name: "never asked",
+                output: Some("[]"),
+            },
+        ]);
+
+        assert_eq!(result.unwrap(), "{}");
+        assert_eq!(
+            skipped,
+            [
+                "down failed: Outcall error: down is down",
+                "Rejected confused output (not JSON)"
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_the_last_error_when_every_provider_fails() {
+        let (result, _) = chain(&[
+            Scripted {
+                name: "down",
+                output: None,
+            },
+            Scripted {
+                name: "confused",
+                output: Some("garbage"),
+            },
+        ]);
+        let error = result.unwrap_err();
+        assert_eq!(error.code, ErrorCode::Parse);
+        assert_eq!(error.message, "confused returned unusable output: not JSON");
+
+        let error = chain(&[]).0.unwrap_err();
+        assert_eq!(error.code, ErrorCode::Config);
+    }
+
+    #[test]
+    fn accepts_only_https_base_urls() {
+        assert!(openai("https://api.openai.com/v1").validate().is_ok());
+        for base_url in ["http://localhost:8080/v1", "api.openai.com/v1", ""] {
+            let error = openai(base_url).validate().unwrap_err();
+            assert_eq!(error.code, ErrorCode::InvalidInput);
+            assert!(error.message.contains("must start with https://"));
+        }
+    }
+
+    #[test]
+    fn rejects_settings_without_providers_or_models() {
+        let empty = LlmSettings {
+            providers: Vec::new(),
+            ..LlmSettings::default()
+        };
+        assert!(empty.validate().is_err());
+        let unnamed = LlmSettings {
+            providers: vec![ProviderConfig::Groq {
+                model: " ".to_string(),
+            }],
+            ..LlmSettings::default()
+        };
+        assert!(unnamed.validate().is_err());
+        assert!(LlmSettings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn names_providers_like_the_providers_themselves() {
+        let canister_id = Principal::from_slice(&[1]);
+        assert_eq!(
+            ProviderConfig::OpenAiCompatible {
+                base_url: "https://api.openai.com/v1".to_string(),
+                model: "gpt-4o-mini".to_string(),
+                api_key: Some("secret".to_string()),
+            }
+            .name(),
+            "https://api.openai.com/v1/gpt-4o-mini"
+        );
+        assert_eq!(
+            ProviderConfig::OnChain {
+                canister_id,
+                model: "llama3.1:8b".to_string(),
+            }
+            .name(),
+            format!("{}/llama3.1:8b", canister_id)
+        );
+        assert_eq!(
+            ProviderConfig::Groq {
+                model: "llama-3.1-8b-instant".to_string(),
+            }
+            .name(),
+            "groq/llama-3.1-8b-instant"
+        );
+    }
+}
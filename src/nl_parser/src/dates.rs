@@ -0,0 +1,596 @@
+// Date expressions in questions, resolved to whole days of the configured time zone:
+// `today`, `yesterday`, `this week`, `last month`, `last 7 days`, `2 weeks ago`,
+// `in 3 days`, `friday`, `march 3`, `3rd of march 2025`, `2024-03-03`, `in march`
+
+use crate::numbers::number_at;
+use crate::tokenizer::Token;
+use common::time::{
+    civil_from_days, days_from_civil, format_date, format_timestamp, parse_date, NANOS_PER_SECOND,
+    SECONDS_PER_DAY,
+};
+
+const MONTHS: &[&str] = &[
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+const WEEKDAYS: &[&str] = &[
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+// Counts of days, weeks, months or years beyond this reach past any date a timestamp
+// can hold, so limiting them to it only keeps the arithmetic from overflowing: the
+// span is still out of range and rejected
+const MAX_COUNT: i64 = 10_000_000;
+
+// The current time and the time zone that days are counted in
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    // Nanoseconds since the epoch
+    pub now: i64,
+    // Seconds east of UTC
+    pub offset: i64,
+}
+
+impl Clock {
+    // Today as days since 1970-01-01 of the local calendar
+    pub fn today(&self) -> i64 {
+        (self.now.div_euclid(NANOS_PER_SECOND) + self.offset).div_euclid(SECONDS_PER_DAY)
+    }
+
+    // The UTC timestamp at which a local day starts, `None` for days nanoseconds in an
+    // i64 can't reach
+    pub fn day_start(&self, day: i64) -> Option<String> {
+        let seconds = day.checked_mul(SECONDS_PER_DAY)?.checked_sub(self.offset)?;
+        seconds.checked_mul(NANOS_PER_SECOND).map(format_timestamp)
+    }
+
+    pub fn now_timestamp(&self) -> String {
+        format_timestamp(self.now)
+    }
+
+    // The time `days` days from now
+    pub fn timestamp_in(&self, days: i64) -> Option<String> {
+        let nanos = days
+            .checked_mul(SECONDS_PER_DAY)?
+            .checked_mul(NANOS_PER_SECOND)?;
+        self.now.checked_add(nanos).map(format_timestamp)
+    }
+
+    pub fn date(&self, day: i64) -> String {
+        format_date(day)
+    }
+}
+
+// Days `start..end` of the local calendar
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DaySpan {
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+fn unit(word: &str) -> Option<Unit> {
+    match word.strip_suffix('s').unwrap_or(word) {
+        "day" => Some(Unit::Day),
+        "week" => Some(Unit::Week),
+        "month" => Some(Unit::Month),
+        "year" => Some(Unit::Year),
+        _ => None,
+    }
+}
+
+// Whether a word names a unit of time, so `last 7 days` isn't read as a limit
+pub fn is_unit(token: Option<&Token>) -> bool {
+    token.and_then(Token::word).and_then(unit).is_some()
+}
+
+// Monday is 0; 1970-01-01 was a Thursday
+fn weekday(day: i64) -> i64 {
+    (day + 3).rem_euclid(7)
+}
+
+fn month_start(year: i64, month: u32) -> i64 {
+    days_from_civil(year, month, 1)
+}
+
+fn next_month(year: i64, month: u32) -> (i64, u32) {
+    if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    }
+}
+
+// The whole week, month or year containing a day
+fn containing(day: i64, unit: Unit) -> DaySpan {
+    let (year, month, _) = civil_from_days(day);
+    match unit {
+        Unit::Day => DaySpan {
+            start: day,
+            end: day + 1,
+        },
+        Unit::Week => {
+            let monday = day - weekday(day);
+            DaySpan {
+                start: monday,
+                end: monday + 7,
+            }
+        }
+        Unit::Month => {
+            let (next_year, next) = next_month(year, month);
+            DaySpan {
+                start: month_start(year, month),
+                end: month_start(next_year, next),
+            }
+        }
+        Unit::Year => DaySpan {
+            start: month_start(year, 1),
+            end: month_start(year + 1, 1),
+        },
+    }
+}
+
+// The day `count` units away, keeping the day of the month where it exists
+fn shift(day: i64, unit: Unit, count: i64) -> i64 {
+    let months = match unit {
+        Unit::Day => return day + count,
+        Unit::Week => return day + 7 * count,
+        Unit::Month => count,
+        Unit::Year => 12 * count,
+    };
+    let (year, month, day_of_month) = civil_from_days(day);
+    let index = year * 12 + i64::from(month) - 1 + months;
+    let (year, month) = (index.div_euclid(12), index.rem_euclid(12) as u32 + 1);
+    let (next_year, next) = next_month(year, month);
+    let length = month_start(next_year, next) - month_start(year, month);
+    month_start(year, month) + i64::from(day_of_month).min(length) - 1
+}
+
+struct Reader<'a> {
+    tokens: &'a [Token],
+    clock: &'a Clock,
+    // Whether a bare weekday means the last one rather than the next one
+    past: bool,
+}
+
+impl Reader<'_> {
+    fn word(&self, i: usize) -> Option<&str> {
+        self.tokens.get(i).and_then(Token::word)
+    }
+
+    fn number(&self, i: usize) -> Option<i64> {
+        match self.tokens.get(i)? {
+            Token::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    // `3` or `three` of `last 3 days`, and how many tokens it takes
+    fn count(&self, i: usize) -> Option<(i64, usize)> {
+        number_at(self.tokens, i)
+    }
+
+    // `3`, `3rd`, `21st`
+    fn day_of_month(&self, i: usize) -> Option<u32> {
+        let day = self.day_number(i)?;
+        u32::try_from(day).ok().filter(|day| (1..=31).contains(day))
+    }
+
+    // A number that sits where the day of a month goes, whether or not it is one
+    fn day_number(&self, i: usize) -> Option<i64> {
+        match self.tokens.get(i)? {
+            Token::Number(number) => Some(*number),
+            Token::Word(word) => ["st", "nd", "rd", "th"]
+                .iter()
+                .find_map(|suffix| word.strip_suffix(suffix))?
+                .parse()
+                .ok(),
+            _ => None,
+        }
+    }
+
+    // Any four-digit year, so `31 december 9999` isn't read as `31 december` and an id;
+    // days timestamps can't hold are turned away when the filter is built
+    fn year(&self, i: usize) -> Option<i64> {
+        self.number(i).filter(|year| (1000..10_000).contains(year))
+    }
+
+    // `march`, `mar`, `sept`
+    fn month(&self, i: usize) -> Option<u32> {
+        let word = self.word(i)?;
+        if word.len() < 3 {
+            return None;
+        }
+        let index = MONTHS.iter().position(|month| month.starts_with(word))?;
+        Some(index as u32 + 1)
+    }
+
+    fn weekday(&self, i: usize) -> Option<i64> {
+        let word = self.word(i)?;
+        let index = WEEKDAYS.iter().position(|day| {
+            *day == word || (word.len() >= 3 && day.starts_with(word) && word != "sun")
+        })?;
+        Some(index as i64)
+    }
+
+    fn span(&self, i: usize, prepositioned: bool) -> Option<(DaySpan, usize)> {
+        let today = self.clock.today();
+        let day = |day: i64| DaySpan {
+            start: day,
+            end: day + 1,
+        };
+
+        match self.word(i) {
+            Some("today" | "tonight") => return Some((day(today), 1)),
+            Some("tomorrow") => return Some((day(today + 1), 1)),
+            Some("yesterday") => return Some((day(today - 1), 1)),
+            Some(
+                word @ ("this" | "current" | "next" | "coming" | "last" | "previous" | "past"),
+            ) => {
+                let step = match word {
+                    "this" | "current" => 0,
+                    "next" | "coming" => 1,
+                    _ => -1,
+                };
+                // `this week`, `last month`, `next year`
+                if let Some(unit) = self.word(i + 1).and_then(unit) {
+                    return Some((containing(shift(today, unit, step), unit), 2));
+                }
+                // `last 7 days` up to and including today, `next 3 weeks` from today
+                if let Some((count, len, unit)) = self.count(i + 1).and_then(|(count, len)| {
+                    Some((count, len, self.word(i + 1 + len).and_then(unit)?))
+                }) {
+                    let count = count.clamp(1, MAX_COUNT);
+                    let span = if step < 0 {
+                        DaySpan {
+                            start: shift(today, unit, -count) + 1,
+                            end: today + 1,
+                        }
+                    } else {
+                        DaySpan {
+                            start: today,
+                            end: shift(today, unit, count),
+                        }
+                    };
+                    return Some((span, 2 + len));
+                }
+                // `last friday`, `next monday`, `this friday`
+                if let Some(target) = self.weekday(i + 1) {
+                    let ahead = (target - weekday(today)).rem_euclid(7);
+                    let date = match step {
+                        0 => today - weekday(today) + target,
+                        1 if ahead == 0 => today + 7,
+                        1 => today + ahead,
+                        _ => today - (weekday(today) - target - 1).rem_euclid(7) - 1,
+                    };
+                    return Some((day(date), 2));
+                }
+                return None;
+            }
+            _ => {}
+        }
+
+        // `2 weeks ago`, `in 3 days`
+        if let Some((count, len, unit)) = self
+            .count(i)
+            .and_then(|(count, len)| Some((count, len, self.word(i + len).and_then(unit)?)))
+        {
+            let count = count.clamp(-MAX_COUNT, MAX_COUNT);
+            if self.word(i + len + 1) == Some("ago") {
+                return Some((containing(shift(today, unit, -count), unit), len + 2));
+            }
+            if prepositioned {
+                return Some((containing(shift(today, unit, count), unit), len + 1));
+            }
+            return None;
+        }
+
+        // `friday` is the next one or the last one, or today
+        if let Some(target) = self.weekday(i) {
+            let date = if self.past {
+                today - (weekday(today) - target).rem_euclid(7)
+            } else {
+                today + (target - weekday(today)).rem_euclid(7)
+            };
+            return Some((day(date), 1));
+        }
+
+        // `2024-03-03`
+        if let Some(Token::Word(word)) = self.tokens.get(i) {
+            if let Some(nanos) = parse_date(word) {
+                let days = nanos / NANOS_PER_SECOND / SECONDS_PER_DAY;
+                return Some((day(days), 1));
+            }
+        }
+
+        self.calendar_date(i, prepositioned)
+    }
+
+    // `march 3`, `march 3rd 2025`, `3 march`, `3rd of march`, `march 2025`, and after a
+    // preposition also `march` and `2024`. Without a year the current one is meant.
+    fn calendar_date(&self, i: usize, prepositioned: bool) -> Option<(DaySpan, usize)> {
+        let (current_year, _, _) = civil_from_days(self.clock.today());
+        let (month, day_of_month, mut len) = if let Some(month) = self.month(i) {
+            match self.day_of_month(i + 1) {
+                Some(day) if self.year(i + 1).is_none() => (month, Some(day), 2),
+                // `march 32` is no date rather than all of march
+                None if self.day_number(i + 1).is_some() && self.year(i + 1).is_none() => {
+                    return None
+                }
+                _ => (month, None, 1),
+            }
+        } else if let Some(day) = self.day_of_month(i) {
+            let of = usize::from(self.word(i + 1) == Some("of"));
+            let month = self.month(i + 1 + of)?;
+            (month, Some(day), 2 + of)
+        } else if prepositioned {
+            let year = self.year(i)?;
+            return Some((containing(month_start(year, 1), Unit::Year), 1));
+        } else {
+            return None;
+        };
+
+        let year = match self.year(i + len) {
+            Some(year) => {
+                len += 1;
+                year
+            }
+            // "may" alone is more likely a verb than a month
+            None if day_of_month.is_none() && !prepositioned => return None,
+            None => current_year,
+        };
+        let span = match day_of_month {
+            Some(day) => {
+                let start = days_from_civil(year, month, day);
+                // `february 31` and the like aren't dates
+                if civil_from_days(start).1 != month {
+                    return None;
+                }
+                DaySpan {
+                    start,
+                    end: start + 1,
+                }
+            }
+            None => containing(month_start(year, month), Unit::Month),
+        };
+        Some((span, len))
+    }
+}
+
+// Something written like a date that isn't one, `february 30`, `32nd of march` or
+// `2026-13-01`: how many tokens it takes, so it can be reported instead of being read
+// as an id. Only meaningful where `parse_span` found no date.
+pub fn invalid_date(tokens: &[Token], i: usize) -> Option<usize> {
+    let reader = Reader {
+        tokens,
+        clock: &Clock { now: 0, offset: 0 },
+        past: false,
+    };
+    let the = usize::from(reader.word(i) == Some("the"));
+    let i = i + the;
+
+    if let Some(Token::Word(word)) = tokens.get(i) {
+        let shaped = word.len() == 10
+            && word.char_indices().all(|(k, c)| match k {
+                4 | 7 => c == '-',
+                _ => c.is_ascii_digit(),
+            });
+        if shaped && parse_date(word).is_none() {
+            return Some(the + 1);
+        }
+    }
+
+    let len = if reader.month(i).is_some() && reader.day_number(i + 1).is_some() {
+        2
+    } else if reader.day_number(i).is_some() {
+        let of = usize::from(reader.word(i + 1) == Some("of"));
+        reader.month(i + 1 + of)?;
+        2 + of
+    } else {
+        return None;
+    };
+    let year = usize::from(reader.year(i + len).is_some());
+    Some(the + len + year)
+}
+
+// `3 weeks`, `a month`, `the next 10 days` after `within`: how many days from today
+// that reaches, and how many tokens it takes
+pub fn parse_duration(tokens: &[Token], i: usize, clock: &Clock) -> Option<(i64, usize)> {
+    let mut k = i;
+    for optional in ["the", "next"] {
+        if tokens.get(k).and_then(Token::word) == Some(optional) {
+            k += 1;
+        }
+    }
+    let (count, len) = match tokens.get(k).and_then(Token::word) {
+        Some("a" | "an") if is_unit(tokens.get(k + 1)) => (1, 1),
+        _ => number_at(tokens, k)?,
+    };
+    let unit = tokens.get(k + len).and_then(Token::word).and_then(unit)?;
+    let today = clock.today();
+    let days = shift(today, unit, count.clamp(0, MAX_COUNT)) - today;
+    Some((days, k + len + 1 - i))
+}
+
+// Reads a date expression at `i`, returning the days it covers and how many tokens it
+// takes. A leading `the` is skipped. After a preposition (`in march`, `before 2024`,
+// `in 3 days`) months, years and counts of days stand on their own; elsewhere they
+// need more context, so a number stays an id and "may" a verb. `past` is set for
+// columns like `created_at`, where "friday" means the last one.
+pub fn parse_span(
+    tokens: &[Token],
+    i: usize,
+    clock: &Clock,
+    prepositioned: bool,
+    past: bool,
+) -> Option<(DaySpan, usize)> {
+    let reader = Reader {
+        tokens,
+        clock,
+        past,
+    };
+    let the = usize::from(reader.word(i) == Some("the"));
+    let (span, len) = reader.span(i + the, prepositioned)?;
+    Some((span, the + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    // Saturday 2026-10-17 10:00 UTC, in UTC+2
+    fn clock() -> Clock {
+        let day = days_from_civil(2026, 10, 17);
+        Clock {
+            now: (day * SECONDS_PER_DAY + 10 * 3600) * NANOS_PER_SECOND,
+            offset: 2 * 3600,
+        }
+    }
+
+    fn dates(expression: &str, prepositioned: bool) -> Option<(String, String)> {
+        let tokens = tokenize(expression);
+        let (span, len) = parse_span(&tokens, 0, &clock(), prepositioned, false)?;
+        assert_eq!(len, tokens.len(), "{}", expression);
+        Some((format_date(span.start), format_date(span.end)))
+    }
+
+    fn assert_span(expression: &str, start: &str, end: &str) {
+        assert_eq!(
+            dates(expression, true),
+            Some((start.to_string(), end.to_string())),
+            "{}",
+            expression
+        );
+    }
+
+    #[test]
+    fn resolves_relative_days_weeks_months_and_years() {
+        assert_span("today", "2026-10-17", "2026-10-18");
+        assert_span("yesterday", "2026-10-16", "2026-10-17");
+        assert_span("tomorrow", "2026-10-18", "2026-10-19");
+        assert_span("this week", "2026-10-12", "2026-10-19");
+        assert_span("next week", "2026-10-19", "2026-10-26");
+        assert_span("last month", "2026-09-01", "2026-10-01");
+        assert_span("this year", "2026-01-01", "2027-01-01");
+        assert_span("the last 7 days", "2026-10-11", "2026-10-18");
+        assert_span("next 3 days", "2026-10-17", "2026-10-20");
+        assert_span("2 weeks ago", "2026-09-28", "2026-10-05");
+        assert_span("3 days", "2026-10-20", "2026-10-21");
+    }
+
+    #[test]
+    fn resolves_weekdays() {
+        assert_span("friday", "2026-10-23", "2026-10-24");
+        assert_span("saturday", "2026-10-17", "2026-10-18");
+        assert_span("last friday", "2026-10-16", "2026-10-17");
+        assert_span("last saturday", "2026-10-10", "2026-10-11");
+        assert_span("next saturday", "2026-10-24", "2026-10-25");
+        assert_span("this monday", "2026-10-12", "2026-10-13");
+        let tokens = tokenize("friday");
+        let (span, _) = parse_span(&tokens, 0, &clock(), false, true).unwrap();
+        assert_eq!(format_date(span.start), "2026-10-16");
+    }
+
+    #[test]
+    fn resolves_calendar_dates() {
+        assert_span("march 3", "2026-03-03", "2026-03-04");
+        assert_span("3rd of march 2025", "2025-03-03", "2025-03-04");
+        assert_span("mar 3rd", "2026-03-03", "2026-03-04");
+        assert_span("2024-02-29", "2024-02-29", "2024-03-01");
+        assert_span("march", "2026-03-01", "2026-04-01");
+        assert_span("december 2025", "2025-12-01", "2026-01-01");
+        assert_span("2024", "2024-01-01", "2025-01-01");
+        assert_eq!(dates("february 30", true), None);
+    }
+
+    #[test]
+    fn needs_a_preposition_for_ambiguous_expressions() {
+        assert_eq!(dates("may", false), None);
+        assert_eq!(dates("2024", false), None);
+        assert_eq!(dates("3 days", false), None);
+        assert!(dates("may 5", false).is_some());
+    }
+
+    #[test]
+    fn counts_days_in_the_time_zone() {
+        // 23:30 UTC is already the next day in UTC+2
+        let late = Clock {
+            now: (days_from_civil(2026, 10, 17) * SECONDS_PER_DAY + 23 * 3600 + 1800)
+                * NANOS_PER_SECOND,
+            offset: 2 * 3600,
+        };
+        assert_eq!(format_date(late.today()), "2026-10-18");
+        assert_eq!(
+            late.day_start(late.today()),
+            Some("2026-10-17T22:00:00Z".to_string())
+        );
+    }
+
+    #[test]
+    fn leaves_days_timestamps_cannot_hold() {
+        assert_eq!(clock().day_start(days_from_civil(9999, 12, 31)), None);
+        assert_eq!(clock().day_start(days_from_civil(1, 1, 1)), None);
+        assert_eq!(clock().timestamp_in(i64::MAX / 2), None);
+    }
+
+    #[test]
+    fn recognises_dates_that_do_not_exist() {
+        for (expression, len) in [
+            ("february 30", 2),
+            ("feb 29", 2),
+            ("march 32", 2),
+            ("32nd of march", 3),
+            ("the 30th of february 2026", 5),
+            ("2026-02-30", 1),
+            ("0000-01-01", 1),
+        ] {
+            let tokens = tokenize(expression);
+            assert_eq!(dates(expression, true), None, "{}", expression);
+            assert_eq!(invalid_date(&tokens, 0), Some(len), "{}", expression);
+        }
+        assert_eq!(invalid_date(&tokenize("5 todos"), 0), None);
+        assert_eq!(invalid_date(&tokenize("may"), 0), None);
+    }
+
+    #[test]
+    fn measures_durations_from_today() {
+        for (expression, days, len) in [
+            ("3 weeks", 21, 2),
+            ("a month", 31, 2),
+            ("the next 10 days", 10, 4),
+            ("two years", 731, 2),
+        ] {
+            let tokens = tokenize(expression);
+            assert_eq!(
+                parse_duration(&tokens, 0, &clock()),
+                Some((days, len)),
+                "{}",
+                expression
+            );
+        }
+        assert_eq!(parse_duration(&tokenize("march"), 0, &clock()), None);
+    }
+}
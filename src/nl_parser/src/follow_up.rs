@@ -0,0 +1,91 @@
+// Follow-up questions like "now only the completed ones" or "sorted by title" refine
+// the query asked before them instead of starting a new one. What they add replaces
+// the matching part of the previous query and everything else is kept.
+
+use crate::tokenizer::Token;
+use common::PostgrestQuery;
+
+// Words a follow-up starts with, which would otherwise read as a search term
+const LEADING: &[&str] = &[
+    "now", "and", "but", "also", "then", "instead", "ok", "okay", "so",
+];
+// "what about the blocked ones"
+const ASKING: &[&str] = &["what", "how"];
+// Leading too, but also how projections start: "only title and status"
+const PROJECTING: &[&str] = &["only", "just"];
+// Words pointing back at the previous answer
+const REFERENCES: &[&str] = &[
+    "ones", "one", "them", "those", "these", "they", "results", "rows",
+];
+
+// The tokens left once the follow-up words are dropped, and whether there were any.
+// With `keep_projection` a leading `only` or `just` stays for the parser to read.
+pub fn strip(tokens: &[Token], keep_projection: bool) -> (Vec<Token>, bool) {
+    let is = |i: usize, words: &[&str]| {
+        tokens
+            .get(i)
+            .and_then(Token::word)
+            .is_some_and(|word| words.contains(&word))
+    };
+    let mut leading = 0;
+    loop {
+        if is(leading, LEADING) || (is(leading, PROJECTING) && !keep_projection) {
+            leading += 1;
+        } else if is(leading, ASKING) && is(leading + 1, &["about"]) {
+            leading += 2;
+        } else {
+            break;
+        }
+    }
+    let rest: Vec<Token> = tokens[leading..]
+        .iter()
+        .filter(|token| !token.word().is_some_and(|word| REFERENCES.contains(&word)))
+        .cloned()
+        .collect();
+    let marked = leading > 0
+        || rest.len() < tokens.len() - leading
+        || (keep_projection && is(0, PROJECTING));
+    (rest, marked)
+}
+
+// `previous` with what `delta` says: filters on the same column and the projection,
+// ordering, limit and offset are replaced when `delta` has them
+pub fn merge(previous: &PostgrestQuery, delta: PostgrestQuery) -> PostgrestQuery {
+    let mut query = previous.clone();
+
+    query.filters.retain(|filter| {
+        !delta
+            .filters
+            .iter()
+            .any(|other| other.column == filter.column)
+    });
+    query.filters.extend(delta.filters);
+    query.groups.extend(delta.groups);
+
+    if !delta.select.is_empty() || !delta.aggregates.is_empty() {
+        // "how many are there" counts every matching row, not the page shown before
+        if !delta.aggregates.is_empty() {
+            query.order.clear();
+            query.limit = None;
+            query.offset = None;
+        }
+        query.select = delta.select;
+        query.aggregates = delta.aggregates;
+    }
+    for embed in delta.embeds {
+        query
+            .embeds
+            .retain(|existing| existing.relation != embed.relation);
+        query.embeds.push(embed);
+    }
+    if !delta.order.is_empty() {
+        query.order = delta.order;
+    }
+    if delta.limit.is_some() {
+        query.limit = delta.limit;
+    }
+    if delta.offset.is_some() {
+        query.offset = delta.offset;
+    }
+    query
+}
@@ -0,0 +1,1774 @@
+// Grammar over the tokens of a question. Each clause (projection, ordering, limit,
+// column conditions, boolean states, text search, related tables) is tried at every
+// position that no earlier clause consumed; columns, their types and the foreign keys
+// between tables come from the schema.
+
+use crate::dates::{invalid_date, is_unit, parse_duration, parse_span, Clock, DaySpan};
+use crate::logic::{combine, Item};
+use crate::numbers::number_at;
+use crate::tokenizer::Token;
+use common::time::{parse_date, parse_timestamp};
+use common::{
+    Aggregate, AggregateFunction, Assignment, ColumnSchema, ColumnType, Condition, Embed, Filter,
+    FilterOperator, FilterValue, Mutation, MutationAction, PostgrestQuery, Schema, SortDirection,
+    TableSchema,
+};
+
+// Words that ask for rows without saying which ones
+pub const VERBS: &[&str] = &[
+    "show",
+    "get",
+    "find",
+    "list",
+    "display",
+    "fetch",
+    "give",
+    "select",
+    "return",
+    "count",
+    "search",
+    "all",
+    "every",
+    "everything",
+];
+// Words that ask to change rows instead of reading them
+pub const MUTATION_VERBS: &[&str] = &[
+    "delete", "remove", "erase", "mark", "flag", "set", "change", "update",
+];
+const DELETE_VERBS: &[&str] = &["delete", "remove", "erase"];
+const NEGATIONS: &[&str] = &[
+    "not", "no", "isn't", "aren't", "wasn't", "weren't", "never", "non",
+];
+// States that mean "not done" for the table's flag column
+const PENDING: &[&str] = &["pending", "open", "outstanding", "remaining"];
+const SEARCH: &[&str] = &[
+    "containing",
+    "contains",
+    "contain",
+    "about",
+    "titled",
+    "named",
+    "called",
+    "like",
+    "matching",
+    "mentioning",
+    "including",
+    "includes",
+];
+const NEWEST: &[&str] = &["latest", "newest", "recent", "newer"];
+const OLDEST: &[&str] = &["oldest", "earliest", "older"];
+const ASCENDING: &[&str] = &["asc", "ascending", "increasing"];
+const DESCENDING: &[&str] = &["desc", "descending", "decreasing"];
+// `highest first` after an ordered column
+const HIGHEST: &[&str] = &["highest", "largest", "biggest"];
+const LOWEST: &[&str] = &["lowest", "smallest", "least"];
+const ORDINALS: &[&str] = &[
+    "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth", "tenth",
+];
+// Words asking for an aggregate of the column named next
+const AGGREGATES: &[(AggregateFunction, &[&str])] = &[
+    (AggregateFunction::Sum, &["sum", "total"]),
+    (AggregateFunction::Avg, &["average", "avg", "mean"]),
+    (
+        AggregateFunction::Min,
+        &["minimum", "min", "lowest", "smallest"],
+    ),
+    (
+        AggregateFunction::Max,
+        &["maximum", "max", "highest", "largest", "biggest"],
+    ),
+];
+// Rows per page when a question asks for a page without saying how big
+const PAGE_SIZE: u64 = 10;
+// Why a limit, page or page size of zero or less can't be read
+const NOT_POSITIVE: &str = "must be a positive number";
+// Words that put a date expression in relation to a column, `due before friday`
+const DATE_PREPOSITIONS: &[&str] = &[
+    "before", "until", "till", "by", "after", "since", "from", "between", "on", "in", "during",
+    "within",
+];
+// Words ending the range a `from` or `between` date starts
+const RANGE_ENDS: &[&str] = &["to", "until", "till", "through", "and"];
+// Words that start a clause of their own, so `or` before them isn't another value
+const KEYWORDS: &[&[&str]] = &[
+    VERBS,
+    NEGATIONS,
+    PENDING,
+    SEARCH,
+    NEWEST,
+    OLDEST,
+    &[
+        "only",
+        "just",
+        "order",
+        "sort",
+        "sorted",
+        "first",
+        "top",
+        "last",
+        "limit",
+        "next",
+        "skip",
+        "offset",
+        "page",
+        "alphabetically",
+        "alphabetical",
+        "without",
+        "missing",
+        "lacking",
+        "with",
+        "having",
+        "has",
+        "have",
+        "but",
+        "except",
+        "excluding",
+    ],
+];
+// Words that are never a search term or a compared value
+const FILLER: &[&str] = &[
+    "the", "a", "an", "me", "my", "all", "of", "that", "which", "are", "is", "with", "for",
+    "please", "to", "in", "on", "by", "and", "or", "where", "whose", "have", "has", "been", "be",
+    "any", "some", "their", "its", "it", "them", "those", "these", "this",
+];
+
+// Finds the earliest mention of a table; at the same position the longest term wins
+pub fn find_table<'a>(
+    schema: &'a Schema,
+    tokens: &[Token],
+) -> Option<(&'a TableSchema, usize, usize)> {
+    (0..tokens.len()).find_map(|start| {
+        schema
+            .tables
+            .iter()
+            .filter_map(|table| {
+                table
+                    .terms()
+                    .iter()
+                    .filter_map(|term| phrase_len(tokens, start, term))
+                    .max()
+                    .map(|len| (table, start, len))
+            })
+            .max_by_key(|(_, _, len)| *len)
+    })
+}
+
+// Number of tokens a phrase like `due date` covers at `start`, if it matches there
+fn phrase_len(tokens: &[Token], start: usize, phrase: &str) -> Option<usize> {
+    let words: Vec<&str> = phrase.split_whitespace().collect();
+    if words.is_empty() || start + words.len() > tokens.len() {
+        return None;
+    }
+    let matches = words
+        .iter()
+        .zip(&tokens[start..])
+        .all(|(word, token)| token.word().is_some_and(|w| same_word(w, word)));
+    matches.then_some(words.len())
+}
+
+// Equal, or equal once a plural `s` is dropped (`dates` for `date`)
+fn same_word(word: &str, expected: &str) -> bool {
+    word == expected || (word.len() > 3 && word.strip_suffix('s') == Some(expected))
+}
+
+fn is_one_of(token: Option<&Token>, words: &[&str]) -> bool {
+    token
+        .and_then(Token::word)
+        .is_some_and(|word| words.contains(&word))
+}
+
+// Columns named after `per` or `by`
+#[derive(Default)]
+struct Grouping {
+    columns: Vec<String>,
+    // Said with `per`, `for each` or `grouped by`, which asks for a count on its own
+    explicit: bool,
+    // `by title desc`: a direction to sort the columns in
+    direction: Option<SortDirection>,
+}
+
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    schema: &'a Schema,
+    table: &'a TableSchema,
+    // Tokens already consumed by the table mention or a clause
+    used: Vec<bool>,
+    // Conditions and the connectives between them, combined once all are read
+    items: Vec<Item>,
+    // Where the table is named, so `5 todos` reads as a limit
+    mention: Option<(usize, usize)>,
+    // Relative dates are only read when the caller knows the time
+    clock: Option<Clock>,
+    // `page 3`, turned into an offset once the page size is known
+    page: Option<u64>,
+    grouping: Option<Grouping>,
+    // `delete ...`, `mark ... as done`, `set status to ...`
+    mutation: Option<Mutation>,
+    // The first part of the question that reads like a clause but can't be one, like
+    // `february 30`; the question is rejected with it rather than read another way
+    problem: Option<String>,
+    query: PostgrestQuery,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(
+        tokens: &'a [Token],
+        schema: &'a Schema,
+        table: &'a TableSchema,
+        mention: Option<(usize, usize)>,
+        clock: Option<Clock>,
+    ) -> Self {
+        let mut used = vec![false; tokens.len()];
+        if let Some((start, len)) = mention {
+            used[start..start + len].iter_mut().for_each(|u| *u = true);
+        }
+        Self {
+            tokens,
+            schema,
+            table,
+            used,
+            items: Vec::new(),
+            mention,
+            clock,
+            page: None,
+            grouping: None,
+            mutation: None,
+            problem: None,
+            query: PostgrestQuery::new(),
+        }
+    }
+
+    // The query of a part of a question; a part that can't be read adds nothing
+    pub fn parse(self) -> PostgrestQuery {
+        self.parse_statement()
+            .map(|(query, _)| query)
+            .unwrap_or_default()
+    }
+
+    // The query and, when the question asks to change rows, what to change in the
+    // rows its filters pick. Fails with what couldn't be read.
+    pub fn parse_statement(mut self) -> Result<(PostgrestQuery, Option<Mutation>), String> {
+        // Digits the tokenizer couldn't hold as a number would otherwise be skipped,
+        // dropping the limit, page or condition they belong to
+        if let Some(i) = self.tokens.iter().position(is_too_large) {
+            return Err(format!("'{}' is too large a number", self.tokens[i].text()));
+        }
+        // `titled ""` would search for nothing, matching every row
+        if self
+            .tokens
+            .iter()
+            .any(|token| matches!(token, Token::Quoted(text) if text.trim().is_empty()))
+        {
+            return Err("an empty quoted value matches every row".to_string());
+        }
+        let mut i = 0;
+        while i < self.tokens.len() {
+            if self.used[i] {
+                i += 1;
+                continue;
+            }
+            let end = self
+                .delete(i)
+                .or_else(|| self.mark(i))
+                .or_else(|| self.assignment(i))
+                .or_else(|| self.projection(i))
+                .or_else(|| self.aggregate(i))
+                .or_else(|| self.grouping(i))
+                .or_else(|| self.embed(i))
+                .or_else(|| self.ordering(i))
+                .or_else(|| self.limit(i))
+                .or_else(|| self.offset(i))
+                .or_else(|| self.page(i))
+                .or_else(|| self.rows(i))
+                .or_else(|| self.absence(i))
+                .or_else(|| self.presence(i))
+                .or_else(|| self.column_clause(i))
+                .or_else(|| self.state(i))
+                .or_else(|| self.search(i))
+                .or_else(|| self.overdue(i))
+                .or_else(|| self.date_clause(i))
+                .or_else(|| self.by_related(i))
+                .or_else(|| self.bare_number(i))
+                .or_else(|| self.connective(i));
+            match end {
+                Some(end) => {
+                    self.used[i..end].iter_mut().for_each(|u| *u = true);
+                    i = end;
+                }
+                None => i += 1,
+            }
+        }
+
+        // A change must not reach more rows than asked for, so words that would have
+        // narrowed them down can't be left unread
+        if self.mutation.is_some() {
+            let unread = (0..self.tokens.len())
+                .find(|&i| !self.used[i] && !is_one_of(self.token(i), &[FILLER, VERBS].concat()));
+            if let Some(i) = unread {
+                self.reject(
+                    i,
+                    i + 1,
+                    "can't be read, so the rows to change aren't certain",
+                );
+            }
+        }
+
+        for condition in combine(std::mem::take(&mut self.items)) {
+            match condition {
+                Condition::Filter(filter) => self.query.filters.push(filter),
+                Condition::Group(group) => self.query.groups.push(group),
+            }
+        }
+        if let Some(problem) = self.problem {
+            return Err(problem);
+        }
+        self.apply_grouping();
+        if let Some(page) = self.page {
+            let size = *self.query.limit.get_or_insert(PAGE_SIZE);
+            self.query.offset = Some((page - 1).saturating_mul(size));
+        }
+        Ok((self.query, self.mutation))
+    }
+
+    // Records why the tokens `start..end` can't be read and returns `end`, so they
+    // aren't read as something else
+    fn reject(&mut self, start: usize, end: usize, problem: &str) -> usize {
+        let text: Vec<String> = self.tokens[start..end].iter().map(Token::text).collect();
+        self.problem
+            .get_or_insert_with(|| format!("'{}' {}", text.join(" "), problem));
+        end
+    }
+
+    // `delete all archived todos`, `remove todo 5`
+    fn delete(&mut self, i: usize) -> Option<usize> {
+        if !is_one_of(self.token(i), DELETE_VERBS) || self.mutation.is_some() {
+            return None;
+        }
+        self.mutation = Some(Mutation::delete());
+        Some(i + 1)
+    }
+
+    // `mark 'Walk the dog' as done`, `mark todo 5 as not done`, `flag todo 2 as blocked`.
+    // The words after `as` read like conditions, so `done` sets `is_done` to true; a
+    // single other word is a new status when the table has a `status` column.
+    fn mark(&mut self, i: usize) -> Option<usize> {
+        if !is_one_of(self.token(i), &["mark", "flag"]) {
+            return None;
+        }
+        let as_at = (i + 1..self.tokens.len())
+            .rev()
+            .find(|&k| !self.used[k] && self.word(k) == Some("as"))?;
+        let values = &self.tokens[as_at + 1..];
+        let set = self.assignments_from(values).or_else(|| {
+            let status = self.table.column("status")?;
+            match values {
+                [token @ (Token::Word(_) | Token::Quoted(_))] => Some(vec![Assignment::new(
+                    &status.name,
+                    value_for(status, token)?,
+                )]),
+                _ => None,
+            }
+        })?;
+        self.used[as_at..].iter_mut().for_each(|u| *u = true);
+        self.push_assignments(set);
+        Some(i + 1)
+    }
+
+    // Conditions like `done` or `not done` turned into the values that make them true
+    fn assignments_from(&self, tokens: &'a [Token]) -> Option<Vec<Assignment>> {
+        let values = Parser::new(tokens, self.schema, self.table, None, self.clock).parse();
+        if !values.groups.is_empty() {
+            return None;
+        }
+        let set: Option<Vec<Assignment>> = values
+            .filters
+            .into_iter()
+            .map(|filter| {
+                let value = match (filter.operator, filter.negated, filter.value) {
+                    (FilterOperator::Eq, false, value) => value,
+                    (FilterOperator::Eq, true, FilterValue::Bool(value)) => {
+                        FilterValue::Bool(!value)
+                    }
+                    (FilterOperator::Is, false, FilterValue::Null) => FilterValue::Null,
+                    _ => return None,
+                };
+                Some(Assignment::new(filter.column, value))
+            })
+            .collect();
+        set.filter(|set| !set.is_empty())
+    }
+
+    // `set status to archived`, `change the title of todo 5 to "Buy milk"`, `update todo
+    // 3 set is done = true and status = closed`: a column, then `to` or `=` and the new
+    // value. Words in between, like `of todo 5`, are left to pick the rows.
+    fn assignment(&mut self, i: usize) -> Option<usize> {
+        let verb = self.word(i)?;
+        if !["set", "change", "update"].contains(&verb) {
+            return None;
+        }
+        let mut j = i + 1;
+        if self.word(j) == Some("the") {
+            j += 1;
+        }
+        let Some((column, len)) = self.column_at(j) else {
+            // `update todo 3 set ...` names the values later
+            if verb == "update" && self.mutation.is_none() {
+                self.mutation = Some(Mutation::update(Vec::new()));
+                return Some(i + 1);
+            }
+            return None;
+        };
+        let to = (j + len..self.tokens.len())
+            .find(|&k| !self.used[k] && self.is_assignment_operator(k))?;
+        let value = value_for(column, self.token(to + 1)?)?;
+        self.used[to..to + 2].iter_mut().for_each(|u| *u = true);
+        let mut set = vec![Assignment::new(&column.name, value)];
+
+        // Further columns right after the value: `and title to x`, `, status = y`
+        let mut k = to + 2;
+        while matches!(self.token(k), Some(Token::Comma)) || self.word(k) == Some("and") {
+            let Some((column, len)) = self.column_at(k + 1) else {
+                break;
+            };
+            if !self.is_assignment_operator(k + 1 + len) {
+                break;
+            }
+            let Some(value) = self
+                .token(k + 2 + len)
+                .and_then(|token| value_for(column, token))
+            else {
+                break;
+            };
+            set.push(Assignment::new(&column.name, value));
+            self.used[k..k + 3 + len].iter_mut().for_each(|u| *u = true);
+            k += 3 + len;
+        }
+
+        self.push_assignments(set);
+        Some(j + len)
+    }
+
+    fn is_assignment_operator(&self, i: usize) -> bool {
+        self.word(i) == Some("to") || self.token(i) == Some(&Token::Symbol("=".into()))
+    }
+
+    fn push_assignments(&mut self, set: Vec<Assignment>) {
+        match &mut self.mutation {
+            Some(mutation) if mutation.action == MutationAction::Update => {
+                for assignment in set {
+                    mutation
+                        .set
+                        .retain(|existing| existing.column != assignment.column);
+                    mutation.set.push(assignment);
+                }
+            }
+            Some(_) => {}
+            None => self.mutation = Some(Mutation::update(set)),
+        }
+    }
+
+    // `or`, `and`, `not`, `but`, `except` and brackets between conditions
+    fn connective(&mut self, i: usize) -> Option<usize> {
+        let items: &[Item] = match self.token(i)? {
+            Token::Open => &[Item::Open],
+            Token::Close => &[Item::Close],
+            Token::Word(word) => match word.as_str() {
+                "or" => &[Item::Or],
+                "and" => &[Item::And],
+                "but" | "however" => &[Item::But],
+                "except" | "excluding" => &[Item::But, Item::Not],
+                word if NEGATIONS.contains(&word) => &[Item::Not],
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.items.extend_from_slice(items);
+        Some(i + 1)
+    }
+
+    fn token(&self, i: usize) -> Option<&'a Token> {
+        self.tokens.get(i)
+    }
+
+    fn word(&self, i: usize) -> Option<&'a str> {
+        self.token(i).and_then(Token::word)
+    }
+
+    // The column named at `i` and how many tokens its name takes. Names are read with
+    // underscores as spaces (`due date`), references without `_id` or `_at` (`user` for
+    // `user_id`, `created` for `created_at`) and synonyms count too.
+    fn column_at(&self, i: usize) -> Option<(&'a ColumnSchema, usize)> {
+        self.column_in(self.table, i)
+    }
+
+    // Like `column_at`, for a column of another table, e.g. a related one
+    fn column_in(&self, table: &'a TableSchema, i: usize) -> Option<(&'a ColumnSchema, usize)> {
+        if self.used.get(i).copied().unwrap_or(true) {
+            return None;
+        }
+        table
+            .columns
+            .iter()
+            .filter_map(|column| {
+                std::iter::once(column.name.replace('_', " "))
+                    .chain(std::iter::once(column.name.clone()))
+                    .chain(column.name.strip_suffix("_id").map(str::to_string))
+                    .chain(column.name.strip_suffix("_at").map(str::to_string))
+                    .chain(column.synonyms.iter().map(|s| s.to_lowercase()))
+                    .filter_map(|phrase| phrase_len(self.tokens, i, &phrase))
+                    .max()
+                    .map(|len| (column, len))
+            })
+            .max_by_key(|(_, len)| *len)
+    }
+
+    // `only id and title`, `just title, status`, `show title and status of todos`,
+    // `the names of users`
+    fn projection(&mut self, i: usize) -> Option<usize> {
+        if is_one_of(self.token(i), &["only", "just"]) {
+            let (columns, end) = self.column_list(i + 1)?;
+            self.query.select = columns;
+            return Some(end);
+        }
+
+        // A list of columns that belongs to the table named right after it
+        let follows = i == 0
+            || is_one_of(self.token(i - 1), VERBS)
+            || is_one_of(self.token(i - 1), &["the", "me", "what", "are", "their"]);
+        if !follows {
+            return None;
+        }
+        let (columns, end) = self.column_list(i)?;
+        if !is_one_of(self.token(end), &["of", "from", "for", "in"]) {
+            return None;
+        }
+        let mut table = end + 1;
+        while is_one_of(self.token(table), &["the", "all", "my", "every"]) {
+            table += 1;
+        }
+        if self.mention.map(|(start, _)| start) != Some(table) {
+            return None;
+        }
+        self.query.select = columns;
+        Some(end + 1)
+    }
+
+    // Column names separated by commas or `and`, and where the list ends
+    fn column_list(&self, i: usize) -> Option<(Vec<String>, usize)> {
+        self.column_list_in(self.table, i)
+    }
+
+    fn column_list_in(&self, table: &'a TableSchema, i: usize) -> Option<(Vec<String>, usize)> {
+        let mut columns = Vec::new();
+        let mut j = i;
+        while let Some((column, len)) = self.column_in(table, j) {
+            if !columns.contains(&column.name) {
+                columns.push(column.name.clone());
+            }
+            j += len;
+            let separator = match self.token(j) {
+                Some(Token::Comma) => 1 + usize::from(self.word(j + 1) == Some("and")),
+                Some(token) if token.is_word("and") => 1,
+                _ => break,
+            };
+            if self.column_in(table, j + separator).is_none() {
+                break;
+            }
+            j += separator;
+        }
+        (!columns.is_empty()).then_some((columns, j))
+    }
+
+    // `how many`, `number of`, `count`, `average budget`, `total of amount`, `max id`
+    fn aggregate(&mut self, i: usize) -> Option<usize> {
+        let word = self.word(i)?;
+        let count_len = match word {
+            "how" if self.word(i + 1) == Some("many") => Some(2),
+            "count" | "number" if self.word(i + 1) == Some("of") => Some(2),
+            "count" => Some(1),
+            "with" if is_one_of(self.token(i + 1), &["count", "counts"]) => Some(2),
+            "total" if self.word(i + 1) == Some("number") && self.word(i + 2) == Some("of") => {
+                Some(3)
+            }
+            _ => None,
+        };
+        if let Some(len) = count_len {
+            self.push_aggregate(Aggregate::count());
+            return Some(i + len);
+        }
+
+        let function = AGGREGATES
+            .iter()
+            .find(|(_, words)| words.contains(&word))
+            .map(|(function, _)| *function)?;
+        let mut j = i + 1;
+        while is_one_of(self.token(j), &["of", "the"]) {
+            j += 1;
+        }
+        let (column, len) = self.column_at(j)?;
+        self.push_aggregate(Aggregate::of(function, &column.name));
+        Some(j + len)
+    }
+
+    fn push_aggregate(&mut self, aggregate: Aggregate) {
+        if !self.query.aggregates.contains(&aggregate) {
+            self.query.aggregates.push(aggregate);
+        }
+    }
+
+    // `per status`, `by status and user`, `for each status`, `grouped by user`: the
+    // columns an aggregate is broken down by. Applied once the whole question is read,
+    // as the aggregate may come later (`grouped by status with counts`).
+    fn grouping(&mut self, i: usize) -> Option<usize> {
+        let (j, explicit) = match (self.word(i)?, self.word(i + 1)) {
+            ("by", _) => (i + 1, false),
+            ("per", _) => (i + 1, true),
+            ("for", Some("each" | "every")) => (i + 2, true),
+            ("grouped" | "group" | "broken" | "split", Some("by" | "down")) => {
+                (i + 2 + usize::from(self.word(i + 2) == Some("by")), true)
+            }
+            _ => return None,
+        };
+        let (columns, end) = self.column_list(j)?;
+        // `posts by user 3` is a condition on the user
+        if matches!(self.token(end), Some(Token::Number(_) | Token::Quoted(_))) {
+            return None;
+        }
+        let (direction, direction_len) = self.direction(end);
+        let grouping = self.grouping.get_or_insert_with(Default::default);
+        grouping.explicit |= explicit;
+        if direction_len > 0 {
+            grouping.direction = Some(direction);
+        }
+        for column in columns {
+            if !grouping.columns.contains(&column) {
+                grouping.columns.push(column);
+            }
+        }
+        Some(end + direction_len)
+    }
+
+    // `with author email`, `including the user`, `with their open todos`: rows of a
+    // related table embedded in each row. Words between `with` and the related table are
+    // conditions on its rows, read by a parser of their own.
+    fn embed(&mut self, i: usize) -> Option<usize> {
+        if !is_one_of(self.token(i), &["with", "including", "plus"]) {
+            return None;
+        }
+        let mut j = i + 1;
+        while is_one_of(
+            self.token(j),
+            &["their", "its", "the", "his", "her", "each"],
+        ) {
+            j += 1;
+        }
+
+        // A referencing column stands for the row it points to, maybe followed by
+        // which of its columns to show
+        if let Some((column, len)) = self.column_at(j) {
+            let reference = column.references.as_ref()?;
+            let Some(related) = self.schema.table(&reference.table) else {
+                let problem = format!("points to {}, which is not in the schema", reference.table);
+                return Some(self.reject(j, j + len, &problem));
+            };
+            let (columns, end) = self
+                .column_list_in(related, j + len)
+                .unwrap_or((Vec::new(), j + len));
+            self.push_embed(Embed::new(&related.name, columns));
+            return Some(end);
+        }
+
+        let unused = (j..j + 4).take_while(|&k| !self.used.get(k).copied().unwrap_or(true));
+        let Some((k, related, len)) = unused
+            .clone()
+            .find_map(|k| self.related_at(k).map(|(related, len)| (k, related, len)))
+        else {
+            // `users with their todos` when nothing links the two tables is an embed that
+            // can't be made, not a question about users alone
+            let (k, len) = unused.clone().find_map(|k| {
+                let (_, start, len) = find_table(self.schema, &self.tokens[k..])?;
+                (start == 0).then_some((k, len))
+            })?;
+            let problem = format!(
+                "is not related to {}, so its rows can't be included",
+                self.table.name
+            );
+            return Some(self.reject(k, k + len, &problem));
+        };
+        let conditions = Parser::new(&self.tokens[j..k], self.schema, related, None, self.clock);
+        for mut filter in conditions.parse().filters {
+            filter.column = format!("{}.{}", related.name, filter.column);
+            self.items.push(Item::Condition(filter));
+        }
+        self.push_embed(Embed::new(&related.name, Vec::<String>::new()));
+        Some(k + len)
+    }
+
+    // A table related to this one named at `i`, and how many tokens its name takes
+    fn related_at(&self, i: usize) -> Option<(&'a TableSchema, usize)> {
+        self.schema
+            .related_tables(&self.table.name)
+            .into_iter()
+            .filter_map(|table| {
+                table
+                    .terms()
+                    .iter()
+                    .filter_map(|term| phrase_len(self.tokens, i, term))
+                    .max()
+                    .map(|len| (table, len))
+            })
+            .max_by_key(|(_, len)| *len)
+    }
+
+    // Embedding a table twice merges the two, `posts by ann with author email`
+    fn push_embed(&mut self, embed: Embed) {
+        match self
+            .query
+            .embeds
+            .iter_mut()
+            .find(|existing| existing.relation == embed.relation)
+        {
+            Some(existing) => {
+                existing.inner |= embed.inner;
+                for column in embed.columns {
+                    if !existing.columns.contains(&column) {
+                        existing.columns.push(column);
+                    }
+                }
+            }
+            None => self.query.embeds.push(embed),
+        }
+    }
+
+    // `posts by alice`, `todos by "Ann Lee"`: rows whose referenced row, like the user
+    // of a post, has that name. The embed is inner, so the filter drops the other rows.
+    fn by_related(&mut self, i: usize) -> Option<usize> {
+        if self.word(i) != Some("by") {
+            return None;
+        }
+        let (related, column) = self.table.columns.iter().find_map(|column| {
+            let related = self.schema.table(&column.references.as_ref()?.table)?;
+            let name = related
+                .column("name")
+                .or_else(|| related.column("title"))
+                .or_else(|| related.first_column_of(ColumnType::Text))?;
+            Some((related, name))
+        })?;
+
+        let mut words = Vec::new();
+        let mut end = i + 1;
+        while let Some(token) = self.token(end) {
+            if self.used[end] || words.len() == 3 || self.is_date_at(end) {
+                break;
+            }
+            match token {
+                Token::Quoted(text) if words.is_empty() => {
+                    words.push(text.clone());
+                    end += 1;
+                    break;
+                }
+                Token::Word(word)
+                    if !FILLER.contains(&word.as_str())
+                        && !self.starts_clause(end)
+                        && find_table(self.schema, &self.tokens[end..end + 1]).is_none() =>
+                {
+                    words.push(word.clone())
+                }
+                _ => break,
+            }
+            end += 1;
+        }
+        if words.is_empty() {
+            return None;
+        }
+
+        self.push_embed(Embed::new(&related.name, Vec::<String>::new()).inner());
+        self.items.push(Item::Condition(Filter::contains(
+            format!("{}.{}", related.name, column.name),
+            &words.join(" "),
+        )));
+        Some(end)
+    }
+
+    // Whether a date expression like `friday` or `next week` starts at `i`, even when
+    // the time isn't known to resolve it
+    fn is_date_at(&self, i: usize) -> bool {
+        let clock = self.clock.unwrap_or(Clock { now: 0, offset: 0 });
+        parse_span(self.tokens, i, &clock, true, true).is_some()
+    }
+
+    // Grouped columns are selected next to the aggregates; `todos per status` alone
+    // counts them, while a plain `todos by title` without an aggregate sorts by it
+    fn apply_grouping(&mut self) {
+        if let Some(grouping) = self.grouping.take() {
+            if grouping.explicit && self.query.aggregates.is_empty() {
+                self.query.aggregates.push(Aggregate::count());
+            }
+            let sorted = self.query.aggregates.is_empty() || grouping.direction.is_some();
+            for column in grouping.columns {
+                if !self.query.aggregates.is_empty() && !self.query.select.contains(&column) {
+                    self.query.select.push(column.clone());
+                }
+                if sorted && !self.query.order.iter().any(|o| o.column == column) {
+                    let direction = grouping.direction.unwrap_or(SortDirection::Asc);
+                    self.query = std::mem::take(&mut self.query).order_by(column, direction);
+                }
+            }
+        }
+        // Aggregated rows can only be sorted by what they are grouped by
+        if !self.query.aggregates.is_empty() {
+            let select = &self.query.select;
+            self.query
+                .order
+                .retain(|order| select.contains(&order.column));
+        }
+    }
+
+    // `sorted by title desc`, `order by status, then due date descending`,
+    // `alphabetically by name`, `newest`, `oldest`
+    fn ordering(&mut self, i: usize) -> Option<usize> {
+        if is_one_of(self.token(i), NEWEST) {
+            return self.order_by_default(i, SortDirection::Desc);
+        }
+        if is_one_of(self.token(i), OLDEST) {
+            return self.order_by_default(i, SortDirection::Asc);
+        }
+        if self.word(i) == Some("most") && self.word(i + 1) == Some("recent") {
+            return self.order_by_default(i + 1, SortDirection::Desc);
+        }
+        if self.alphabetical(i).is_some() {
+            return self.alphabetical_order(i);
+        }
+
+        if !is_one_of(
+            self.token(i),
+            &["order", "ordered", "sort", "sorted", "ordering", "sorting"],
+        ) {
+            return None;
+        }
+        let mut j = i + 1;
+        // `sort the todos by title`: the table can be named before the columns
+        let mut table = j;
+        while is_one_of(self.token(table), &["the", "all", "my", "every"]) {
+            table += 1;
+        }
+        if let Some((start, len)) = self.mention.filter(|&(start, _)| start == table) {
+            j = start + len;
+        }
+        if self.alphabetical(j).is_some() {
+            return self.alphabetical_order(j);
+        }
+        if self.word(j) == Some("by") {
+            j += 1;
+        }
+        let (column, len) = self.column_at(j)?;
+        let (direction, direction_len) = self.direction(j + len);
+        let mut keys = vec![(column, direction)];
+        j += len + direction_len;
+
+        // Further keys after `,`, `then` or `and`: `by status, then by title desc`
+        loop {
+            let mut k = j;
+            let separator = match self.token(k) {
+                Some(Token::Comma) => Some(","),
+                token => token.and_then(Token::word),
+            };
+            match separator {
+                Some("," | "then" | "and") => k += 1,
+                _ => break,
+            }
+            if self.word(k) == Some("then") {
+                k += 1;
+            }
+            if self.word(k) == Some("by") {
+                k += 1;
+            }
+            let Some((column, len)) = self.column_at(k) else {
+                break;
+            };
+            let (direction, direction_len) = self.direction(k + len);
+            // `sorted by title and status active` goes on with a condition instead
+            if separator == Some("and")
+                && direction_len == 0
+                && !matches!(self.token(k + len), None | Some(Token::Comma))
+                && self.word(k + len) != Some("then")
+            {
+                break;
+            }
+            keys.push((column, direction));
+            j = k + len + direction_len;
+        }
+
+        for (column, direction) in keys {
+            self.query = std::mem::take(&mut self.query).order_by(&column.name, direction);
+        }
+        Some(j)
+    }
+
+    // Sort direction after an ordered column and how many tokens it takes: `desc`,
+    // `in descending order`, `highest first`, `z-a`. Ascending when none is given.
+    fn direction(&self, j: usize) -> (SortDirection, usize) {
+        let k = j + usize::from(self.word(j) == Some("in"));
+        let direction = if is_one_of(self.token(k), DESCENDING) {
+            SortDirection::Desc
+        } else if is_one_of(self.token(k), ASCENDING) {
+            SortDirection::Asc
+        } else if self.word(j + 1) == Some("first") {
+            return match self.word(j) {
+                Some(word) if HIGHEST.contains(&word) || NEWEST.contains(&word) => {
+                    (SortDirection::Desc, 2)
+                }
+                Some(word) if LOWEST.contains(&word) || OLDEST.contains(&word) => {
+                    (SortDirection::Asc, 2)
+                }
+                _ => (SortDirection::Asc, 0),
+            };
+        } else {
+            return match self.word(j) {
+                Some("z-a") => (SortDirection::Desc, 1),
+                Some("a-z") => (SortDirection::Asc, 1),
+                _ => (SortDirection::Asc, 0),
+            };
+        };
+        // `in descending order` takes `in` and `order` along
+        let order = usize::from(self.word(k + 1) == Some("order"));
+        (direction, k - j + 1 + order)
+    }
+
+    // `alphabetically`, `alphabetical`, `reverse alphabetical`: the direction and the
+    // number of tokens
+    fn alphabetical(&self, i: usize) -> Option<(SortDirection, usize)> {
+        let reverse = usize::from(self.word(i) == Some("reverse"));
+        match self.word(i + reverse)? {
+            "alphabetically" | "alphabetical" if reverse == 1 => Some((SortDirection::Desc, 2)),
+            "alphabetically" | "alphabetical" => Some((SortDirection::Asc, 1)),
+            _ => None,
+        }
+    }
+
+    // `alphabetically by title`, `in alphabetical order`: by the named column, or the
+    // table's title or name, or its first text column
+    fn alphabetical_order(&mut self, i: usize) -> Option<usize> {
+        let (direction, len) = self.alphabetical(i)?;
+        let mut j = i + len;
+        if self.word(j) == Some("order") {
+            j += 1;
+        }
+        let named = match self.word(j) {
+            Some("by" | "on") => self
+                .column_at(j + 1)
+                .map(|(column, len)| (column, j + 1 + len)),
+            _ => None,
+        };
+        let (column, end) = match named {
+            Some(named) => named,
+            None => {
+                let column = self
+                    .table
+                    .column("title")
+                    .or_else(|| self.table.column("name"))
+                    .or_else(|| self.table.first_column_of(ColumnType::Text))?;
+                (column, j)
+            }
+        };
+        if !self.query.order.iter().any(|o| o.column == column.name) {
+            self.query = std::mem::take(&mut self.query).order_by(&column.name, direction);
+        }
+        Some(end)
+    }
+
+    // Orders by the creation time, or the first timestamp column
+    fn order_by_default(&mut self, i: usize, direction: SortDirection) -> Option<usize> {
+        let column = self
+            .table
+            .column("created_at")
+            .or_else(|| self.table.first_column_of(ColumnType::Timestamp))?;
+        if !self.query.order.iter().any(|o| o.column == column.name) {
+            self.query = std::mem::take(&mut self.query).order_by(&column.name, direction);
+        }
+        // `newest first`
+        Some(i + 1 + usize::from(self.word(i + 1) == Some("first")))
+    }
+
+    // A count at `i`, in digits or words, and how many tokens it takes. It may be zero
+    // or negative, which the clause reading it rejects.
+    fn count(&self, i: usize) -> Option<(i64, usize)> {
+        if self.used.get(i).copied().unwrap_or(true) {
+            return None;
+        }
+        number_at(self.tokens, i)
+    }
+
+    // The count as a limit, page or page size, or the problem with the clause
+    // `start..end` when it isn't positive
+    fn positive(&mut self, count: i64, start: usize, end: usize) -> Result<u64, usize> {
+        match u64::try_from(count) {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(self.reject(start, end, NOT_POSITIVE)),
+        }
+    }
+
+    // `first 5`, `top twenty`, `limit 3`, `last 5`, `next 15`, `show 5 todos`,
+    // `with 20 per page`
+    fn limit(&mut self, i: usize) -> Option<usize> {
+        if let Some(end) = self.page_size(i) {
+            return Some(end);
+        }
+        // `with 20 per page` rather than a search for 20
+        if is_one_of(self.token(i), &["with", "at"]) {
+            if let Some(end) = self.page_size(i + 1) {
+                return Some(end);
+            }
+        }
+        // A count right before the table, maybe with `newest` in between
+        if let Some((count, len)) = self.count(i) {
+            let mut j = i + len;
+            if is_one_of(self.token(j), NEWEST) || is_one_of(self.token(j), OLDEST) {
+                j += 1;
+            }
+            if self.mention.map(|(start, _)| start) != Some(j) {
+                return None;
+            }
+            match self.positive(count, i, i + len) {
+                Ok(count) => self.query.limit = Some(count),
+                Err(end) => return Some(end),
+            }
+            return Some(i + len);
+        }
+
+        let word = self.word(i)?;
+        if !["first", "top", "limit", "last", "next"].contains(&word) {
+            return None;
+        }
+        let (count, len) = self.count(i + 1)?;
+        // `last 7 days` is a date range
+        if is_unit(self.token(i + 1 + len)) {
+            return None;
+        }
+        match self.positive(count, i, i + 1 + len) {
+            Ok(count) => self.query.limit = Some(count),
+            Err(end) => return Some(end),
+        }
+        if word == "last" {
+            self.order_by_default(i, SortDirection::Desc);
+        }
+        Some(i + 1 + len)
+    }
+
+    // `20 per page`, `20 rows per page`
+    fn page_size(&mut self, i: usize) -> Option<usize> {
+        let (count, len) = self.count(i)?;
+        let j = i + len + usize::from(self.word(i + len).is_some_and(is_row_word));
+        if self.word(j) != Some("per") || self.word(j + 1) != Some("page") {
+            return None;
+        }
+        match self.positive(count, i, j + 2) {
+            Ok(count) => self.query.limit = Some(count),
+            Err(end) => return Some(end),
+        }
+        Some(j + 2)
+    }
+
+    // `skip 20`, `offset 10`, `skipping the first 5`, `after the first 30`
+    fn offset(&mut self, i: usize) -> Option<usize> {
+        let mut j = match self.word(i)? {
+            "skip" | "skipping" | "offset" => i + 1,
+            "after"
+                if self.word(i + 1) == Some("first")
+                    || (self.word(i + 1) == Some("the") && self.word(i + 2) == Some("first")) =>
+            {
+                i + 1
+            }
+            _ => return None,
+        };
+        if self.word(j) == Some("by") {
+            j += 1;
+        }
+        if self.word(j) == Some("the") {
+            j += 1;
+        }
+        if self.word(j) == Some("first") {
+            j += 1;
+        }
+        let (count, len) = self.count(j)?;
+        let Ok(count) = u64::try_from(count) else {
+            return Some(self.reject(i, j + len, "can't be negative"));
+        };
+        self.query.offset = Some(count);
+        j += len;
+        // `skip 5 rows`
+        j += usize::from(self.word(j).is_some_and(is_row_word));
+        Some(j)
+    }
+
+    // `page 3`, `page three`, `the second page`; the page size is the limit, if any
+    fn page(&mut self, i: usize) -> Option<usize> {
+        if self.word(i) == Some("page") {
+            if self.word(i + 1) == Some("size") {
+                // `page size 20`
+                let (count, len) = self.count(i + 2)?;
+                match self.positive(count, i, i + 2 + len) {
+                    Ok(count) => self.query.limit = Some(count),
+                    Err(end) => return Some(end),
+                }
+                return Some(i + 2 + len);
+            }
+            let (page, len) = self.count(i + 1)?;
+            match self.positive(page, i, i + 1 + len) {
+                Ok(page) => self.page = Some(page),
+                Err(end) => return Some(end),
+            }
+            return Some(i + 1 + len);
+        }
+        let page = ORDINALS
+            .iter()
+            .position(|word| self.word(i) == Some(word))?;
+        if self.word(i + 1) != Some("page") {
+            return None;
+        }
+        self.page = Some(page as u64 + 1);
+        Some(i + 2)
+    }
+
+    // `todos 10 to 20`, `rows 1 through 5`: the rows at those places, counted from 1 and
+    // including both ends, so the first is skipped over and the rest are the limit
+    fn rows(&mut self, i: usize) -> Option<usize> {
+        let after_table = self.mention.map(|(start, len)| start + len) == Some(i);
+        let j = i + usize::from(self.word(i).is_some_and(is_row_word));
+        if j == i && !after_table {
+            return None;
+        }
+        let (first, len) = self.count(j)?;
+        if !is_one_of(self.token(j + len), &["to", "through", "until", "till"]) {
+            return None;
+        }
+        let (last, last_len) = self.count(j + len + 1)?;
+        let end = j + len + 1 + last_len;
+        let (Ok(first), Ok(last)) = (u64::try_from(first), u64::try_from(last)) else {
+            return Some(self.reject(i, end, "is not a range of rows"));
+        };
+        if first == 0 || last < first {
+            return Some(self.reject(i, end, "is not a range of rows"));
+        }
+        self.query.offset = Some(first - 1);
+        self.query.limit = Some(last - first + 1);
+        Some(end)
+    }
+
+    // `without due date`, `missing email`, `no description`
+    fn absence(&mut self, i: usize) -> Option<usize> {
+        if !is_one_of(self.token(i), &["without", "missing", "lacking", "no"]) {
+            return None;
+        }
+        let (column, len) = self.column_at(i + 1)?;
+        let filter = if column.column_type == ColumnType::Boolean {
+            Filter::eq(&column.name, false)
+        } else {
+            Filter::is_null(&column.name)
+        };
+        self.items.push(Item::Condition(filter));
+        Some(i + 1 + len)
+    }
+
+    // `with due date`, `having status active`, `with title like dog`
+    fn presence(&mut self, i: usize) -> Option<usize> {
+        if !is_one_of(self.token(i), &["with", "having", "has", "have"]) {
+            return None;
+        }
+        let (column, len) = self.column_at(i + 1)?;
+        if let Some(end) = self.column_clause(i + 1) {
+            return Some(end);
+        }
+        // `with id greater than x` is a condition that couldn't be read, not one that
+        // the column is set
+        if let Some(operator_len) = self.operator_len(i + 1 + len) {
+            let end = (i + 2 + len + operator_len).min(self.tokens.len());
+            return Some(self.reject(i + 1, end, "is not a condition that can be read"));
+        }
+        let filter = if column.column_type == ColumnType::Boolean {
+            Filter::eq(&column.name, true)
+        } else {
+            Filter::not_null(&column.name)
+        };
+        self.items.push(Item::Condition(filter));
+        Some(i + 1 + len)
+    }
+
+    // A column followed by a condition: `due date is not null`, `title contains dog`,
+    // `id > 5`, `status is active`, `id 7`
+    fn column_clause(&mut self, i: usize) -> Option<usize> {
+        let (column, len) = self.column_at(i)?;
+        let j = i + len;
+
+        if let Some(end) = self.date_condition(column, j) {
+            return Some(end);
+        }
+
+        // `is null`, `is not empty`
+        if is_one_of(self.token(j), &["is"]) || self.token(j) == Some(&Token::Symbol("=".into())) {
+            let negated = self.word(j + 1) == Some("not");
+            let k = j + 1 + usize::from(negated);
+            if is_one_of(
+                self.token(k),
+                &["null", "empty", "missing", "blank", "unset"],
+            ) {
+                let filter = Filter::is_null(&column.name);
+                self.items.push(Item::Condition(if negated {
+                    filter.negate()
+                } else {
+                    filter
+                }));
+                return Some(k + 1);
+            }
+        }
+
+        // `not like work`, `does not contain dog`, `not in 1, 2`, `is not between 3 and 7`
+        let (negated, len) = self.negation(j);
+        let first = self.items.len();
+        if negated {
+            self.items.push(Item::Not);
+        }
+        let end = self
+            .column_search(column, j + len)
+            .or_else(|| self.one_of(column, j + len))
+            .or_else(|| self.between(column, j + len));
+        if end.is_some() {
+            return end;
+        }
+        self.items.truncate(first);
+
+        if let Some((operator, op_len)) = self.comparison(j) {
+            let value = value_for(column, self.token(j + op_len)?)?;
+            self.items
+                .push(Item::Condition(Filter::new(&column.name, operator, value)));
+            return Some(self.alternatives(j + op_len + 1, |token| {
+                value_for(column, token).map(|value| Filter::new(&column.name, operator, value))
+            }));
+        }
+
+        // Bare values: `id 7`, `status active`
+        let value = match (column.column_type, self.token(j)?) {
+            (ColumnType::Integer, Token::Number(number)) => FilterValue::Int(*number),
+            (ColumnType::Text, token @ (Token::Word(_) | Token::Quoted(_)))
+                if !self.is_search_column(column) && !is_one_of(Some(token), FILLER) =>
+            {
+                FilterValue::Text(token.text())
+            }
+            _ => return None,
+        };
+        self.items.push(Item::Condition(Filter::new(
+            &column.name,
+            FilterOperator::Eq,
+            value,
+        )));
+        Some(self.alternatives(j + 1, |token| {
+            value_for(column, token)
+                .map(|value| Filter::new(&column.name, FilterOperator::Eq, value))
+        }))
+    }
+
+    // How many tokens a comparison, search, list or range at `j` takes before the value
+    // it needs: `greater than`, `is not`, `like`, `in`, `between`
+    fn operator_len(&self, j: usize) -> Option<usize> {
+        if let Some((_, len)) = self.comparison(j) {
+            return Some(len);
+        }
+        let (_, len) = self.negation(j);
+        let operators = [SEARCH, &["in", "between", "one", "any"]].concat();
+        is_one_of(self.token(j + len), &operators).then_some(len + 1)
+    }
+
+    // An optional `is` and a negation before a search, list or range: whether it is
+    // negated and how many tokens that takes
+    fn negation(&self, j: usize) -> (bool, usize) {
+        let is = usize::from(is_one_of(self.token(j), &["is", "are"]));
+        match (self.word(j + is), self.word(j + is + 1)) {
+            (Some("does" | "do"), Some("not")) => (true, is + 2),
+            (Some("not" | "isn't" | "aren't" | "doesn't" | "don't"), _) => (true, is + 1),
+            _ => (false, is),
+        }
+    }
+
+    // `title like dog`, `name containing ann or bob`
+    fn column_search(&mut self, column: &ColumnSchema, j: usize) -> Option<usize> {
+        if column.column_type != ColumnType::Text || !is_one_of(self.token(j), SEARCH) {
+            return None;
+        }
+        let term = self.search_term(j + 1)?;
+        self.items
+            .push(Item::Condition(Filter::contains(&column.name, &term)));
+        Some(self.alternatives(j + 2, |token| {
+            Some(Filter::contains(&column.name, &token.text()))
+        }))
+    }
+
+    // `id in 1, 2, 3`, `status in (open, blocked)`, `id one of 4 or 5`
+    fn one_of(&mut self, column: &ColumnSchema, j: usize) -> Option<usize> {
+        let mut k = match (self.word(j), self.word(j + 1)) {
+            (Some("in"), _) => j + 1,
+            (Some("one" | "any"), Some("of")) => j + 2,
+            _ => return None,
+        };
+        let bracketed = self.token(k) == Some(&Token::Open);
+        k += usize::from(bracketed);
+
+        let mut values = vec![value_for(column, self.token(k)?)?];
+        k += 1;
+        loop {
+            // `,`, `or`, `and` or `, and` before the next value
+            let mut next = k + usize::from(self.token(k) == Some(&Token::Comma));
+            if matches!(self.word(next), Some("or" | "and")) {
+                next += 1;
+            }
+            if next == k || self.starts_clause(next) {
+                break;
+            }
+            let Some(value) = self.token(next).and_then(|token| value_for(column, token)) else {
+                break;
+            };
+            values.push(value);
+            k = next + 1;
+        }
+        if bracketed {
+            if self.token(k) != Some(&Token::Close) {
+                return None;
+            }
+            k += 1;
+        }
+
+        self.items.push(Item::Condition(Filter::new(
+            &column.name,
+            FilterOperator::In,
+            FilterValue::List(values),
+        )));
+        Some(k)
+    }
+
+    // `id between 3 and 7`, both ends included
+    fn between(&mut self, column: &ColumnSchema, j: usize) -> Option<usize> {
+        if self.word(j) != Some("between") || self.word(j + 2) != Some("and") {
+            return None;
+        }
+        let mut low = value_for(column, self.token(j + 1)?)?;
+        let mut high = value_for(column, self.token(j + 3)?)?;
+        if let (FilterValue::Int(a), FilterValue::Int(b)) = (&low, &high) {
+            if a > b {
+                std::mem::swap(&mut low, &mut high);
+            }
+        }
+        self.push_together(vec![
+            Filter::new(&column.name, FilterOperator::Gte, low),
+            Filter::new(&column.name, FilterOperator::Lte, high),
+        ]);
+        Some(j + 4)
+    }
+
+    // Further values for the condition just read, `status open or blocked` or `titled dog
+    // or cat`. They are bracketed with it, so `and` after them applies to all of them.
+    // Returns where the last value ends.
+    fn alternatives(&mut self, mut end: usize, filter: impl Fn(&Token) -> Option<Filter>) -> usize {
+        let first = self.items.len() - 1;
+        while self.word(end) == Some("or") && !self.starts_clause(end + 1) {
+            let Some(next) = self
+                .search_term(end + 1)
+                .and(self.token(end + 1))
+                .and_then(&filter)
+            else {
+                break;
+            };
+            self.items.extend([Item::Or, Item::Condition(next)]);
+            end += 2;
+        }
+        if self.items.len() > first + 1 {
+            self.items.insert(first, Item::Open);
+            self.items.push(Item::Close);
+        }
+        end
+    }
+
+    // Whether the token at `i` means something on its own, like a column or a keyword,
+    // rather than being another value
+    fn starts_clause(&self, i: usize) -> bool {
+        if self.column_at(i).is_some() {
+            return true;
+        }
+        self.word(i).is_some_and(|word| {
+            KEYWORDS.iter().any(|keywords| keywords.contains(&word)) || self.opposite_state(word)
+        })
+    }
+
+    // `due this week`, `created before march 3`, `due today or tomorrow`, `from 1 jan to
+    // 3 feb`: days of the local calendar on a timestamp or date column
+    fn date_condition(&mut self, column: &'a ColumnSchema, j: usize) -> Option<usize> {
+        if !matches!(column.column_type, ColumnType::Timestamp | ColumnType::Date) {
+            return None;
+        }
+        let clock = self.clock?;
+        let mut relation = self.word(j).filter(|word| DATE_PREPOSITIONS.contains(word));
+        let k = j + usize::from(relation.is_some());
+        if relation == Some("within") {
+            if let Some(end) = self.within(column, k, &clock) {
+                return Some(end);
+            }
+        }
+        // `monday` is the coming one for a due date but the last one otherwise
+        let past = relation == Some("since") || !is_due(column);
+        let Some((mut span, len)) = parse_span(self.tokens, k, &clock, relation.is_some(), past)
+        else {
+            let len = invalid_date(self.tokens, k)?;
+            return Some(self.reject(k, k + len, "is not a date"));
+        };
+        let mut end = k + len;
+
+        // One range from the earliest to the latest day, whichever is named first
+        if matches!(relation, Some("from" | "between")) && is_one_of(self.token(end), RANGE_ENDS) {
+            match parse_span(self.tokens, end + 1, &clock, true, past) {
+                Some((last, len)) => {
+                    span = DaySpan {
+                        start: span.start.min(last.start),
+                        end: span.end.max(last.end),
+                    };
+                    relation = None;
+                    end += 1 + len;
+                }
+                None => {
+                    if let Some(len) = invalid_date(self.tokens, end + 1) {
+                        return Some(self.reject(end + 1, end + 1 + len, "is not a date"));
+                    }
+                }
+            }
+        }
+
+        let first = self.items.len();
+        if self.push_span(column, relation, span, &clock).is_none() {
+            return Some(self.reject(k, end, "is outside the dates that can be queried"));
+        }
+        while self.word(end) == Some("or") {
+            let Some((span, len)) = parse_span(self.tokens, end + 1, &clock, true, past) else {
+                break;
+            };
+            self.items.push(Item::Or);
+            if self.push_span(column, relation, span, &clock).is_none() {
+                return Some(self.reject(
+                    end + 1,
+                    end + 1 + len,
+                    "is outside the dates that can be queried",
+                ));
+            }
+            end += 1 + len;
+        }
+        if self.items.len() > first + 1 {
+            self.items.insert(first, Item::Open);
+            self.items.push(Item::Close);
+        }
+        Some(end)
+    }
+
+    // `due within 3 weeks`: from now until that long from now
+    fn within(&mut self, column: &ColumnSchema, k: usize, clock: &Clock) -> Option<usize> {
+        let (days, len) = parse_duration(self.tokens, k, clock)?;
+        let (start, end) = match column.column_type {
+            ColumnType::Date => (
+                Some(clock.date(clock.today())),
+                Some(clock.date(clock.today() + days + 1)),
+            ),
+            _ => (Some(clock.now_timestamp()), clock.timestamp_in(days)),
+        };
+        let (Some(start), Some(end)) = (start, end) else {
+            return Some(self.reject(k, k + len, "is outside the dates that can be queried"));
+        };
+        self.push_together(vec![
+            Filter::new(&column.name, FilterOperator::Gte, FilterValue::Text(start)),
+            Filter::new(&column.name, FilterOperator::Lt, FilterValue::Text(end)),
+        ]);
+        Some(k + len)
+    }
+
+    // `None` when a day of the span is beyond what timestamps can hold
+    fn push_span(
+        &mut self,
+        column: &ColumnSchema,
+        relation: Option<&str>,
+        span: DaySpan,
+        clock: &Clock,
+    ) -> Option<()> {
+        let value = |day: i64| match column.column_type {
+            ColumnType::Date => Some(FilterValue::Text(clock.date(day))),
+            _ => clock.day_start(day).map(FilterValue::Text),
+        };
+        let filter = |operator, day| Some(Filter::new(&column.name, operator, value(day)?));
+        let filters = match relation {
+            Some("before") => vec![filter(FilterOperator::Lt, span.start)?],
+            Some("until" | "till" | "by") => vec![filter(FilterOperator::Lt, span.end)?],
+            Some("after") => vec![filter(FilterOperator::Gte, span.end)?],
+            Some("since") => vec![filter(FilterOperator::Gte, span.start)?],
+            _ => vec![
+                filter(FilterOperator::Gte, span.start)?,
+                filter(FilterOperator::Lt, span.end)?,
+            ],
+        };
+        self.push_together(filters);
+        Some(())
+    }
+
+    // Conditions that only make sense together, bracketed so a `not` or `or` next to
+    // them applies to all of them
+    fn push_together(&mut self, filters: Vec<Filter>) {
+        if let [filter] = filters.as_slice() {
+            self.items.push(Item::Condition(filter.clone()));
+            return;
+        }
+        self.items.push(Item::Open);
+        self.items.extend(filters.into_iter().map(Item::Condition));
+        self.items.push(Item::Close);
+    }
+
+    // A date without a column, `todos from last week` or `posts since monday`, is about
+    // when rows were created
+    fn date_clause(&mut self, i: usize) -> Option<usize> {
+        let column = self
+            .table
+            .column("created_at")
+            .or_else(|| self.table.first_column_of(ColumnType::Timestamp))
+            .or_else(|| self.table.first_column_of(ColumnType::Date))?;
+        self.date_condition(column, i)
+    }
+
+    // Past the due date and, when the table has a flag like `is_done`, not done yet
+    fn overdue(&mut self, i: usize) -> Option<usize> {
+        if self.word(i) != Some("overdue") {
+            return None;
+        }
+        let clock = self.clock?;
+        let due = self.table.columns.iter().find(|column| is_due(column))?;
+        let now = match due.column_type {
+            ColumnType::Date => clock.date(clock.today()),
+            _ => clock.now_timestamp(),
+        };
+
+        let mut filters = vec![Filter::new(
+            &due.name,
+            FilterOperator::Lt,
+            FilterValue::Text(now),
+        )];
+        if let Some(flag) = self.flag_column() {
+            filters.push(Filter::eq(&flag.name, false));
+        }
+        self.push_together(filters);
+        Some(i + 1)
+    }
+
+    // Comparison words at `i`: the operator and how many tokens it takes. A leading
+    // `is` is optional, so `id is over 5` reads like `id over 5`.
+    fn comparison(&self, i: usize) -> Option<(FilterOperator, usize)> {
+        use FilterOperator::*;
+
+        if let Some(Token::Symbol(symbol)) = self.token(i) {
+            let operator = match symbol.as_str() {
+                "=" => Eq,
+                "!=" => Neq,
+                "<" => Lt,
+                "<=" => Lte,
+                ">" => Gt,
+                ">=" => Gte,
+                _ => return None,
+            };
+            return Some((operator, 1));
+        }
+
+        let first = self.word(i)?;
+        let second = self.word(i + 1).unwrap_or("");
+        let two_words = match (first, second) {
+            ("greater" | "more" | "later", "than") => Some(Gt),
+            ("less" | "fewer" | "earlier", "than") => Some(Lt),
+            ("at", "least") => Some(Gte),
+            ("at", "most") => Some(Lte),
+            ("equal", "to") => Some(Eq),
+            ("is", "not") => Some(Neq),
+            _ => None,
+        };
+        if let Some(operator) = two_words {
+            return Some((operator, 2));
+        }
+        if first == "is" {
+            if let Some((operator, len)) = self.comparison(i + 1) {
+                return Some((operator, len + 1));
+            }
+        }
+
+        let operator = match first {
+            "is" | "equals" | "equal" | "eq" => Eq,
+            "isn't" | "not" => Neq,
+            "over" | "above" | "after" | "since" => Gt,
+            "under" | "below" | "before" => Lt,
+            _ => return None,
+        };
+        Some((operator, 1))
+    }
+
+    // `completed`, `unfinished`, `incomplete`, `pending`. A `not` before any of them
+    // is a connective, so `not done` is read as `not` + `done`.
+    fn state(&mut self, i: usize) -> Option<usize> {
+        if let Some((column, len)) = self.column_at(i) {
+            if column.column_type != ColumnType::Boolean {
+                return None;
+            }
+            self.items
+                .push(Item::Condition(Filter::eq(&column.name, true)));
+            return Some(i + len);
+        }
+
+        let word = self.word(i)?;
+        if !self.opposite_state(word) {
+            return None;
+        }
+        let flag = self.flag_column()?;
+        self.items
+            .push(Item::Condition(Filter::eq(&flag.name, false)));
+        Some(i + 1)
+    }
+
+    // `pending`, or the flag column's terms with a negative prefix like `unfinished`
+    fn opposite_state(&self, word: &str) -> bool {
+        let Some(flag) = self.flag_column() else {
+            return false;
+        };
+        PENDING.contains(&word)
+            || ["un", "in", "non-"].iter().any(|prefix| {
+                word.strip_prefix(prefix).is_some_and(|rest| {
+                    rest.len() >= 4 && flag_terms(flag).iter().any(|term| term.starts_with(rest))
+                })
+            })
+    }
+
+    // The boolean column that words like "pending" or "incomplete" refer to: the first
+    // one with synonyms
+    fn flag_column(&self) -> Option<&'a ColumnSchema> {
+        self.table
+            .columns
+            .iter()
+            .find(|c| c.column_type == ColumnType::Boolean && !c.synonyms.is_empty())
+    }
+
+    // `containing dog`, `about "big plans"`, `named ann`, or a quoted phrase on its own
+    fn search(&mut self, i: usize) -> Option<usize> {
+        if let Some(Token::Quoted(text)) = self.token(i) {
+            let column = self.search_column()?;
+            self.items
+                .push(Item::Condition(Filter::contains(&column.name, text)));
+            return Some(i + 1);
+        }
+
+        let word = self.word(i)?;
+        if !SEARCH.contains(&word) && word != "with" {
+            return None;
+        }
+        let term = self.search_term(i + 1)?;
+        let column = match word {
+            "named" | "called" => self.table.column("name").or_else(|| self.search_column()),
+            _ => self.search_column(),
+        }?;
+        self.items
+            .push(Item::Condition(Filter::contains(&column.name, &term)));
+        Some(self.alternatives(i + 2, |token| {
+            Some(Filter::contains(&column.name, &token.text()))
+        }))
+    }
+
+    fn search_term(&self, i: usize) -> Option<String> {
+        if self.used.get(i).copied().unwrap_or(true) {
+            return None;
+        }
+        match self.token(i)? {
+            Token::Quoted(text) => Some(text.clone()),
+            Token::Number(number) => Some(number.to_string()),
+            Token::Word(word) if !FILLER.contains(&word.as_str()) => Some(word.clone()),
+            _ => None,
+        }
+    }
+
+    // Text searches go to `title`, then `name`, then the first text column
+    fn search_column(&self) -> Option<&'a ColumnSchema> {
+        self.table
+            .column("title")
+            .or_else(|| self.table.column("name"))
+            .or_else(|| self.table.first_column_of(ColumnType::Text))
+    }
+
+    fn is_search_column(&self, column: &ColumnSchema) -> bool {
+        self.search_column()
+            .is_some_and(|search| search.name == column.name)
+    }
+
+    // A number on its own is an id: `todo 5`, `user #3`
+    fn bare_number(&mut self, i: usize) -> Option<usize> {
+        let Some(Token::Number(id)) = self.token(i) else {
+            return None;
+        };
+        let column = self.table.column("id")?;
+        if column.column_type != ColumnType::Integer {
+            return None;
+        }
+        self.items.push(Item::Condition(Filter::eq("id", *id)));
+        Some(i + 1)
+    }
+}
+
+// A date column saying when something is due, `due_date` or one with a `due` synonym
+fn is_due(column: &ColumnSchema) -> bool {
+    matches!(column.column_type, ColumnType::Timestamp | ColumnType::Date)
+        && (column.name.contains("due")
+            || column
+                .synonyms
+                .iter()
+                .any(|synonym| synonym.contains("due")))
+}
+
+// Digits too many for an `i64`, which the tokenizer leaves as a word
+fn is_too_large(token: &Token) -> bool {
+    token.word().is_some_and(|word| {
+        let digits = word.strip_prefix('-').unwrap_or(word);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+// `rows`, `results` or `items`, counted instead of the table's name
+fn is_row_word(word: &str) -> bool {
+    ["rows", "results", "items", "records", "entries"].contains(&word)
+}
+
+// Words that mean a flag column is set: its synonyms and its name without `is_`
+fn flag_terms(column: &ColumnSchema) -> Vec<String> {
+    let mut terms: Vec<String> = column.synonyms.iter().map(|s| s.to_lowercase()).collect();
+    terms.push(column.name.trim_start_matches("is_").to_string());
+    terms
+}
+
+// The token as a value of the column's type, or `None` if it doesn't fit
+fn value_for(column: &ColumnSchema, token: &Token) -> Option<FilterValue> {
+    match (column.column_type, token) {
+        (ColumnType::Integer, Token::Number(number)) => Some(FilterValue::Int(*number)),
+        (ColumnType::Float, Token::Number(number)) => Some(FilterValue::Int(*number)),
+        (ColumnType::Float, Token::Word(word)) => word.parse().ok().map(FilterValue::Float),
+        (ColumnType::Boolean, Token::Word(word)) => match word.as_str() {
+            "true" | "yes" => Some(FilterValue::Bool(true)),
+            "false" | "no" => Some(FilterValue::Bool(false)),
+            _ => None,
+        },
+        (ColumnType::Timestamp | ColumnType::Date, Token::Word(word) | Token::Quoted(word))
+            if parse_timestamp(word).is_some() || parse_date(word).is_some() =>
+        {
+            Some(FilterValue::Text(word.clone()))
+        }
+        (ColumnType::Text | ColumnType::Uuid, Token::Quoted(text)) => {
+            Some(FilterValue::Text(text.clone()))
+        }
+        (ColumnType::Text | ColumnType::Uuid, Token::Word(word))
+            if !FILLER.contains(&word.as_str()) =>
+        {
+            Some(FilterValue::Text(word.clone()))
+        }
+        (ColumnType::Text, Token::Number(number)) => Some(FilterValue::Text(number.to_string())),
+        _ => None,
+    }
+}
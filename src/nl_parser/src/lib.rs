@@ -0,0 +1,845 @@
+// Rule-based parser turning a question into a PostgREST query for a table of the schema.
+// Both canisters use it when no LLM is available, so questions parse the same way
+// whichever canister ends up answering them. Plain Rust, so it is tested natively.
+
+mod dates;
+mod follow_up;
+mod grammar;
+mod logic;
+mod numbers;
+pub mod tokenizer;
+
+use common::rpc::match_rpc;
+use common::time::parse_time_zone;
+use common::{
+    ApiError, Mutation, MutationAction, ParseContext, PostgrestQuery, QueryParseResult, Schema,
+};
+use dates::Clock;
+use grammar::{find_table, Parser, MUTATION_VERBS, VERBS};
+use tokenizer::tokenize;
+
+// An allowlisted function named in the question wins; otherwise the question has to
+// mention a table, or at least ask for rows ("show everything"), which reads the first one
+pub fn parse(question: &str, context: &ParseContext) -> Result<QueryParseResult, ApiError> {
+    if let Some(function) = match_rpc(&question.to_lowercase(), &context.allowed_rpcs) {
+        return Ok(QueryParseResult::rpc(function, "{}"));
+    }
+
+    let schema = context.schema();
+    let tokens = tokenize(question);
+    let asks_for_rows = tokens.iter().any(|token| {
+        token
+            .word()
+            .is_some_and(|word| VERBS.contains(&word) || MUTATION_VERBS.contains(&word))
+    });
+
+    let (table, mention) = match find_table(&schema, &tokens) {
+        Some((table, start, len)) => (table, Some((start, len))),
+        None if asks_for_rows => match schema.tables.first() {
+            Some(table) => (table, None),
+            None => {
+                return Err(ApiError::parse(
+                    "The schema registry has no tables, call set_schema",
+                ))
+            }
+        },
+        None => {
+            return Err(ApiError::parse(format!(
+                "Unable to parse '{}' as a database query. Please use words like 'show', 'get' or 'find' with a table name like {}",
+                question,
+                quoted_table_names(&schema)
+            )))
+        }
+    };
+
+    let (query, mutation) = Parser::new(&tokens, &schema, table, mention, clock(context))
+        .parse_statement()
+        .map_err(|problem| unreadable(question, &problem))?;
+    statement(question, &table.name, query, mutation)
+}
+
+// Reads `question` as a follow-up to `previous`, the query last asked on `table`:
+// "now only the completed ones", "sorted by title", "delete them". `None` when it is a
+// question of its own, because it names another table, or names the same one without
+// pointing back ("show completed todos"), or adds nothing the parser understands.
+pub fn refine(
+    question: &str,
+    table: &str,
+    previous: &PostgrestQuery,
+    context: &ParseContext,
+) -> Option<Result<QueryParseResult, ApiError>> {
+    let schema = context.schema();
+    let table = schema.table(table)?;
+    let tokens = tokenize(question);
+
+    // "only the completed ones" filters and "only title and status" projects, so a
+    // leading `only` is read as a projection when nothing else is understood
+    let parsed = [false, true].into_iter().find_map(|keep_projection| {
+        let (tokens, marked) = follow_up::strip(&tokens, keep_projection);
+        let mention = match find_table(&schema, &tokens) {
+            Some((other, _, _)) if other.name != table.name => return None,
+            Some(_) if !marked => return None,
+            Some((_, start, len)) => Some((start, len)),
+            None => None,
+        };
+        match Parser::new(&tokens, &schema, table, mention, clock(context)).parse_statement() {
+            Ok((delta, mutation)) => (delta != PostgrestQuery::new() || mutation.is_some())
+                .then_some(Ok((delta, mutation))),
+            Err(problem) => Some(Err(problem)),
+        }
+    })?;
+    let (delta, mutation) = match parsed {
+        Ok(parsed) => parsed,
+        Err(problem) => return Some(Err(unreadable(question, &problem))),
+    };
+    let query = follow_up::merge(previous, delta);
+    Some(statement(question, &table.name, query, mutation))
+}
+
+fn clock(context: &ParseContext) -> Option<Clock> {
+    context.now.map(|now| Clock {
+        now: i64::try_from(now).unwrap_or(i64::MAX),
+        offset: context
+            .time_zone
+            .as_deref()
+            .and_then(parse_time_zone)
+            .unwrap_or(0),
+    })
+}
+
+fn unreadable(question: &str, problem: &str) -> ApiError {
+    ApiError::parse(format!("Unable to parse '{}': {}", question, problem))
+}
+
+fn statement(
+    question: &str,
+    table: &str,
+    query: PostgrestQuery,
+    mutation: Option<Mutation>,
+) -> Result<QueryParseResult, ApiError> {
+    let result = QueryParseResult::success(table, query);
+    match mutation {
+        Some(mutation) if mutation.action == MutationAction::Update && mutation.set.is_empty() => {
+            Err(ApiError::parse(format!(
+                "'{}' doesn't say what to change, e.g. 'set status to done' or 'mark ... as done'",
+                question
+            )))
+        }
+        Some(mutation) => Ok(result.with_mutation(mutation)),
+        None => Ok(result),
+    }
+}
+
+// Table names for error messages, e.g. 'todos', 'users' or 'posts'
+fn quoted_table_names(schema: &Schema) -> String {
+    let names: Vec<String> = schema
+        .table_names()
+        .iter()
+        .map(|name| format!("'{}'", name))
+        .collect();
+    match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        Some((last, _)) => last.clone(),
+        None => "a table".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::time::{days_from_civil, NANOS_PER_SECOND, SECONDS_PER_DAY};
+    use common::{ColumnSchema, ColumnType, ErrorCode, TableSchema};
+
+    fn parse_builtin(question: &str) -> QueryParseResult {
+        parse(question, &ParseContext::default()).unwrap()
+    }
+
+    fn assert_parses(question: &str, table: &str, query: &str) {
+        let result = parse_builtin(question);
+        assert_eq!(
+            (result.table.as_str(), result.query.as_str()),
+            (table, query),
+            "{}",
+            question
+        );
+    }
+
+    #[test]
+    fn reads_tables_by_name_singular_and_synonym() {
+        assert_parses("show all todos", "todos", "select=*");
+        assert_parses("show all projects", "todos", "select=*");
+        assert_parses("list users", "users", "select=*");
+        assert_parses("get the post 3", "posts", "select=*&id=eq.3");
+        assert_parses("show my tasks", "todos", "select=*");
+    }
+
+    #[test]
+    fn rejects_questions_without_a_table_or_verb() {
+        let error = parse("hello there", &ParseContext::default()).unwrap_err();
+        assert_eq!(error.code, ErrorCode::Parse);
+        assert!(error.message.contains("'todos', 'users' or 'posts'"));
+    }
+
+    #[test]
+    fn parses_readme_examples() {
+        assert_parses(
+            "show me todos where due date is not null",
+            "todos",
+            "select=*&due_date=not.is.null",
+        );
+        assert_parses(
+            "find todos that are completed",
+            "todos",
+            "select=*&is_done=eq.true",
+        );
+        assert_parses("show completed todos", "todos", "select=*&is_done=eq.true");
+        assert_parses(
+            "show incomplete tasks",
+            "todos",
+            "select=*&is_done=eq.false",
+        );
+        assert_parses(
+            "show unfinished tasks",
+            "todos",
+            "select=*&is_done=eq.false",
+        );
+        assert_parses(
+            "todos that are not done",
+            "todos",
+            "select=*&is_done=eq.false",
+        );
+        assert_parses("pending todos", "todos", "select=*&is_done=eq.false");
+        assert_parses(
+            "list todos with due dates",
+            "todos",
+            "select=*&due_date=not.is.null",
+        );
+        assert_parses(
+            "find todos without due dates",
+            "todos",
+            "select=*&due_date=is.null",
+        );
+        assert_parses("get todos by id 1", "todos", "select=*&id=eq.1");
+        assert_parses(
+            "show todos with title like dog",
+            "todos",
+            "select=*&title=ilike.*dog*",
+        );
+        assert_parses(
+            "find todos containing work",
+            "todos",
+            "select=*&title=ilike.*work*",
+        );
+        assert_parses(
+            "top 10 latest todos",
+            "todos",
+            "select=*&order=created_at.desc&limit=10",
+        );
+    }
+
+    #[test]
+    fn parses_projection_ordering_and_comparisons() {
+        assert_parses(
+            "show only id and title of todos sorted by title desc",
+            "todos",
+            "select=id,title&order=title.desc",
+        );
+        assert_parses("todos with id > 5", "todos", "select=*&id=gt.5");
+        assert_parses("todos where id is at least 5", "todos", "select=*&id=gte.5");
+        assert_parses(
+            "todos with status active",
+            "todos",
+            "select=*&status=eq.active",
+        );
+        assert_parses(
+            "last 3 users",
+            "users",
+            "select=*&order=created_at.desc&limit=3",
+        );
+        assert_parses(
+            r#"find posts titled "Big Plans""#,
+            "posts",
+            "select=*&title=ilike.*Big Plans*",
+        );
+        assert_parses("users named ann", "users", "select=*&name=ilike.*ann*");
+        assert_parses("posts by user 3", "posts", "select=*&user_id=eq.3");
+    }
+
+    #[test]
+    fn parses_lists_ranges_and_negated_searches() {
+        assert_parses(
+            "todos with id in 1, 2, 3",
+            "todos",
+            "select=*&id=in.(1,2,3)",
+        );
+        assert_parses(
+            "todos with status in (open, blocked)",
+            "todos",
+            "select=*&status=in.(open,blocked)",
+        );
+        assert_parses(
+            "todos where id not in 1, 2 and 3",
+            "todos",
+            "select=*&id=not.in.(1,2,3)",
+        );
+        assert_parses(
+            "todos with id in 1, 2 and status open",
+            "todos",
+            "select=*&id=in.(1,2)&status=eq.open",
+        );
+        assert_parses(
+            "todos with id between 3 and 7",
+            "todos",
+            "select=*&id=gte.3&id=lte.7",
+        );
+        assert_parses(
+            "todos with id between 7 and 3",
+            "todos",
+            "select=*&id=gte.3&id=lte.7",
+        );
+        assert_parses(
+            "todos where id is not between 3 and 7",
+            "todos",
+            "select=*&not.and=(id.gte.3,id.lte.7)",
+        );
+        assert_parses(
+            "todos where title not like work",
+            "todos",
+            "select=*&title=not.ilike.*work*",
+        );
+        assert_parses(
+            "todos whose title does not contain dog or cat",
+            "todos",
+            "select=*&not.or=(title.ilike.*dog*,title.ilike.*cat*)",
+        );
+        assert_parses(
+            "todos where status is not open",
+            "todos",
+            "select=*&status=neq.open",
+        );
+    }
+
+    #[test]
+    fn parses_negation_and_compound_conditions() {
+        assert_parses(
+            "todos that are done or have no due date but not titled work",
+            "todos",
+            "select=*&title=not.ilike.*work*&or=(is_done.eq.true,due_date.is.null)",
+        );
+        assert_parses(
+            "show todos that aren't completed",
+            "todos",
+            "select=*&is_done=eq.false",
+        );
+        assert_parses(
+            "todos except done ones",
+            "todos",
+            "select=*&is_done=eq.false",
+        );
+        assert_parses(
+            "todos with status open or blocked",
+            "todos",
+            "select=*&or=(status.eq.open,status.eq.blocked)",
+        );
+        assert_parses(
+            "tasks titled milk or eggs and not completed",
+            "todos",
+            "select=*&is_done=eq.false&or=(title.ilike.*milk*,title.ilike.*eggs*)",
+        );
+        assert_parses(
+            "todos that are not (done or titled work)",
+            "todos",
+            "select=*&not.or=(is_done.eq.true,title.ilike.*work*)",
+        );
+        assert_parses(
+            "todos with status open or status blocked and no due date",
+            "todos",
+            "select=*&or=(status.eq.open,and(status.eq.blocked,due_date.is.null))",
+        );
+    }
+
+    #[test]
+    fn parses_limits_pages_and_ordering() {
+        assert_parses("top twenty todos", "todos", "select=*&limit=20");
+        assert_parses("show 5 todos", "todos", "select=*&limit=5");
+        assert_parses("todos page 3", "todos", "select=*&limit=10&offset=20");
+        assert_parses(
+            "page three of todos with 25 per page",
+            "todos",
+            "select=*&limit=25&offset=50",
+        );
+        assert_parses(
+            "show the second page of users",
+            "users",
+            "select=*&limit=10&offset=10",
+        );
+        assert_parses(
+            "skip 10 todos and show the next 15",
+            "todos",
+            "select=*&limit=15&offset=10",
+        );
+        assert_parses(
+            "todos sorted by due date descending",
+            "todos",
+            "select=*&order=due_date.desc",
+        );
+        assert_parses(
+            "todos alphabetically by title",
+            "todos",
+            "select=*&order=title.asc",
+        );
+        assert_parses(
+            "users in reverse alphabetical order",
+            "users",
+            "select=*&order=name.desc",
+        );
+        assert_parses(
+            "todos ordered by status, then by due date in descending order",
+            "todos",
+            "select=*&order=status.asc,due_date.desc",
+        );
+        assert_parses(
+            "todos sorted by title and status active",
+            "todos",
+            "select=*&status=eq.active&order=title.asc",
+        );
+        assert_parses(
+            "posts sorted by id highest first",
+            "posts",
+            "select=*&order=id.desc",
+        );
+        // `last` takes the newest ones
+        assert_parses(
+            "last two todos",
+            "todos",
+            "select=*&order=created_at.desc&limit=2",
+        );
+    }
+
+    #[test]
+    fn reads_ordering_before_the_table_and_rejects_pages_that_are_not_positive() {
+        assert_parses("sort todos by title", "todos", "select=*&order=title.asc");
+        assert_parses(
+            "order the todos by due date desc",
+            "todos",
+            "select=*&order=due_date.desc",
+        );
+        // `by` without an aggregate sorts, with one it groups
+        assert_parses("todos by title", "todos", "select=*&order=title.asc");
+        assert_parses("todos by title desc", "todos", "select=*&order=title.desc");
+        assert_parses("count todos by status", "todos", "select=status,count()");
+        // Rows counted from 1, both ends included
+        assert_parses("show todos 10 to 20", "todos", "select=*&limit=11&offset=9");
+        assert_parses(
+            "rows 1 through 5 of todos",
+            "todos",
+            "select=*&limit=5&offset=0",
+        );
+
+        for (question, problem) in [
+            ("todos page 0", "'page 0' must be a positive number"),
+            ("todos page -1", "'page -1' must be a positive number"),
+            ("next 0 todos", "'next 0' must be a positive number"),
+            ("todos limit 0", "'limit 0' must be a positive number"),
+            ("show 0 todos", "'0' must be a positive number"),
+            ("0 per page todos", "'0 per page' must be a positive number"),
+            ("skip -5 todos", "'skip -5' can't be negative"),
+            ("todos 20 to 10", "'20 to 10' is not a range of rows"),
+        ] {
+            let error = parse(question, &ParseContext::default()).unwrap_err();
+            assert_eq!(error.code, ErrorCode::Parse, "{}", question);
+            assert!(error.message.ends_with(problem), "{}", error.message);
+        }
+    }
+
+    #[test]
+    fn rejects_numbers_too_large_and_conditions_that_cannot_be_read() {
+        for (question, problem) in [
+            (
+                "show todos with id greater than 9223372036854775808",
+                "'9223372036854775808' is too large a number",
+            ),
+            (
+                "top 99999999999999999999 todos",
+                "'99999999999999999999' is too large a number",
+            ),
+            (
+                "todos page 99999999999999999999",
+                "'99999999999999999999' is too large a number",
+            ),
+            (
+                "todos with id greater than abc",
+                "'id greater than abc' is not a condition that can be read",
+            ),
+            (
+                "todos with title like",
+                "'title like' is not a condition that can be read",
+            ),
+        ] {
+            let error = parse(question, &ParseContext::default()).unwrap_err();
+            assert_eq!(error.code, ErrorCode::Parse, "{}", question);
+            assert!(error.message.ends_with(problem), "{}", error.message);
+        }
+        // A column on its own still asks for rows where it is set
+        assert_parses(
+            "todos with due date",
+            "todos",
+            "select=*&due_date=not.is.null",
+        );
+    }
+
+    #[test]
+    fn parses_projections_and_aggregates() {
+        assert_parses(
+            "show title and status of todos",
+            "todos",
+            "select=title,status",
+        );
+        assert_parses(
+            "list names and emails of users",
+            "users",
+            "select=name,email",
+        );
+        assert_parses(
+            "how many todos are done",
+            "todos",
+            "select=count()&is_done=eq.true",
+        );
+        assert_parses("count todos per status", "todos", "select=status,count()");
+        assert_parses(
+            "how many posts for each user",
+            "posts",
+            "select=user_id,count()",
+        );
+        assert_parses(
+            "todos grouped by status with counts",
+            "todos",
+            "select=status,count()",
+        );
+        assert_parses("max id of users", "users", "select=id.max()");
+        // Sorting that aggregated rows can't have is left out
+        assert_parses("how many latest todos", "todos", "select=count()");
+        // A value after the column makes `by` a condition
+        assert_parses(
+            "count posts by user 3",
+            "posts",
+            "select=count()&user_id=eq.3",
+        );
+    }
+
+    #[test]
+    fn resolves_dates_against_the_clock_and_time_zone() {
+        // Saturday 2026-10-17, 12:00 in UTC+2
+        let now = (days_from_civil(2026, 10, 17) * SECONDS_PER_DAY + 10 * 3600) * NANOS_PER_SECOND;
+        let context = ParseContext {
+            now: Some(now as u64),
+            time_zone: Some("UTC+2".to_string()),
+            ..ParseContext::default()
+        };
+        let query = |question| parse(question, &context).unwrap().query;
+
+        assert_eq!(
+            query("todos due this week"),
+            "select=*&due_date=gte.2026-10-11T22:00:00Z&due_date=lt.2026-10-18T22:00:00Z"
+        );
+        assert_eq!(
+            query("overdue todos"),
+            "select=*&due_date=lt.2026-10-17T10:00:00Z&is_done=eq.false"
+        );
+        assert_eq!(
+            query("todos due before March 3"),
+            "select=*&due_date=lt.2026-03-02T22:00:00Z"
+        );
+        assert_eq!(
+            query("todos created yesterday"),
+            "select=*&created_at=gte.2026-10-15T22:00:00Z&created_at=lt.2026-10-16T22:00:00Z"
+        );
+        assert_eq!(
+            query("posts since monday"),
+            "select=*&created_at=gte.2026-10-11T22:00:00Z"
+        );
+        assert_eq!(
+            query("todos due today or tomorrow"),
+            concat!(
+                "select=*&or=(and(due_date.gte.\"2026-10-16T22:00:00Z\",due_date.lt.\"2026-10-17T22:00:00Z\"),",
+                "and(due_date.gte.\"2026-10-17T22:00:00Z\",due_date.lt.\"2026-10-18T22:00:00Z\"))"
+            )
+        );
+        // A number alone is still an id, not a day
+        assert_eq!(query("get the post 3"), "select=*&id=eq.3");
+    }
+
+    #[test]
+    fn reads_date_ranges_and_rejects_dates_that_do_not_exist() {
+        // Saturday 2026-10-17, 12:00 in UTC+2
+        let now = (days_from_civil(2026, 10, 17) * SECONDS_PER_DAY + 10 * 3600) * NANOS_PER_SECOND;
+        let context = ParseContext {
+            now: Some(now as u64),
+            time_zone: Some("UTC+2".to_string()),
+            ..ParseContext::default()
+        };
+        let query = |question| parse(question, &context).unwrap().query;
+
+        assert_eq!(
+            query("todos from 1 jan to 3 feb"),
+            "select=*&created_at=gte.2025-12-31T22:00:00Z&created_at=lt.2026-02-03T22:00:00Z"
+        );
+        assert_eq!(
+            query("todos due between march 3 and march 1"),
+            "select=*&due_date=gte.2026-02-28T22:00:00Z&due_date=lt.2026-03-03T22:00:00Z"
+        );
+        assert_eq!(
+            query("todos created between 2026-01-01 and 2026-02-01"),
+            "select=*&created_at=gte.2025-12-31T22:00:00Z&created_at=lt.2026-02-01T22:00:00Z"
+        );
+        assert_eq!(
+            query("todos due within 3 weeks"),
+            "select=*&due_date=gte.2026-10-17T10:00:00Z&due_date=lt.2026-11-07T10:00:00Z"
+        );
+        assert_eq!(
+            query("todos due within a month"),
+            "select=*&due_date=gte.2026-10-17T10:00:00Z&due_date=lt.2026-11-17T10:00:00Z"
+        );
+
+        for (question, problem) in [
+            (
+                "todos due before February 30",
+                "'february 30' is not a date",
+            ),
+            ("todos due on feb 29", "'feb 29' is not a date"),
+            ("todos due before march 32", "'march 32' is not a date"),
+            ("todos created on 0000-01-01", "'0000-01-01' is not a date"),
+            ("todos due before 9999-12-31", "'9999-12-31' is not a date"),
+            (
+                "todos due 31 december 9999",
+                "'31 december 9999' is outside the dates that can be queried",
+            ),
+            (
+                "todos created in the last 99999999999 days",
+                "'the last 99999999999 days' is outside the dates that can be queried",
+            ),
+            (
+                "todos due in 99999999999999999 days",
+                "'99999999999999999 days' is outside the dates that can be queried",
+            ),
+            (
+                "todos created 99999999999 weeks ago",
+                "'99999999999 weeks ago' is outside the dates that can be queried",
+            ),
+            (
+                "todos due within 99999999999999999 years",
+                "'99999999999999999 years' is outside the dates that can be queried",
+            ),
+        ] {
+            let error = parse(question, &context).unwrap_err();
+            assert_eq!(error.code, ErrorCode::Parse, "{}", question);
+            assert!(error.message.ends_with(problem), "{}", error.message);
+        }
+    }
+
+    #[test]
+    fn uses_columns_from_the_context_schema() {
+        let schema = Schema {
+            tables: vec![TableSchema::new(
+                "projects",
+                vec![
+                    ColumnSchema::new("id", ColumnType::Integer),
+                    ColumnSchema::new("name", ColumnType::Text),
+                    ColumnSchema::new("budget", ColumnType::Float),
+                    ColumnSchema::new("archived", ColumnType::Boolean).synonyms(["closed"]),
+                ],
+            )],
+        };
+        let context = ParseContext {
+            schema: Some(schema),
+            ..ParseContext::default()
+        };
+
+        let result = parse("open projects with budget over 1000", &context).unwrap();
+        assert_eq!(result.query, "select=*&archived=eq.false&budget=gt.1000");
+        let result = parse("closed projects", &context).unwrap();
+        assert_eq!(result.query, "select=*&archived=eq.true");
+        // A column another table has means nothing here
+        let result = parse("projects without due date", &context).unwrap();
+        assert_eq!(result.query, "select=*");
+    }
+
+    #[test]
+    fn embeds_related_tables() {
+        assert_parses(
+            "posts by Alice with author email",
+            "posts",
+            "select=*,users!inner(email)&users.name=ilike.*alice*",
+        );
+        assert_parses(
+            "posts by \"Ann Lee\" newest first",
+            "posts",
+            "select=*,users!inner(*)&users.name=ilike.*Ann Lee*&order=created_at.desc",
+        );
+        assert_parses(
+            "posts with author name and email",
+            "posts",
+            "select=*,users(name,email)",
+        );
+        assert_parses("users with their posts", "users", "select=*,posts(*)");
+        // A number after the reference is still a condition on it
+        assert_parses("posts by user 3", "posts", "select=*&user_id=eq.3");
+
+        assert_parses(
+            "users with their open todos",
+            "users",
+            "select=*,todos(*)&todos.is_done=eq.false",
+        );
+        assert_parses(
+            "todos by alice",
+            "todos",
+            "select=*,users!inner(*)&users.name=ilike.*alice*",
+        );
+
+        // Without a reference between the tables the embed can't be made
+        let mut schema = Schema::builtin();
+        schema.tables[0]
+            .columns
+            .retain(|column| column.references.is_none());
+        let context = ParseContext {
+            schema: Some(schema),
+            ..ParseContext::default()
+        };
+        let error = parse("users with their open todos", &context).unwrap_err();
+        assert_eq!(error.code, ErrorCode::Parse);
+        assert!(error
+            .message
+            .ends_with("'todos' is not related to users, so its rows can't be included"));
+    }
+
+    #[test]
+    fn parses_updates_and_deletes() {
+        let mutation = |question| {
+            let result = parse_builtin(question);
+            (result.query, result.mutation.unwrap().to_string())
+        };
+
+        assert_eq!(
+            mutation("mark 'Walk the dog' as done"),
+            (
+                "select=*&title=ilike.*Walk the dog*".to_string(),
+                "update set is_done=true".to_string()
+            )
+        );
+        assert_eq!(
+            mutation("mark todo 5 as not done"),
+            (
+                "select=*&id=eq.5".to_string(),
+                "update set is_done=false".to_string()
+            )
+        );
+        assert_eq!(
+            mutation("mark todo 2 as blocked").1,
+            "update set status=blocked"
+        );
+        assert_eq!(
+            mutation("update todo 3 set is done = true and status = closed"),
+            (
+                "select=*&id=eq.3".to_string(),
+                "update set is_done=true, status=closed".to_string()
+            )
+        );
+        assert_eq!(
+            mutation("change the title of todo 5 to \"Buy milk\"").1,
+            "update set title=Buy milk"
+        );
+        assert_eq!(
+            mutation("delete completed todos"),
+            ("select=*&is_done=eq.true".to_string(), "delete".to_string())
+        );
+        assert_eq!(parse_builtin("show completed todos").mutation, None);
+
+        let error = parse("update todo 3", &ParseContext::default()).unwrap_err();
+        assert!(error.message.contains("doesn't say what to change"));
+    }
+
+    #[test]
+    fn rejects_changes_with_words_that_narrow_them_left_unread() {
+        for (question, problem) in [
+            (
+                "delete todos other than 5",
+                "'other' can't be read, so the rows to change aren't certain",
+            ),
+            (
+                "delete todos with id greater than 99999999999999999999",
+                "'99999999999999999999' is too large a number",
+            ),
+            (
+                "delete todos created after 99999",
+                "'created' can't be read, so the rows to change aren't certain",
+            ),
+            (
+                "delete todos with title \"\"",
+                "an empty quoted value matches every row",
+            ),
+        ] {
+            let error = parse(question, &ParseContext::default()).unwrap_err();
+            assert_eq!(error.code, ErrorCode::Parse, "{}", question);
+            assert!(error.message.ends_with(problem), "{}", error.message);
+        }
+        // Filler words don't stand in the way
+        assert_eq!(
+            parse_builtin("please delete all the completed todos").query,
+            "select=*&is_done=eq.true"
+        );
+    }
+
+    #[test]
+    fn prefers_allowlisted_functions() {
+        let context = ParseContext {
+            allowed_rpcs: vec!["get_overdue_todos".to_string()],
+            ..ParseContext::default()
+        };
+        let result = parse("get overdue todos", &context).unwrap();
+        assert_eq!(result.rpc.unwrap().function, "get_overdue_todos");
+    }
+
+    #[test]
+    fn refines_follow_ups() {
+        let context = ParseContext::default();
+        let previous: PostgrestQuery = "select=*&status=eq.open&limit=10".parse().unwrap();
+        let refine_builtin = |question| {
+            refine(question, "todos", &previous, &context).map(|result| result.unwrap().query)
+        };
+
+        assert_eq!(
+            refine_builtin("now only the completed ones").as_deref(),
+            Some("select=*&status=eq.open&is_done=eq.true&limit=10")
+        );
+        assert_eq!(
+            refine_builtin("sorted by title").as_deref(),
+            Some("select=*&status=eq.open&order=title.asc&limit=10")
+        );
+        assert_eq!(
+            refine_builtin("just the first 5").as_deref(),
+            Some("select=*&status=eq.open&limit=5")
+        );
+        assert_eq!(
+            refine_builtin("only title and status").as_deref(),
+            Some("select=title,status&status=eq.open&limit=10")
+        );
+        assert_eq!(
+            refine_builtin("how many are there").as_deref(),
+            Some("select=count()&status=eq.open")
+        );
+        assert_eq!(
+            refine_builtin("what about the ones with status blocked").as_deref(),
+            Some("select=*&status=eq.blocked&limit=10")
+        );
+
+        // Questions of their own start over
+        assert_eq!(refine_builtin("what about users"), None);
+        assert_eq!(refine_builtin("show completed todos"), None);
+        assert_eq!(refine_builtin("hello there"), None);
+
+        let result = refine("delete them", "todos", &previous, &context)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.mutation, Some(Mutation::delete()));
+    }
+}
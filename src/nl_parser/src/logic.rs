@@ -0,0 +1,223 @@
+// Combines the conditions the grammar found with the connectives between them. `and`
+// binds tighter than `or`, brackets group, `not` negates what follows, and `but` or
+// `except` start a condition that is ANDed with everything before it, so "done or
+// without due date but not titled work" reads as `(done or no due date) and not work`.
+
+use common::{Condition, Filter, FilterGroup, FilterOperator, FilterValue, LogicOperator};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Condition(Filter),
+    And,
+    Or,
+    Not,
+    But,
+    Open,
+    Close,
+}
+
+// The conditions that are ANDed at the top level: plain filters become query
+// parameters, groups become `or=(...)`/`and=(...)` parameters
+pub fn combine(items: Vec<Item>) -> Vec<Condition> {
+    let mut combiner = Combiner { items, pos: 0 };
+    let mut conjuncts = Vec::new();
+
+    while combiner.pos < combiner.items.len() {
+        if let Some(condition) = combiner.expression() {
+            match condition {
+                Condition::Group(group)
+                    if group.operator == LogicOperator::And && !group.negated =>
+                {
+                    conjuncts.extend(group.conditions)
+                }
+                other => conjuncts.push(other),
+            }
+        }
+        // Skip what stopped the expression: `but` or an unmatched bracket
+        if combiner.pos < combiner.items.len() {
+            combiner.pos += 1;
+        }
+    }
+
+    conjuncts
+}
+
+struct Combiner {
+    items: Vec<Item>,
+    pos: usize,
+}
+
+impl Combiner {
+    fn peek(&self) -> Option<&Item> {
+        self.items.get(self.pos)
+    }
+
+    fn starts_factor(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Item::Condition(_) | Item::Not | Item::Open)
+        )
+    }
+
+    // term (`or` term)*
+    fn expression(&mut self) -> Option<Condition> {
+        let mut alternatives = Vec::new();
+        loop {
+            alternatives.extend(self.term());
+            if self.peek() != Some(&Item::Or) {
+                break;
+            }
+            self.pos += 1;
+        }
+        group(LogicOperator::Or, alternatives)
+    }
+
+    // factor ([`and`] factor)*, adjacent conditions are ANDed
+    fn term(&mut self) -> Option<Condition> {
+        let mut factors = Vec::new();
+        loop {
+            if self.peek() == Some(&Item::And) {
+                self.pos += 1;
+            } else if self.starts_factor() {
+                factors.extend(self.factor());
+            } else {
+                break;
+            }
+        }
+        group(LogicOperator::And, factors)
+    }
+
+    // `not` factor | `(` expression `)` | condition
+    fn factor(&mut self) -> Option<Condition> {
+        let item = self.items.get(self.pos)?.clone();
+        self.pos += 1;
+        match item {
+            Item::Condition(filter) => Some(Condition::Filter(filter)),
+            Item::Not if self.starts_factor() => self.factor().map(negate),
+            Item::Open => {
+                let inner = self.expression();
+                if self.peek() == Some(&Item::Close) {
+                    self.pos += 1;
+                }
+                inner
+            }
+            _ => None,
+        }
+    }
+}
+
+// Groups two or more conditions, merging nested groups of the same kind
+fn group(operator: LogicOperator, mut conditions: Vec<Condition>) -> Option<Condition> {
+    if conditions.len() < 2 {
+        return conditions.pop();
+    }
+    let conditions = conditions
+        .into_iter()
+        .flat_map(|condition| match condition {
+            Condition::Group(group) if group.operator == operator && !group.negated => {
+                group.conditions
+            }
+            other => vec![other],
+        })
+        .collect();
+    Some(Condition::Group(FilterGroup {
+        operator,
+        negated: false,
+        conditions,
+    }))
+}
+
+// `not done` reads better as `is_done=eq.false` than `is_done=not.eq.true`
+fn negate(condition: Condition) -> Condition {
+    match condition {
+        Condition::Filter(Filter {
+            operator: FilterOperator::Eq,
+            negated: false,
+            value: FilterValue::Bool(value),
+            column,
+        }) => Condition::Filter(Filter::eq(column, !value)),
+        Condition::Filter(filter) => Condition::Filter(filter.negate()),
+        Condition::Group(group) => Condition::Group(group.negate()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(column: &str) -> Item {
+        Item::Condition(Filter::eq(column, 1))
+    }
+
+    fn rendered(items: Vec<Item>) -> Vec<String> {
+        combine(items)
+            .into_iter()
+            .map(|condition| match condition {
+                Condition::Filter(filter) => filter.to_string(),
+                Condition::Group(group) => group.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let items = vec![
+            condition("a"),
+            Item::Or,
+            condition("b"),
+            Item::And,
+            condition("c"),
+        ];
+        assert_eq!(rendered(items), ["or=(a.eq.1,and(b.eq.1,c.eq.1))"]);
+    }
+
+    #[test]
+    fn brackets_and_but_group_conditions() {
+        let items = vec![
+            Item::Open,
+            condition("a"),
+            Item::Or,
+            condition("b"),
+            Item::Close,
+            condition("c"),
+            Item::But,
+            Item::Not,
+            condition("d"),
+        ];
+        assert_eq!(
+            rendered(items),
+            ["or=(a.eq.1,b.eq.1)", "c=eq.1", "d=not.eq.1"]
+        );
+    }
+
+    #[test]
+    fn not_negates_groups_and_flips_booleans() {
+        let items = vec![
+            Item::Not,
+            Item::Open,
+            condition("a"),
+            Item::Or,
+            condition("b"),
+            Item::Close,
+            Item::Not,
+            Item::Condition(Filter::eq("is_done", true)),
+        ];
+        assert_eq!(
+            rendered(items),
+            ["not.or=(a.eq.1,b.eq.1)", "is_done=eq.false"]
+        );
+    }
+
+    #[test]
+    fn dangling_connectives_are_ignored() {
+        let items = vec![
+            Item::Or,
+            condition("a"),
+            Item::Or,
+            Item::Close,
+            Item::And,
+            Item::Not,
+        ];
+        assert_eq!(rendered(items), ["a=eq.1"]);
+    }
+}
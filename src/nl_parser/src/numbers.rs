@@ -0,0 +1,115 @@
+// Numbers written as digits or words: `15`, `fifteen`, `twenty five`, `twenty-five`,
+// `two hundred and fifty`, `a dozen`. Only counts use them (limits, pages, `last
+// three days`); ids and compared values stay digits, so `the one titled work` is no id.
+
+use crate::tokenizer::Token;
+
+const UNITS: &[&str] = &[
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const TENS: &[&str] = &[
+    "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+// The number at `i` and how many tokens it takes
+pub fn number_at(tokens: &[Token], i: usize) -> Option<(i64, usize)> {
+    match tokens.get(i)? {
+        Token::Number(number) => Some((*number, 1)),
+        Token::Word(word) if word == "dozen" => Some((12, 1)),
+        Token::Word(word) if word == "a" => match tokens.get(i + 1).and_then(Token::word) {
+            Some("dozen") => Some((12, 2)),
+            Some("hundred") => below_hundred(tokens, i + 2)
+                .map_or(Some((100, 2)), |(rest, len)| Some((100 + rest, 2 + len))),
+            _ => None,
+        },
+        Token::Word(_) => {
+            let (number, len) = below_hundred(tokens, i)?;
+            if tokens.get(i + len).and_then(Token::word) != Some("hundred") {
+                return Some((number, len));
+            }
+            let hundreds = number * 100;
+            let mut j = i + len + 1;
+            if tokens.get(j).and_then(Token::word) == Some("and") {
+                j += 1;
+            }
+            match below_hundred(tokens, j) {
+                Some((rest, rest_len)) => Some((hundreds + rest, j + rest_len - i)),
+                None => Some((hundreds, len + 1)),
+            }
+        }
+        _ => None,
+    }
+}
+
+// `seven`, `forty`, `forty two`, `forty-two`
+fn below_hundred(tokens: &[Token], i: usize) -> Option<(i64, usize)> {
+    let word = tokens.get(i)?.word()?;
+    if let Some(unit) = unit(word) {
+        return Some((unit, 1));
+    }
+    if let Some((tens, unit)) = word.split_once('-') {
+        let unit = self::unit(unit).filter(|unit| (1..10).contains(unit))?;
+        return Some((ten(tens)? + unit, 1));
+    }
+    let tens = ten(word)?;
+    match tokens.get(i + 1).and_then(Token::word).and_then(unit) {
+        Some(unit) if (1..10).contains(&unit) => Some((tens + unit, 2)),
+        _ => Some((tens, 1)),
+    }
+}
+
+fn unit(word: &str) -> Option<i64> {
+    UNITS
+        .iter()
+        .position(|unit| *unit == word)
+        .map(|n| n as i64)
+}
+
+fn ten(word: &str) -> Option<i64> {
+    TENS.iter()
+        .position(|ten| *ten == word)
+        .map(|n| (n as i64 + 2) * 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    fn number(text: &str) -> Option<(i64, usize)> {
+        number_at(&tokenize(text), 0)
+    }
+
+    #[test]
+    fn reads_digits_and_number_words() {
+        assert_eq!(number("15 todos"), Some((15, 1)));
+        assert_eq!(number("twenty todos"), Some((20, 1)));
+        assert_eq!(number("twenty five todos"), Some((25, 2)));
+        assert_eq!(number("twenty-five todos"), Some((25, 1)));
+        assert_eq!(number("a dozen todos"), Some((12, 2)));
+        assert_eq!(number("two hundred and fifty"), Some((250, 4)));
+        assert_eq!(number("a hundred todos"), Some((100, 2)));
+        assert_eq!(number("three hundred todos"), Some((300, 2)));
+        assert_eq!(number("todos"), None);
+        assert_eq!(number("a todo"), None);
+    }
+}
@@ -0,0 +1,246 @@
+// Splits a question into words, numbers, quoted phrases and comparison symbols.
+// Words are lowercased; quoted phrases keep their case and spacing.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(String),
+    Number(i64),
+    // Text between quotes, e.g. the search term in `todos titled "Buy milk"` or the
+    // title in `mark 'Walk the dog' as done`
+    Quoted(String),
+    // `=`, `!=`, `<`, `<=`, `>` or `>=`
+    Symbol(String),
+    // Commas separate items of a list like `id, title and status`
+    Comma,
+    // Brackets group conditions: `(done or pending) and titled work`
+    Open,
+    Close,
+}
+
+impl Token {
+    pub fn word(&self) -> Option<&str> {
+        match self {
+            Token::Word(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    pub fn is_word(&self, expected: &str) -> bool {
+        self.word() == Some(expected)
+    }
+
+    // The token as a value in a filter, e.g. the `dog` of `title contains dog`
+    pub fn text(&self) -> String {
+        match self {
+            Token::Word(text) | Token::Quoted(text) | Token::Symbol(text) => text.clone(),
+            Token::Number(number) => number.to_string(),
+            Token::Comma => ",".to_string(),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+        }
+    }
+}
+
+fn is_quote(c: char) -> bool {
+    matches!(c, '"' | '\u{201C}' | '\u{201D}')
+}
+
+// Where a phrase in single quotes that starts at `i` ends. Only a quote at the start of
+// a word opens one and only a quote at the end of a word closes it, so apostrophes like
+// `don't` or `Bob's` stay inside words.
+fn single_quoted_end(chars: &[char], i: usize) -> Option<usize> {
+    let is_single = |c: char| matches!(c, '\'' | '\u{2018}' | '\u{2019}');
+    if !is_single(chars[i]) || (i > 0 && chars[i - 1].is_alphanumeric()) {
+        return None;
+    }
+    (i + 2..chars.len()).find(|&end| {
+        is_single(chars[end])
+            && !chars[end - 1].is_whitespace()
+            && !chars
+                .get(end + 1)
+                .is_some_and(|next| next.is_alphanumeric())
+    })
+}
+
+// Characters that may appear inside a word, so `user_id`, `don't`, `2024-01-15`,
+// `a@b.com` and `12:30` stay in one piece
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '\'' | '-' | '@')
+}
+
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if is_quote(c) {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|&c| is_quote(c))
+                .map_or(chars.len(), |offset| i + 1 + offset);
+            tokens.push(Token::Quoted(chars[i + 1..end].iter().collect()));
+            i = end + 1;
+        } else if let Some(end) = single_quoted_end(&chars, i) {
+            tokens.push(Token::Quoted(chars[i + 1..end].iter().collect()));
+            i = end + 1;
+        } else if matches!(c, '=' | '<' | '>' | '!') {
+            let mut symbol = c.to_string();
+            if chars.get(i + 1) == Some(&'=') {
+                symbol.push('=');
+                i += 1;
+            }
+            // A lone `!` is punctuation, not a comparison
+            if symbol != "!" {
+                tokens.push(Token::Symbol(symbol));
+            }
+            i += 1;
+        } else if c == ',' {
+            tokens.push(Token::Comma);
+            i += 1;
+        } else if matches!(c, '(' | '[') {
+            tokens.push(Token::Open);
+            i += 1;
+        } else if matches!(c, ')' | ']') {
+            tokens.push(Token::Close);
+            i += 1;
+        } else if is_word_char(c) {
+            let start = i;
+            while i < chars.len() {
+                let c = chars[i];
+                // `.` and `:` only join characters inside a word, like `b.com`, `1.5` or `12:30`
+                let joins = matches!(c, '.' | ':')
+                    && i > start
+                    && chars.get(i + 1).is_some_and(|next| next.is_alphanumeric());
+                if !is_word_char(c) && !joins {
+                    break;
+                }
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect::<String>().to_lowercase();
+            let word = word.trim_matches('\'').trim_end_matches('-').to_string();
+            if word.is_empty() {
+                continue;
+            }
+            match word.parse::<i64>() {
+                Ok(number) => tokens.push(Token::Number(number)),
+                Err(_) => tokens.push(Token::Word(word)),
+            }
+        } else {
+            // Other punctuation (`?`, `.`, `#`) only separates words
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(tokens: &[&str]) -> Vec<Token> {
+        tokens.iter().map(|w| Token::Word(w.to_string())).collect()
+    }
+
+    #[test]
+    fn splits_and_lowercases_words() {
+        assert_eq!(
+            tokenize("Show ALL todos, please."),
+            vec![
+                Token::Word("show".into()),
+                Token::Word("all".into()),
+                Token::Word("todos".into()),
+                Token::Comma,
+                Token::Word("please".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_identifiers_dates_and_emails_whole() {
+        assert_eq!(
+            tokenize("user_id 2024-01-15 ann@example.com don't"),
+            words(&["user_id", "2024-01-15", "ann@example.com", "don't"])
+        );
+    }
+
+    #[test]
+    fn reads_numbers() {
+        assert_eq!(
+            tokenize("todo #42?"),
+            vec![Token::Word("todo".into()), Token::Number(42)]
+        );
+        assert_eq!(tokenize("-3"), vec![Token::Number(-3)]);
+    }
+
+    #[test]
+    fn keeps_quoted_phrases_verbatim() {
+        assert_eq!(
+            tokenize(r#"titled "Buy Milk & eggs" now"#),
+            vec![
+                Token::Word("titled".into()),
+                Token::Quoted("Buy Milk & eggs".into()),
+                Token::Word("now".into()),
+            ]
+        );
+        assert_eq!(
+            tokenize("called \u{201C}Big plans\u{201D}"),
+            vec![
+                Token::Word("called".into()),
+                Token::Quoted("Big plans".into())
+            ]
+        );
+        assert_eq!(
+            tokenize("mark 'Walk Bob's dog' as done"),
+            vec![
+                Token::Word("mark".into()),
+                Token::Quoted("Walk Bob's dog".into()),
+                Token::Word("as".into()),
+                Token::Word("done".into()),
+            ]
+        );
+        assert_eq!(tokenize("users' todos"), words(&["users", "todos"]));
+        assert_eq!(
+            tokenize(r#"about "unterminated"#),
+            vec![
+                Token::Word("about".into()),
+                Token::Quoted("unterminated".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_brackets() {
+        assert_eq!(
+            tokenize("(done or [open])"),
+            vec![
+                Token::Open,
+                Token::Word("done".into()),
+                Token::Word("or".into()),
+                Token::Open,
+                Token::Word("open".into()),
+                Token::Close,
+                Token::Close,
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_comparison_symbols() {
+        assert_eq!(
+            tokenize("id>=5 and id != 7!"),
+            vec![
+                Token::Word("id".into()),
+                Token::Symbol(">=".into()),
+                Token::Number(5),
+                Token::Word("and".into()),
+                Token::Word("id".into()),
+                Token::Symbol("!=".into()),
+                Token::Number(7),
+            ]
+        );
+    }
+}
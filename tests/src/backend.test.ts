@@ -1,16 +1,18 @@
 import { describe, it, expect, beforeAll, afterAll, inject } from "vitest";
-import { PocketIc } from "@dfinity/pic";
+import { PocketIc, Actor, createIdentity } from "@dfinity/pic";
+import { IDL } from "@dfinity/candid";
 import { _SERVICE } from "../../src/declarations/backend/backend.did.d.ts";
 import { Principal } from "@dfinity/principal";
-import { ActorSubclass } from "@dfinity/agent";
 import { readFileSync } from "fs";
 import { resolve, dirname } from "path";
 import { fileURLToPath } from "url";
 
 describe("Backend Service", () => {
   let pic: PocketIc;
-  let actor: ActorSubclass<_SERVICE>;
+  let actor: Actor<_SERVICE>;
   let canisterId: Principal;
+  let wasmModule: Buffer;
+  let emptyInitArg: ArrayBuffer;
 
   beforeAll(async () => {
     // Initialize PocketIC with default configuration - let it manage the server
@@ -27,7 +29,6 @@ describe("Backend Service", () => {
       "backend.wasm",
     );
 
-    let wasmModule: Buffer;
     try {
       wasmModule = readFileSync(wasmPath);
     } catch (error) {
@@ -37,14 +38,16 @@ describe("Backend Service", () => {
     }
 
     // Load the IDL factory
-    const { idlFactory } = await import(
+    const { idlFactory, init } = await import(
       "../../src/declarations/backend/backend.did.js"
     );
 
-    // Create and install the backend canister
+    // Create and install the backend canister without init arguments
+    emptyInitArg = IDL.encode(init({ IDL }), [[]]);
     const fixture = await pic.setupCanister<_SERVICE>({
       idlFactory,
       wasm: wasmModule,
+      arg: emptyInitArg,
     });
 
     actor = fixture.actor;
@@ -88,13 +91,803 @@ describe("Backend Service", () => {
     });
   });
 
+  describe("config", () => {
+    it("should let a controller set credentials and show the key masked", async () => {
+      // Setup
+      const anonKey = "eyJhbGciOiJIUzI1NiJ9.example-anon-key.x9Qk";
+
+      // Execute
+      const urlResult = await actor.set_supabase_url(
+        "https://example.supabase.co/",
+      );
+      const keyResult = await actor.set_supabase_anon_key(anonKey);
+      const config = await actor.get_config();
+
+      // Assert
+      expect(urlResult).toEqual({ Ok: null });
+      expect(keyResult).toEqual({ Ok: null });
+      expect(config).toEqual({
+        Ok: {
+          supabase_url: ["https://example.supabase.co"],
+          supabase_anon_key: ["eyJh…x9Qk"],
+          allowed_rpcs: [],
+          time_zone: [],
+          mutation_gateway_url: [],
+        },
+      });
+    });
+
+    it("should let a controller set the time zone dates are read in", async () => {
+      // Execute
+      const result = await actor.set_time_zone(" UTC+2 ");
+      const invalid = await actor.set_time_zone("Mars/Olympus");
+      const config = await actor.get_config();
+
+      // Assert
+      expect(result).toEqual({ Ok: null });
+      expect("Err" in invalid).toBe(true);
+      if ("Err" in invalid) {
+        expect(invalid.Err.code).toEqual({ InvalidInput: null });
+      }
+      if ("Ok" in config) {
+        expect(config.Ok.time_zone).toEqual(["UTC+2"]);
+      }
+    });
+
+    it("should reject URLs that are not https", async () => {
+      // Execute
+      const result = await actor.set_supabase_url("http://example.com");
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ InvalidInput: null });
+      }
+    });
+
+    it("should reject config changes from non-controllers", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("not-a-controller"));
+
+      // Execute
+      const setResult = await actor.set_supabase_anon_key("stolen-key");
+      const zoneResult = await actor.set_time_zone("UTC");
+      const getResult = await actor.get_config();
+      actor.setPrincipal(Principal.anonymous());
+
+      // Assert
+      expect(setResult).toEqual({
+        Err: {
+          code: { Unauthorized: null },
+          message: "Only canister controllers can call this method",
+          retryable: false,
+          upstream_status: [],
+        },
+      });
+      expect("Err" in zoneResult).toBe(true);
+      expect("Err" in getResult).toBe(true);
+    });
+  });
+
+  describe("downstream canister registry", () => {
+    const llmServiceId = Principal.fromText("ryjl3-tyaaa-aaaaa-aaaba-cai");
+
+    it("should start without registered canisters", async () => {
+      // Execute
+      const result = await actor.check_downstream_canisters();
+
+      // Assert
+      expect("Ok" in result).toBe(true);
+      if ("Ok" in result) {
+        expect(result.Ok).toHaveLength(2);
+        for (const canister of result.Ok) {
+          expect(canister.canister_id).toEqual([]);
+          expect(canister.health).toEqual({ NotRegistered: null });
+        }
+      }
+    });
+
+    it("should let a controller register a canister", async () => {
+      // Execute
+      const result = await actor.set_downstream_canister(
+        { LlmService: null },
+        llmServiceId,
+      );
+      const canisters = await actor.get_downstream_canisters();
+
+      // Assert
+      expect(result).toEqual({ Ok: null });
+      const llmService = canisters.find((c) => "LlmService" in c.name);
+      expect(llmService?.canister_id[0]?.toText()).toBe(llmServiceId.toText());
+      expect(llmService?.health).toEqual({ Unknown: null });
+    });
+
+    it("should report a registered canister that does not exist as unreachable", async () => {
+      // Execute
+      const result = await actor.check_downstream_canisters();
+
+      // Assert
+      expect("Ok" in result).toBe(true);
+      if ("Ok" in result) {
+        const llmService = result.Ok.find((c) => "LlmService" in c.name);
+        expect(llmService?.health).toHaveProperty("Unreachable");
+        expect(llmService?.last_checked_at).toHaveLength(1);
+      }
+    });
+
+    it("should report stopped and empty canisters as unreachable", async () => {
+      // Setup
+      const { idlFactory } = await import(
+        "../../src/declarations/backend/backend.did.js"
+      );
+      // The backend has no `health` method, like the `llm` canister
+      const running = await pic.setupCanister<_SERVICE>({
+        idlFactory,
+        wasm: wasmModule,
+        arg: emptyInitArg,
+      });
+      const stopped = await pic.setupCanister<_SERVICE>({
+        idlFactory,
+        wasm: wasmModule,
+        arg: emptyInitArg,
+      });
+      await pic.stopCanister({ canisterId: stopped.canisterId });
+      const empty = await pic.createCanister();
+
+      // Execute
+      await actor.set_downstream_canister({ Llm: null }, running.canisterId);
+      await actor.set_downstream_canister(
+        { LlmService: null },
+        stopped.canisterId,
+      );
+      const withStopped = await actor.check_downstream_canisters();
+      await actor.set_downstream_canister({ LlmService: null }, empty);
+      const withEmpty = await actor.check_downstream_canisters();
+      await actor.set_downstream_canister({ LlmService: null }, llmServiceId);
+
+      // Assert
+      const health = (
+        result: typeof withStopped,
+        name: "Llm" | "LlmService",
+      ) =>
+        "Ok" in result
+          ? result.Ok.find((c) => name in c.name)?.health
+          : undefined;
+      expect(health(withStopped, "Llm")).toEqual({ Healthy: null });
+      expect(health(withStopped, "LlmService")).toHaveProperty("Unreachable");
+      expect(health(withEmpty, "LlmService")).toHaveProperty("Unreachable");
+    });
+
+    it("should reject registry changes from non-controllers", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("not-a-controller"));
+
+      // Execute
+      const result = await actor.set_downstream_canister(
+        { Llm: null },
+        llmServiceId,
+      );
+      const checkResult = await actor.check_downstream_canisters();
+      actor.setPrincipal(Principal.anonymous());
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      expect(checkResult).toEqual({
+        Err: {
+          code: { Unauthorized: null },
+          message: "Only canister controllers can call this method",
+          retryable: false,
+          upstream_status: [],
+        },
+      });
+    });
+  });
+
+  describe("upgrades", () => {
+    it("should start at the current storage version", async () => {
+      // Execute
+      const version = await actor.get_storage_version();
+
+      // Assert
+      expect(version).toBeGreaterThanOrEqual(1);
+    });
+
+    it("should keep counter, config and registry across an upgrade", async () => {
+      // Setup
+      const llmId = Principal.fromText("ryjl3-tyaaa-aaaaa-aaaba-cai");
+      await actor.set_count(1234n);
+      await actor.set_supabase_url("https://upgrade-test.supabase.co");
+      await actor.set_downstream_canister({ Llm: null }, llmId);
+      const versionBefore = await actor.get_storage_version();
+
+      // Execute
+      await pic.upgradeCanister({
+        canisterId,
+        wasm: wasmModule,
+        arg: emptyInitArg,
+      });
+
+      // Assert
+      expect(await actor.get_count()).toBe(1234n);
+      expect(await actor.get_storage_version()).toBe(versionBefore);
+      const config = await actor.get_config();
+      expect(config).toHaveProperty("Ok");
+      if ("Ok" in config) {
+        expect(config.Ok.supabase_url).toEqual([
+          "https://upgrade-test.supabase.co",
+        ]);
+      }
+      const canisters = await actor.get_downstream_canisters();
+      const llm = canisters.find((c) => "Llm" in c.name);
+      expect(llm?.canister_id[0]?.toText()).toBe(llmId.toText());
+    });
+  });
+
   describe("chat", () => {
-    it("should process chat messages", async () => {
-      const messages = [{ role: "user", content: "Hello, how are you?" }];
+    it("should reject unknown roles", async () => {
+      // Setup
+      const messages = [
+        { role: "system", content: "You are a helpful assistant." },
+        { role: "bot", content: "Hi!" },
+        { role: "user", content: "Hello, how are you?" },
+      ];
+
+      // Execute
+      const result = await actor.chat(messages);
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("Message 2: Unknown role 'bot'");
+      }
+    });
+
+    it("should require the conversation to end with a user message", async () => {
+      // Setup
+      const messages = [
+        { role: "user", content: "Hello" },
+        { role: "assistant", content: "Hi, how can I help?" },
+      ];
+
+      // Execute
+      const result = await actor.chat(messages);
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("must come from the user");
+      }
+    });
+
+    it("should refuse an empty conversation", async () => {
+      // Execute
+      const result = await actor.chat([]);
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("at least one message");
+      }
+    });
+  });
+
+  describe("sessions", () => {
+    it("should not keep sessions for anonymous callers", async () => {
+      // Execute
+      const sessions = await actor.list_sessions();
+      const started = await actor.start_session();
+
+      // Assert
+      expect(sessions).toEqual([]);
+      expect("Err" in started).toBe(true);
+      if ("Err" in started) {
+        expect(started.Err.code).toEqual({ Unauthorized: null });
+      }
+    });
+
+    it("should keep prompts per caller and resume them", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("session-owner"));
+      const started = await actor.start_session();
+
+      // Execute
+      const reply = await actor.prompt("hello there");
+      const sessions = await actor.list_sessions();
+      const resumed =
+        "Ok" in started
+          ? await actor.resume_session(started.Ok.id)
+          : started;
+      actor.setIdentity(createIdentity("someone-else"));
+      const othersSessions = await actor.list_sessions();
+      const othersResume =
+        "Ok" in started
+          ? await actor.resume_session(started.Ok.id)
+          : started;
+      actor.setPrincipal(Principal.anonymous());
+
+      // Assert
+      expect("Ok" in started).toBe(true);
+      expect(sessions).toHaveLength(1);
+      expect(sessions[0]?.title).toBe("hello there");
+      // No LLM canister is registered, so only the prompt is kept
+      expect("Err" in reply).toBe(true);
+      if ("Err" in reply) {
+        expect(reply.Err.code).toEqual({ Config: null });
+      }
+      expect(sessions[0]?.message_count).toBe(1n);
+      expect("Ok" in resumed).toBe(true);
+      if ("Ok" in resumed) {
+        expect(resumed.Ok.messages.map((m) => m.role)).toEqual(["user"]);
+        expect(resumed.Ok.messages[0]?.content).toBe("hello there");
+      }
+      expect(othersSessions).toEqual([]);
+      expect("Err" in othersResume).toBe(true);
+    });
+
+    it("should delete sessions", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("session-deleter"));
+      const started = await actor.start_session();
+      const id = "Ok" in started ? started.Ok.id : 0n;
+
+      // Execute
+      const deleted = await actor.delete_session(id);
+      const deletedAgain = await actor.delete_session(id);
+      const sessions = await actor.list_sessions();
+      actor.setPrincipal(Principal.anonymous());
+
+      // Assert
+      expect(deleted).toEqual({ Ok: null });
+      expect("Err" in deletedAgain).toBe(true);
+      expect(sessions).toEqual([]);
+    });
+
+    it("should reject prompts too long to keep", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("session-rambler"));
+
+      // Execute
+      const reply = await actor.prompt("why ".repeat(600));
+      const sessions = await actor.list_sessions();
+      actor.setPrincipal(Principal.anonymous());
+
+      // Assert
+      expect("Err" in reply).toBe(true);
+      if ("Err" in reply) {
+        expect(reply.Err.code).toEqual({ InvalidInput: null });
+      }
+      expect(sessions).toEqual([]);
+    });
+  });
+
+  describe("row mutations", () => {
+    it("should refuse to update without a row filter", async () => {
+      // Execute
+      const result = await actor.update_in_supabase(
+        "todos",
+        "select=*",
+        '{"is_done":true}',
+      );
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("doesn't restrict any column");
+      }
+    });
+
+    it("should refuse to delete with an empty filter", async () => {
+      // Execute
+      const result = await actor.delete_from_supabase("todos", "  ");
+
+      // Assert
+      expect("Err" in result).toBe(true);
+    });
+
+    it("should refuse updates and deletes without a mutation gateway", async () => {
+      // Execute
+      const updated = await actor.update_in_supabase(
+        "todos",
+        "id=eq.1",
+        '{"is_done":true}',
+      );
+      const deleted = await actor.delete_from_supabase("todos", "id=eq.1");
+      const insecure = await actor.set_mutation_gateway_url(
+        "http://gateway.example.com",
+      );
+
+      // Assert
+      for (const result of [updated, deleted]) {
+        expect("Err" in result).toBe(true);
+        if ("Err" in result) {
+          expect(result.Err.code).toEqual({ Config: null });
+          expect(result.Err.message).toContain("set_mutation_gateway_url");
+        }
+      }
+      expect("Err" in insecure).toBe(true);
+    });
+
+    it("should reject a patch that is not a JSON object", async () => {
+      // Execute
+      const result = await actor.update_in_supabase(
+        "todos",
+        "id=eq.1",
+        "[true]",
+      );
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("JSON object");
+      }
+    });
+
+    it("should refuse a natural language change that picks no rows", async () => {
+      // Execute
+      const result =
+        await actor.query_supabase_with_natural_language("delete all todos");
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ InvalidInput: null });
+        expect(result.Err.message).toContain("No filter says which rows to change");
+      }
+    });
+
+    it("should refuse a natural language change with words it cannot read", async () => {
+      for (const question of [
+        "delete todos other than 5",
+        "delete todos with id greater than 99999999999999999999",
+        "delete todos created after 99999",
+        'delete todos with title ""',
+      ]) {
+        // Execute
+        const result =
+          await actor.query_supabase_with_natural_language(question);
+
+        // Assert
+        expect("Err" in result).toBe(true);
+        if ("Err" in result) {
+          expect(result.Err.code).toEqual({ Parse: null });
+        }
+      }
+    });
+
+    it("should not plan natural language changes without a mutation gateway", async () => {
+      // Execute
+      const result = await actor.query_supabase_with_natural_language(
+        "delete completed todos",
+      );
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ Config: null });
+        expect(result.Err.message).toContain("set_mutation_gateway_url");
+      }
+    });
+
+    it("should not report a change asked through prompt as done", async () => {
+      // Execute
+      const reply = await actor.prompt("delete completed todos");
+
+      // Assert
+      expect("Err" in reply).toBe(true);
+      if ("Err" in reply) {
+        expect(reply.Err.code).toEqual({ Config: null });
+        expect(reply.Err.message).toContain("set_mutation_gateway_url");
+      }
+    });
+
+    it("should refuse to confirm or cancel a plan that does not exist", async () => {
+      // Execute
+      const confirmed = await actor.confirm_mutation(999n);
+      const cancelled = await actor.cancel_mutation(999n);
+
+      // Assert
+      expect("Err" in confirmed).toBe(true);
+      if ("Err" in confirmed) {
+        expect(confirmed.Err.code).toEqual({ Config: null });
+      }
+      expect("Err" in cancelled).toBe(true);
+      if ("Err" in cancelled) {
+        expect(cancelled.Err.message).toContain("No pending plan 999");
+      }
+    });
+
+    it("should keep a confirmed plan when sending the change fails", async () => {
+      // Setup
+      const { idlFactory } = await import(
+        "../../src/declarations/backend/backend.did.js"
+      );
+      const fixture = await pic.setupCanister<_SERVICE>({
+        idlFactory,
+        wasm: wasmModule,
+        arg: emptyInitArg,
+      });
+      await fixture.actor.set_supabase_url("https://project.supabase.co");
+      await fixture.actor.set_supabase_anon_key("anon-key");
+      await fixture.actor.set_mutation_gateway_url(
+        "https://gateway.example.com",
+      );
+      const deferred = pic.createDeferredActor<_SERVICE>(
+        idlFactory,
+        fixture.canisterId,
+      );
+      const respond = async (
+        statusCode: number,
+        body: string,
+        headers: [string, string][] = [],
+      ) => {
+        await pic.tick(2);
+        const [outcall] = await pic.getPendingHttpsOutcalls();
+        await pic.mockPendingHttpsOutcall({
+          requestId: outcall.requestId,
+          subnetId: outcall.subnetId,
+          response: {
+            type: "success",
+            statusCode,
+            headers,
+            body: new TextEncoder().encode(body),
+          },
+        });
+      };
+      const plan = await deferred.query_supabase_with_natural_language(
+        "delete completed todos",
+      );
+      await respond(200, '[{"id":1,"is_done":true}]', [
+        ["content-range", "0-0/1"],
+      ]);
+      const planned = await plan();
+      const planId = "Ok" in planned ? (planned.Ok.plan[0]?.id ?? 0n) : 0n;
+
+      // Execute
+      const firstTry = await deferred.confirm_mutation(planId);
+      await respond(503, "Service Unavailable");
+      const failed = await firstTry();
+      const secondTry = await deferred.confirm_mutation(planId);
+      await respond(200, '[{"id":1,"is_done":true}]');
+      const confirmed = await secondTry();
+
+      // Assert
+      expect("Ok" in planned).toBe(true);
+      expect("Err" in failed).toBe(true);
+      expect("Ok" in confirmed).toBe(true);
+      if ("Ok" in confirmed) {
+        expect(confirmed.Ok.data).toBe('[{"id":1,"is_done":true}]');
+      }
+    });
+  });
+
+  describe("bulk inserts", () => {
+    it("should reject an empty batch", async () => {
+      // Execute
+      const result = await actor.bulk_insert_to_supabase("todos", [], []);
+
+      // Assert
+      expect("Err" in result).toBe(true);
+    });
+
+    it("should reject rows with different columns", async () => {
+      // Setup
+      const rows = [
+        [{ column: "title", value: { Text: "Buy groceries" } }],
+        [
+          { column: "title", value: { Text: "Walk the dog" } },
+          { column: "is_done", value: { Bool: true } },
+        ],
+      ];
+
+      // Execute
+      const result = await actor.bulk_insert_to_supabase("todos", rows, []);
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("Row 1");
+      }
+    });
+
+    it("should reject invalid conflict columns before sending anything", async () => {
+      // Execute
+      const result = await actor.upsert_to_supabase(
+        "todos",
+        '[{"id":1}]',
+        ["id;drop"],
+      );
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("Invalid column name");
+      }
+    });
+  });
+
+  describe("paginated reads", () => {
+    it("should refuse queries that set their own limit", async () => {
+      // Execute
+      const result = await actor.fetch_page_from_supabase(
+        "todos",
+        "select=*&limit=10",
+        [{ offset: [20n], page_size: [10n], row_budget: [] }],
+      );
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("remove limit/offset");
+      }
+    });
+  });
+
+  describe("typed results", () => {
+    it("should report natural language errors as parse errors", async () => {
+      // Execute
+      const result =
+        await actor.query_supabase_with_natural_language("hello there");
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ Parse: null });
+        expect(result.Err.retryable).toBe(false);
+      }
+    });
+  });
+
+  describe("rpc", () => {
+    it("should reject an invalid function name", async () => {
+      // Execute
+      const result = await actor.call_supabase_rpc("drop table;", "{}");
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.message).toContain("Invalid function name");
+      }
+    });
+
+    it("should reject arguments that are not a JSON object", async () => {
+      // Execute
+      const result = await actor.call_supabase_rpc("get_overdue_todos", "[1]");
+
+      // Assert
+      expect("Err" in result).toBe(true);
+    });
+
+    it("should let controllers allowlist functions for natural language queries", async () => {
+      // Execute
+      const setResult = await actor.set_allowed_rpcs(["get_overdue_todos"]);
+      const parseResult = await actor.parse_natural_language_query_fallback(
+        "get overdue todos",
+      );
+
+      // Assert
+      expect(setResult).toEqual({ Ok: null });
+      expect("Ok" in parseResult).toBe(true);
+      if ("Ok" in parseResult) {
+        expect(parseResult.Ok.rpc).toEqual([
+          { function: "get_overdue_todos", args: "{}" },
+        ]);
+        expect(parseResult.Ok.parsed).toEqual([]);
+      }
+    });
+
+    it("should not let other callers change the allowlist", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("not-a-controller"));
+
+      // Execute
+      const result = await actor.set_allowed_rpcs(["get_overdue_todos"]);
+      actor.setPrincipal(Principal.anonymous());
 
-      const response = await actor.chat(messages);
-      expect(typeof response).toBe("string");
-      expect(response.length).toBeGreaterThan(0);
+      // Assert
+      expect("Err" in result).toBe(true);
+    });
+  });
+
+  describe("schema registry", () => {
+    it("should start with the built-in tables", async () => {
+      // Execute
+      const schema = await actor.get_schema();
+
+      // Assert
+      expect(schema.tables.map((table) => table.name)).toEqual([
+        "todos",
+        "users",
+        "posts",
+      ]);
+    });
+
+    it("should let controllers add a table that the parsers then recognise", async () => {
+      // Setup
+      await actor.upsert_table_schema({
+        name: "projects",
+        description: ["Projects that group todos"],
+        synonyms: ["project"],
+        columns: [
+          {
+            name: "id",
+            column_type: { Integer: null },
+            description: [],
+            synonyms: [],
+          },
+          {
+            name: "name",
+            column_type: { Text: null },
+            description: [],
+            synonyms: [],
+          },
+        ],
+      });
+
+      // Execute
+      const result = await actor.parse_natural_language_query_fallback(
+        "show all projects",
+      );
+      await actor.remove_table_schema("projects");
+
+      // Assert
+      expect("Ok" in result).toBe(true);
+      if ("Ok" in result) {
+        expect(result.Ok.table).toBe("projects");
+        expect(result.Ok.query).toBe("select=*");
+      }
+    });
+
+    it("should reject a table with invalid column names", async () => {
+      // Execute
+      const result = await actor.upsert_table_schema({
+        name: "projects",
+        description: [],
+        synonyms: [],
+        columns: [
+          {
+            name: "name; drop table",
+            column_type: { Text: null },
+            description: [],
+            synonyms: [],
+          },
+        ],
+      });
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ InvalidInput: null });
+      }
+    });
+
+    it("should not let other callers refresh the schema", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("not-a-controller"));
+
+      // Execute
+      const result = await actor.refresh_schema([true]);
+      actor.setPrincipal(Principal.anonymous());
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ Unauthorized: null });
+      }
+    });
+
+    it("should not let other callers change the schema", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("not-a-controller"));
+
+      // Execute
+      const result = await actor.remove_table_schema("todos");
+      actor.setPrincipal(Principal.anonymous());
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ Unauthorized: null });
+      }
     });
   });
 
@@ -107,8 +900,197 @@ describe("Backend Service", () => {
         expect(result.Ok).toHaveProperty("table");
         expect(result.Ok).toHaveProperty("query");
       } else {
-        expect(result.Err).toBeDefined();
-        expect(typeof result.Err).toBe("string");
+        expect(result.Err.code).toBeDefined();
+        expect(typeof result.Err.message).toBe("string");
+      }
+    });
+
+    it("should return a typed query alongside the rendered query string", async () => {
+      // Execute
+      const result = await actor.parse_natural_language_query_fallback(
+        "show completed todos",
+      );
+
+      // Assert
+      expect("Ok" in result).toBe(true);
+      if ("Ok" in result) {
+        expect(result.Ok.table).toBe("todos");
+        expect(result.Ok.query).toBe("select=*&is_done=eq.true");
+        expect(result.Ok.parsed).toHaveLength(1);
+        const [parsed] = result.Ok.parsed;
+        expect(parsed?.filters).toEqual([
+          {
+            column: "is_done",
+            operator: { Eq: null },
+            negated: false,
+            value: { Bool: true },
+          },
+        ]);
+      }
+    });
+
+    it("should map or, but and not to logic trees and negated filters", async () => {
+      // Execute
+      const result = await actor.parse_natural_language_query_fallback(
+        "todos that are done or have no due date but not titled work",
+      );
+
+      // Assert
+      expect("Ok" in result).toBe(true);
+      if ("Ok" in result) {
+        expect(result.Ok.query).toBe(
+          "select=*&title=not.ilike.*work*&or=(is_done.eq.true,due_date.is.null)",
+        );
+        const [parsed] = result.Ok.parsed;
+        expect(parsed?.filters.map((filter) => filter.negated)).toEqual([
+          true,
+        ]);
+        expect(parsed?.groups).toHaveLength(1);
+        expect(parsed?.groups[0]?.operator).toEqual({ Or: null });
+        expect(parsed?.groups[0]?.conditions).toHaveLength(2);
+      }
+    });
+
+    it("should read number words, pages and multi-column ordering", async () => {
+      // Execute
+      const result = await actor.parse_natural_language_query_fallback(
+        "page three of todos sorted by status, then by due date desc",
+      );
+
+      // Assert
+      expect("Ok" in result).toBe(true);
+      if ("Ok" in result) {
+        expect(result.Ok.query).toBe(
+          "select=*&order=status.asc,due_date.desc&limit=10&offset=20",
+        );
+        const [parsed] = result.Ok.parsed;
+        expect(parsed?.limit).toEqual([10n]);
+        expect(parsed?.offset).toEqual([20n]);
+        expect(parsed?.order.map((order) => order.column)).toEqual([
+          "status",
+          "due_date",
+        ]);
+      }
+    });
+
+    it("should parse counts and grouped aggregates", async () => {
+      // Execute
+      const count = await actor.parse_natural_language_query_fallback(
+        "how many todos are done",
+      );
+      const grouped = await actor.parse_natural_language_query_fallback(
+        "count todos per status",
+      );
+
+      // Assert
+      expect("Ok" in count).toBe(true);
+      if ("Ok" in count) {
+        expect(count.Ok.query).toBe("select=count()&is_done=eq.true");
+      }
+      expect("Ok" in grouped).toBe(true);
+      if ("Ok" in grouped) {
+        expect(grouped.Ok.query).toBe("select=status,count()");
+        const [parsed] = grouped.Ok.parsed;
+        expect(parsed?.select).toEqual(["status"]);
+        expect(parsed?.aggregates).toEqual([
+          { function: { Count: null }, column: [] },
+        ]);
+      }
+    });
+
+    it("should parse changes as mutations next to the rows they pick", async () => {
+      // Execute
+      const mark = await actor.parse_natural_language_query_fallback(
+        "mark 'Walk the dog' as done",
+      );
+      const remove = await actor.parse_natural_language_query_fallback(
+        "delete completed todos",
+      );
+
+      // Assert
+      expect("Ok" in mark).toBe(true);
+      if ("Ok" in mark) {
+        expect(mark.Ok.query).toBe("select=*&title=ilike.*Walk the dog*");
+        expect(mark.Ok.mutation).toEqual([
+          {
+            action: { Update: null },
+            set: [{ column: "is_done", value: { Bool: true } }],
+          },
+        ]);
+      }
+      expect("Ok" in remove).toBe(true);
+      if ("Ok" in remove) {
+        expect(remove.Ok.query).toBe("select=*&is_done=eq.true");
+        expect(remove.Ok.mutation).toEqual([{ action: { Delete: null }, set: [] }]);
+      }
+    });
+
+    it("should embed related tables joined by schema references", async () => {
+      // Execute
+      const byAuthor = await actor.parse_natural_language_query_fallback(
+        "posts by Alice with author email",
+      );
+      const withPosts = await actor.parse_natural_language_query_fallback(
+        "users with their posts",
+      );
+
+      // Assert
+      expect("Ok" in byAuthor).toBe(true);
+      if ("Ok" in byAuthor) {
+        expect(byAuthor.Ok.query).toBe(
+          "select=*,users!inner(email)&users.name=ilike.*alice*",
+        );
+        const [parsed] = byAuthor.Ok.parsed;
+        expect(parsed?.embeds).toEqual([
+          { relation: "users", columns: ["email"], inner: true },
+        ]);
+        expect(parsed?.filters.map((filter) => filter.column)).toEqual([
+          "users.name",
+        ]);
+      }
+      expect("Ok" in withPosts).toBe(true);
+      if ("Ok" in withPosts) {
+        expect(withPosts.Ok.query).toBe("select=*,posts(*)");
+      }
+    });
+
+    it("should resolve relative dates to ranges in the configured time zone", async () => {
+      // Setup
+      await pic.setTime(new Date("2026-10-17T10:00:00Z").getTime());
+      await pic.tick();
+      await actor.set_time_zone("UTC+2");
+
+      // Execute
+      const week = await actor.parse_natural_language_query_fallback(
+        "todos due this week",
+      );
+      const overdue =
+        await actor.parse_natural_language_query_fallback("overdue todos");
+
+      // Assert
+      expect(week).toHaveProperty("Ok");
+      if ("Ok" in week) {
+        expect(week.Ok.query).toBe(
+          "select=*&due_date=gte.2026-10-11T22:00:00Z&due_date=lt.2026-10-18T22:00:00Z",
+        );
+      }
+      expect(overdue).toHaveProperty("Ok");
+      if ("Ok" in overdue) {
+        expect(overdue.Ok.query).toMatch(
+          /^select=\*&due_date=lt\.2026-10-17T10:00:\d\d(\.\d+)?Z&is_done=eq\.false$/,
+        );
+      }
+    });
+
+    it("should report unparseable queries as parse errors", async () => {
+      // Execute
+      const result =
+        await actor.parse_natural_language_query_fallback("hello there");
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ Parse: null });
       }
     });
   });
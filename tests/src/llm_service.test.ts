@@ -0,0 +1,278 @@
+import { describe, it, expect, beforeAll, afterAll, inject } from "vitest";
+import { PocketIc, Actor, createIdentity } from "@dfinity/pic";
+import { IDL } from "@dfinity/candid";
+import { _SERVICE } from "../../src/declarations/llm_service/llm_service.did.d.ts";
+import { Principal } from "@dfinity/principal";
+import { readFileSync } from "fs";
+import { resolve, dirname } from "path";
+import { fileURLToPath } from "url";
+
+describe("LLM Service", () => {
+  let pic: PocketIc;
+  let actor: Actor<_SERVICE>;
+
+  beforeAll(async () => {
+    pic = await PocketIc.create(inject("PIC_URL"));
+
+    const wasmPath = resolve(
+      dirname(fileURLToPath(import.meta.url)),
+      "..",
+      "..",
+      "target",
+      "wasm32-unknown-unknown",
+      "release",
+      "llm_service.wasm",
+    );
+    const wasmModule = readFileSync(wasmPath);
+
+    const { idlFactory, init } = await import(
+      "../../src/declarations/llm_service/llm_service.did.js"
+    );
+
+    const fixture = await pic.setupCanister<_SERVICE>({
+      idlFactory,
+      wasm: wasmModule,
+      arg: IDL.encode(init({ IDL }), [[]]),
+    });
+    actor = fixture.actor;
+  });
+
+  afterAll(async () => {
+    if (pic) {
+      await pic.tearDown();
+    }
+  });
+
+  describe("providers", () => {
+    it("should default to Groq", async () => {
+      // Execute
+      const config = await actor.get_config();
+
+      // Assert
+      expect("Ok" in config).toBe(true);
+      if ("Ok" in config) {
+        expect(config.Ok.llm_settings.providers).toEqual([
+          { Groq: { model: "llama-3.1-8b-instant" } },
+        ]);
+      }
+    });
+
+    it("should reject an empty provider chain", async () => {
+      // Execute
+      const result = await actor.set_llm_settings({
+        providers: [],
+        temperature: 0.1,
+        max_tokens: 300,
+      });
+
+      // Assert
+      expect("Err" in result).toBe(true);
+    });
+
+    it("should reject OpenAI-compatible providers without https", async () => {
+      // Execute
+      const result = await actor.set_llm_settings({
+        providers: [
+          {
+            OpenAiCompatible: {
+              base_url: "http://localhost:8080/v1",
+              model: "llama3",
+              api_key: [],
+            },
+          },
+        ],
+        temperature: 0,
+        max_tokens: 100,
+      });
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ InvalidInput: null });
+      }
+    });
+
+    it("should mask API keys of OpenAI-compatible providers", async () => {
+      // Setup
+      const settings = {
+        providers: [
+          {
+            OpenAiCompatible: {
+              base_url: "https://llm.example.com/v1",
+              model: "llama3",
+              api_key: ["sk-local-test-key-123456"] as [string],
+            },
+          },
+        ],
+        temperature: 0,
+        max_tokens: 100,
+      };
+
+      // Execute
+      await actor.set_llm_settings(settings);
+      const config = await actor.get_config();
+
+      // Assert
+      expect("Ok" in config).toBe(true);
+      if ("Ok" in config) {
+        expect(config.Ok.llm_settings.providers).toEqual([
+          {
+            OpenAiCompatible: {
+              base_url: "https://llm.example.com/v1",
+              model: "llama3",
+              api_key: ["sk-l…3456"],
+            },
+          },
+        ]);
+      }
+    });
+
+    it("should parse with the first provider whose output is usable", async () => {
+      // Setup
+      await actor.set_llm_settings({
+        providers: [
+          { Mock: { response: "not json" } },
+          {
+            Mock: {
+              response:
+                '{"table": "todos", "query": "select=*&is_done=eq.false", "error": null}',
+            },
+          },
+        ],
+        temperature: 0,
+        max_tokens: 100,
+      });
+
+      // Execute
+      const result = await actor.parse_natural_language_to_sql(
+        "what is left to do",
+        [],
+      );
+
+      // Assert
+      expect(result).toEqual({
+        Ok: expect.objectContaining({
+          table: "todos",
+          query: "select=*&is_done=eq.false",
+        }),
+      });
+    });
+
+    it("should repair LLM output that almost matches the schema", async () => {
+      // Setup
+      await actor.set_llm_settings({
+        providers: [
+          {
+            Mock: {
+              response:
+                '{"table": "todo", "query": "select=*&completed=eq.yes&titel=ilike.%dog%", "error": null}',
+            },
+          },
+        ],
+        temperature: 0,
+        max_tokens: 100,
+      });
+
+      // Execute
+      const result = await actor.parse_natural_language_to_sql(
+        "finished todos about dogs",
+        [],
+      );
+
+      // Assert
+      expect("Ok" in result).toBe(true);
+      if ("Ok" in result) {
+        expect(result.Ok.table).toBe("todos");
+        expect(result.Ok.query).toBe(
+          "select=*&is_done=eq.true&title=ilike.*dog*",
+        );
+        expect(result.Ok.repairs[0]?.map((issue) => issue.part)).toEqual([
+          "table",
+          "filter completed=eq.yes",
+          "filter completed=eq.yes",
+          "filter titel=ilike.%dog%",
+          "filter titel=ilike.%dog%",
+        ]);
+      }
+    });
+
+    it("should skip providers whose output uses unknown columns", async () => {
+      // Setup
+      await actor.set_llm_settings({
+        providers: [
+          {
+            Mock: {
+              response:
+                '{"table": "todos", "query": "select=*&priority=eq.high", "error": null}',
+            },
+          },
+          {
+            Mock: {
+              response:
+                '{"table": "todos", "query": "select=*&status=eq.high", "error": null}',
+            },
+          },
+        ],
+        temperature: 0,
+        max_tokens: 100,
+      });
+
+      // Execute
+      const result = await actor.parse_natural_language_to_sql(
+        "high priority todos",
+        [],
+      );
+
+      // Assert
+      expect(result).toEqual({
+        Ok: expect.objectContaining({
+          table: "todos",
+          query: "select=*&status=eq.high",
+          repairs: [],
+        }),
+      });
+    });
+
+    it("should fall back to the rule-based parser when no provider is usable", async () => {
+      // Setup
+      await actor.set_llm_settings({
+        providers: [{ Mock: { response: "not json" } }],
+        temperature: 0,
+        max_tokens: 100,
+      });
+
+      // Execute
+      const result = await actor.parse_natural_language_to_sql(
+        "show incomplete tasks sorted by due date",
+        [],
+      );
+
+      // Assert
+      expect(result).toEqual({
+        Ok: expect.objectContaining({
+          table: "todos",
+          query: "select=*&is_done=eq.false&order=due_date.asc",
+        }),
+      });
+    });
+
+    it("should not let other callers change providers", async () => {
+      // Setup
+      actor.setIdentity(createIdentity("not-a-controller"));
+
+      // Execute
+      const result = await actor.set_llm_settings({
+        providers: [{ Mock: { response: "{}" } }],
+        temperature: 0,
+        max_tokens: 100,
+      });
+      actor.setPrincipal(Principal.anonymous());
+
+      // Assert
+      expect("Err" in result).toBe(true);
+      if ("Err" in result) {
+        expect(result.Err.code).toEqual({ Unauthorized: null });
+      }
+    });
+  });
+});